@@ -0,0 +1,45 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::anyhow;
+use deno_emit::LoadFuture;
+use deno_emit::LoadOptions;
+use deno_emit::Loader;
+use deno_emit::ModuleSpecifier;
+use deno_graph::source::LoadResponse;
+
+/// A loader which reads `file:` modules from the file system.
+///
+/// Remote modules aren't fetched, as there's no HTTP client, so they error
+/// with a message to vendor them or map them to local modules instead.
+#[derive(Debug, Default)]
+pub struct FsLoader;
+
+impl Loader for FsLoader {
+  fn load(
+    &self,
+    specifier: &ModuleSpecifier,
+    _options: LoadOptions,
+  ) -> LoadFuture {
+    let result = match specifier.scheme() {
+      "file" => match specifier.to_file_path() {
+        Ok(path) => match std::fs::read(&path) {
+          Ok(content) => Ok(Some(LoadResponse::Module {
+            content: content.into(),
+            specifier: specifier.clone(),
+            maybe_headers: None,
+          })),
+          Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+          Err(err) => Err(
+            anyhow!(err).context(format!("Failed reading \"{}\".", path.display())),
+          ),
+        },
+        Err(()) => Err(anyhow!("Invalid file URL \"{specifier}\".")),
+      },
+      "http" | "https" => Err(anyhow!(
+        "Remote modules can't be loaded from the file system, but \"{specifier}\" is imported. Vendor the module or map it to a local module with an import map."
+      )),
+      _ => Ok(None),
+    };
+    Box::pin(futures::future::ready(result))
+  }
+}
@@ -0,0 +1,625 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+mod fs_loader;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::Args;
+use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
+use deno_emit::BundleEmit;
+use deno_emit::BundleMode;
+use deno_emit::BundleOptions;
+use deno_emit::BundleType;
+use deno_emit::CompilerOptions;
+#[cfg(feature = "dev_server")]
+use deno_emit::ConfigFile;
+#[cfg(feature = "dev_server")]
+use deno_emit::DevServer;
+#[cfg(feature = "dev_server")]
+use deno_emit::DevServerOptions;
+use deno_emit::EmitOptions;
+use deno_emit::ImportMapInput;
+use deno_emit::ModuleFilter;
+use deno_emit::ModuleSpecifier;
+use deno_emit::SourceMapOption;
+use deno_emit::TranspileGraphOptions;
+use deno_emit::TranspileOptions;
+use deno_emit::WatchEvent;
+use deno_emit::WatchOutput;
+use deno_emit::WatchTarget;
+use deno_emit::Watcher;
+use deno_emit::Workspace;
+
+use crate::fs_loader::FsLoader;
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Bundles and transpiles Deno modules.
+#[derive(Debug, Parser)]
+#[command(name = "deno_emit", version, about)]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+  /// Bundles a root module and its dependencies into a single file.
+  Bundle(BundleArgs),
+  /// Transpiles root modules and their dependencies to JavaScript, with the
+  /// specifiers rewritten to the emitted files.
+  Transpile(TranspileArgs),
+  /// Serves a directory over HTTP, transpiling modules when they're
+  /// requested.
+  #[cfg(feature = "dev_server")]
+  Serve(ServeArgs),
+}
+
+#[derive(Debug, Args)]
+struct BundleArgs {
+  /// The root module to bundle.
+  root: PathBuf,
+  /// Minify the bundle.
+  #[arg(long)]
+  minify: bool,
+  /// Emit a development bundle, which keeps every module in its own function
+  /// instead of hoisting them into one scope.
+  #[arg(long, conflicts_with = "minify")]
+  dev: bool,
+  /// Register the components and hooks of JSX and TSX modules with React
+  /// Refresh.
+  #[arg(long, requires = "dev")]
+  react_refresh: bool,
+  /// Whether to emit an ES module or a script which runs the root module in
+  /// an IIFE.
+  #[arg(long = "type", value_enum, default_value_t = BundleTypeArg::Module)]
+  bundle_type: BundleTypeArg,
+  #[command(flatten)]
+  emit: EmitArgs,
+}
+
+#[derive(Debug, Args)]
+struct TranspileArgs {
+  /// The root modules to transpile.
+  #[arg(required = true)]
+  roots: Vec<PathBuf>,
+  #[command(flatten)]
+  emit: EmitArgs,
+}
+
+#[derive(Debug, Args)]
+struct EmitArgs {
+  /// A deno.json(c) or tsconfig.json whose compiler options, imports and
+  /// workspace members are used.
+  #[arg(long, conflicts_with = "import_map")]
+  config: Option<PathBuf>,
+  /// An import map to resolve the imports with.
+  #[arg(long)]
+  import_map: Option<PathBuf>,
+  /// The directory the emitted files are written to.
+  #[arg(long, default_value = "dist")]
+  out_dir: PathBuf,
+  /// How source maps are emitted, which defaults to the compiler options of
+  /// the config.
+  #[arg(long, value_enum)]
+  source_map: Option<SourceMapArg>,
+  /// Include the sources in the source maps.
+  #[arg(long)]
+  inline_sources: bool,
+  /// Instrument the local modules with istanbul-compatible coverage
+  /// counters.
+  #[arg(long)]
+  instrument_coverage: bool,
+  /// Rebuild when the local modules change.
+  #[arg(long)]
+  watch: bool,
+}
+
+#[cfg(feature = "dev_server")]
+#[derive(Debug, Args)]
+struct ServeArgs {
+  /// The directory to serve.
+  #[arg(default_value = ".")]
+  dir: PathBuf,
+  /// The address to listen on.
+  #[arg(long, default_value = "127.0.0.1")]
+  host: String,
+  #[arg(long, default_value_t = 8000)]
+  port: u16,
+  /// A deno.json(c) or tsconfig.json whose compiler options and imports are
+  /// used.
+  #[arg(long, conflicts_with = "import_map")]
+  config: Option<PathBuf>,
+  /// An import map to resolve the imports with.
+  #[arg(long)]
+  import_map: Option<PathBuf>,
+  /// Register the components and hooks of JSX and TSX modules with React
+  /// Refresh.
+  #[arg(long)]
+  react_refresh: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum BundleTypeArg {
+  Module,
+  Classic,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SourceMapArg {
+  None,
+  Inline,
+  Separate,
+}
+
+/// How the imports of the graph are resolved.
+enum Resolution {
+  ImportMap(Option<ImportMapInput>),
+  Workspace(Workspace),
+}
+
+/// The resolution and options of the emit arguments.
+struct Emit {
+  resolution: Resolution,
+  transpile_options: TranspileOptions,
+  emit_options: EmitOptions,
+}
+
+fn main() {
+  if let Err(err) = futures::executor::block_on(run(Cli::parse())) {
+    eprintln!("error: {err:#}");
+    std::process::exit(1);
+  }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+  match cli.command {
+    Command::Bundle(args) => bundle(args).await,
+    Command::Transpile(args) => transpile(args).await,
+    #[cfg(feature = "dev_server")]
+    Command::Serve(args) => serve(args).await,
+  }
+}
+
+/// Bundles the root module into the output directory.
+async fn bundle(args: BundleArgs) -> Result<()> {
+  let mut loader = FsLoader;
+  let root = path_to_specifier(&args.root)?;
+  let emit = load_emit(&args.emit, &loader).await?;
+  let options = BundleOptions {
+    bundle_type: match args.bundle_type {
+      BundleTypeArg::Module => BundleType::Module,
+      BundleTypeArg::Classic => BundleType::Classic,
+    },
+    transpile_options: emit.transpile_options,
+    emit_options: emit.emit_options,
+    minify: args.minify,
+    mode: if args.dev {
+      BundleMode::Dev
+    } else {
+      BundleMode::Hoisted
+    },
+    react_refresh: args.react_refresh,
+    instrument_coverage: coverage_filter(&args.emit),
+    ..Default::default()
+  };
+  let stem = args
+    .root
+    .file_stem()
+    .map(|stem| stem.to_string_lossy().into_owned())
+    .unwrap_or_else(|| "bundle".to_string());
+  let file_name = format!("{stem}.js");
+
+  if args.emit.watch {
+    let target = WatchTarget::Bundle { root, options };
+    return watch(target, emit.resolution, &args.emit.out_dir, |output| {
+      match output {
+        WatchOutput::Bundle(output) => Ok(bundle_files(&file_name, output)),
+        WatchOutput::Transpile(output) => output.into_files(),
+      }
+    })
+    .await;
+  }
+
+  let output = match emit.resolution {
+    Resolution::ImportMap(maybe_import_map) => {
+      deno_emit::bundle(root, &mut loader, maybe_import_map, options).await?
+    }
+    Resolution::Workspace(workspace) => {
+      deno_emit::bundle_workspace(root, &mut loader, &workspace, options)
+        .await?
+    }
+  };
+  let paths =
+    write_files(&args.emit.out_dir, bundle_files(&file_name, output))?;
+  print_paths(&paths);
+  Ok(())
+}
+
+fn bundle_files(
+  file_name: &str,
+  output: BundleEmit,
+) -> BTreeMap<String, Vec<u8>> {
+  let mut files = BTreeMap::new();
+  if let Some(map) = output.maybe_map {
+    files.insert(format!("{file_name}.map"), map.into_bytes());
+  }
+  files.insert(file_name.to_string(), output.code.into_bytes());
+  files
+}
+
+/// Transpiles the root modules into the output directory.
+async fn transpile(args: TranspileArgs) -> Result<()> {
+  let mut loader = FsLoader;
+  let roots = args
+    .roots
+    .iter()
+    .map(|root| path_to_specifier(root))
+    .collect::<Result<Vec<_>>>()?;
+  let emit = load_emit(&args.emit, &loader).await?;
+  let options = TranspileGraphOptions {
+    transpile_options: emit.transpile_options,
+    emit_options: emit.emit_options,
+    rewrite_specifiers: true,
+    instrument_coverage: coverage_filter(&args.emit),
+    ..Default::default()
+  };
+
+  if args.emit.watch {
+    let target = WatchTarget::Transpile { roots, options };
+    return watch(target, emit.resolution, &args.emit.out_dir, |output| {
+      match output {
+        WatchOutput::Transpile(output) => output.into_files(),
+        WatchOutput::Bundle(output) => Ok(bundle_files("bundle.js", output)),
+      }
+    })
+    .await;
+  }
+
+  let output = match emit.resolution {
+    Resolution::ImportMap(maybe_import_map) => {
+      deno_emit::transpile(roots, &mut loader, maybe_import_map, options)
+        .await?
+    }
+    Resolution::Workspace(workspace) => {
+      deno_emit::transpile_workspace(roots, &mut loader, &workspace, options)
+        .await?
+    }
+  };
+  let paths = write_files(&args.emit.out_dir, output.into_files()?)?;
+  print_paths(&paths);
+  Ok(())
+}
+
+/// The modules which are instrumented with coverage counters, when requested.
+fn coverage_filter(args: &EmitArgs) -> Option<ModuleFilter> {
+  args.instrument_coverage.then_some(ModuleFilter::LocalOnly)
+}
+
+/// Serves the directory until the process is stopped.
+#[cfg(feature = "dev_server")]
+async fn serve(args: ServeArgs) -> Result<()> {
+  let (maybe_import_map, (transpile_options, emit_options)) =
+    match (&args.config, &args.import_map) {
+      (Some(config), _) => {
+        let config =
+          ConfigFile::load(path_to_specifier(config)?, &FsLoader).await?;
+        let options = config.to_options()?;
+        (config.maybe_import_map, options)
+      }
+      (None, maybe_import_map) => (
+        maybe_import_map
+          .as_deref()
+          .map(load_import_map)
+          .transpose()?,
+        // source maps are served unless a config disables them
+        CompilerOptions {
+          source_map: true,
+          ..Default::default()
+        }
+        .into_options()?,
+      ),
+    };
+  let server = DevServer::bind(
+    (args.host.as_str(), args.port),
+    &args.dir,
+    DevServerOptions {
+      transpile_options,
+      emit_options,
+      maybe_import_map,
+      react_refresh: args.react_refresh,
+    },
+  )?;
+  println!(
+    "Serving \"{}\" at http://{}/",
+    args.dir.display(),
+    server.local_addr()?
+  );
+  server.serve()
+}
+
+fn print_paths(paths: &[PathBuf]) {
+  for path in paths {
+    println!("{}", path.display());
+  }
+}
+
+/// Builds the target, and then rebuilds it whenever its local modules change,
+/// until the process is stopped.
+async fn watch(
+  target: WatchTarget,
+  resolution: Resolution,
+  out_dir: &Path,
+  into_files: impl Fn(WatchOutput) -> Result<BTreeMap<String, Vec<u8>>>,
+) -> Result<()> {
+  let loader = Box::new(FsLoader);
+  let mut watcher = match resolution {
+    Resolution::ImportMap(maybe_import_map) => {
+      Watcher::new(target, loader, maybe_import_map)?
+    }
+    Resolution::Workspace(workspace) => {
+      Watcher::new_workspace(target, loader, &workspace)?
+    }
+  };
+  let mut changed = Vec::new();
+  loop {
+    // the modification times are taken before the build, so changes made
+    // while it runs trigger another build
+    let before = modification_times(&watcher.watched());
+    match watcher.build(std::mem::take(&mut changed)).await {
+      WatchEvent::Built {
+        output,
+        changed,
+        loaded,
+        transpiled,
+        duration,
+        ..
+      } => {
+        // output which can't be written, like two modules emitted to the
+        // same path, fails the build until the next change
+        match into_files(output).and_then(|files| write_files(out_dir, files))
+        {
+          Ok(paths) => println!(
+            "Built {} files in {}ms ({} changed, {loaded} loaded, {transpiled} transpiled).",
+            paths.len(),
+            duration.as_millis(),
+            changed.len(),
+          ),
+          Err(error) => {
+            eprintln!("error: {error:#}");
+            eprintln!(
+              "Build failed in {}ms. Waiting for changes.",
+              duration.as_millis()
+            );
+          }
+        }
+      }
+      WatchEvent::Failed {
+        error, duration, ..
+      } => {
+        eprintln!("error: {error:#}");
+        eprintln!(
+          "Build failed in {}ms. Waiting for changes.",
+          duration.as_millis()
+        );
+      }
+    }
+    changed = wait_for_changes(&watcher.watched(), before);
+  }
+}
+
+type ModificationTimes = HashMap<ModuleSpecifier, Option<SystemTime>>;
+
+fn modification_times(watched: &[ModuleSpecifier]) -> ModificationTimes {
+  watched
+    .iter()
+    .map(|specifier| (specifier.clone(), modified(specifier)))
+    .collect()
+}
+
+fn modified(specifier: &ModuleSpecifier) -> Option<SystemTime> {
+  let path = specifier.to_file_path().ok()?;
+  std::fs::metadata(path)
+    .and_then(|meta| meta.modified())
+    .ok()
+}
+
+/// Polls the modification times of the watched files until some of them
+/// differ from their times before the build, and returns those. The files
+/// which weren't watched before the build are compared with their current
+/// times instead.
+fn wait_for_changes(
+  watched: &[ModuleSpecifier],
+  mut before: ModificationTimes,
+) -> Vec<ModuleSpecifier> {
+  let initial = watched
+    .iter()
+    .map(|specifier| {
+      let time = before
+        .remove(specifier)
+        .unwrap_or_else(|| modified(specifier));
+      (specifier, time)
+    })
+    .collect::<Vec<_>>();
+  loop {
+    let changed = initial
+      .iter()
+      .filter(|(specifier, time)| modified(specifier) != *time)
+      .map(|(specifier, _)| (*specifier).clone())
+      .collect::<Vec<_>>();
+    if !changed.is_empty() {
+      return changed;
+    }
+    std::thread::sleep(POLL_INTERVAL);
+  }
+}
+
+async fn load_emit(args: &EmitArgs, loader: &FsLoader) -> Result<Emit> {
+  let (resolution, (transpile_options, mut emit_options)) =
+    match (&args.config, &args.import_map) {
+      (Some(config), _) => {
+        let workspace =
+          Workspace::load(path_to_specifier(config)?, loader).await?;
+        let options = workspace.to_options()?;
+        (Resolution::Workspace(workspace), options)
+      }
+      (None, Some(import_map)) => (
+        Resolution::ImportMap(Some(load_import_map(import_map)?)),
+        CompilerOptions::default().into_options()?,
+      ),
+      (None, None) => (
+        Resolution::ImportMap(None),
+        CompilerOptions::default().into_options()?,
+      ),
+    };
+  if let Some(source_map) = args.source_map {
+    emit_options.source_map = match source_map {
+      SourceMapArg::None => SourceMapOption::None,
+      SourceMapArg::Inline => SourceMapOption::Inline,
+      SourceMapArg::Separate => SourceMapOption::Separate,
+    };
+  }
+  if args.inline_sources {
+    emit_options.inline_sources = true;
+  }
+  Ok(Emit {
+    resolution,
+    transpile_options,
+    emit_options,
+  })
+}
+
+fn load_import_map(path: &Path) -> Result<ImportMapInput> {
+  let base_url = path_to_specifier(path)?;
+  let json_string = std::fs::read_to_string(path)
+    .with_context(|| format!("Failed reading \"{}\".", path.display()))?;
+  Ok(ImportMapInput {
+    base_url,
+    json_string,
+  })
+}
+
+fn path_to_specifier(path: &Path) -> Result<ModuleSpecifier> {
+  let path = std::fs::canonicalize(path)
+    .with_context(|| format!("Could not find \"{}\".", path.display()))?;
+  ModuleSpecifier::from_file_path(&path)
+    .map_err(|()| anyhow!("Invalid path \"{}\".", path.display()))
+}
+
+/// Writes the files to the output directory, linking each emitted file to
+/// its separate source map with a `sourceMappingURL` comment.
+fn write_files(
+  out_dir: &Path,
+  mut files: BTreeMap<String, Vec<u8>>,
+) -> Result<Vec<PathBuf>> {
+  let mapped = files
+    .keys()
+    .filter_map(|key| key.strip_suffix(".map"))
+    .filter(|key| files.contains_key(*key))
+    .map(|key| key.to_string())
+    .collect::<Vec<_>>();
+  for key in mapped {
+    let code = files.get_mut(&key).unwrap();
+    if !code.ends_with(b"\n") {
+      code.push(b'\n');
+    }
+    let file_name = key.rsplit('/').next().unwrap();
+    code.extend_from_slice(
+      format!("//# sourceMappingURL={file_name}.map\n").as_bytes(),
+    );
+  }
+
+  let mut paths = Vec::with_capacity(files.len());
+  for (key, content) in files {
+    let path = out_dir.join(&key);
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent).with_context(|| {
+        format!("Failed creating \"{}\".", parent.display())
+      })?;
+    }
+    std::fs::write(&path, content)
+      .with_context(|| format!("Failed writing \"{}\".", path.display()))?;
+    paths.push(path);
+  }
+  Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+  fn testdata(path: &str) -> String {
+    format!("{}/../testdata/{path}", env!("CARGO_MANIFEST_DIR"))
+  }
+
+  fn out_dir(name: &str) -> PathBuf {
+    let out_dir = std::env::temp_dir()
+      .join(format!("deno_emit_cli_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&out_dir);
+    out_dir
+  }
+
+  #[tokio::test]
+  async fn bundle_with_import_map() {
+    let out_dir = out_dir("bundle");
+    let cli = Cli::parse_from([
+      "deno_emit",
+      "bundle",
+      &testdata("import_map/main.ts"),
+      "--import-map",
+      &testdata("import_map/import_map.json"),
+      "--source-map",
+      "separate",
+      "--out-dir",
+      out_dir.to_str().unwrap(),
+    ]);
+    run(cli).await.unwrap();
+    let code = std::fs::read_to_string(out_dir.join("main.js")).unwrap();
+    assert!(code.contains("console.log(foo);"), "{code}");
+    assert!(
+      code.ends_with("//# sourceMappingURL=main.js.map\n"),
+      "{code}"
+    );
+    assert!(out_dir.join("main.js.map").exists());
+    std::fs::remove_dir_all(out_dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn transpile_roots() {
+    let out_dir = out_dir("transpile");
+    let cli = Cli::parse_from([
+      "deno_emit",
+      "transpile",
+      &testdata("mod1.ts"),
+      "--out-dir",
+      out_dir.to_str().unwrap(),
+    ]);
+    run(cli).await.unwrap();
+    let code = std::fs::read_to_string(out_dir.join("mod1.js")).unwrap();
+    assert!(
+      code.starts_with(
+        "import { printHello2, returnsFoo } from \"./subdir/mod2.js\";"
+      ),
+      "{code}"
+    );
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(out_dir.join("subdir")).unwrap() {
+      files.push(entry.unwrap().file_name().to_string_lossy().into_owned());
+    }
+    files.sort();
+    assert_eq!(files, vec!["mod2.js", "print_hello.js"]);
+    std::fs::remove_dir_all(out_dir).unwrap();
+  }
+}
@@ -114,7 +114,7 @@ export interface CompilerOptions {
   /** Determines if reflection meta data is emitted for legacy decorators or
    * not.  Defaults to `false`. */
   emitDecoratorMetadata?: boolean;
-  importsNotUsedAsValues?: string;
+  importsNotUsedAsValues?: "remove" | "preserve" | "error";
   /** When set, instead of writing out a `.js.map` file to provide source maps,
    * the source map will be embedded the source map content in the `.js` files.
    *
@@ -146,6 +146,18 @@ export interface CompilerOptions {
   /** The string module specifier to implicitly import JSX factories from when
    * transpiling JSX. */
   jsxImportSource?: string;
+  /** The names of the elements which are transformed to calls of the JSX
+   * factory instead of being precompiled to templates when `jsx` is
+   * `"precompile"`. */
+  precompileJsxSkipElements?: string[];
+  /** The names of the props whose values are always serialized dynamically
+   * by precompiled templates when `jsx` is `"precompile"`. */
+  precompileJsxDynamicProps?: string[];
+  /** Emit the imports which are injected by the JSX transforms as `const`
+   * declarations of dynamic imports instead of import declarations. */
+  varDeclImports?: boolean;
+  /** Remove the comments from the emitted code. */
+  removeComments?: boolean;
   /** Enables the generation of sourcemap files. */
   sourceMap?: boolean;
 }
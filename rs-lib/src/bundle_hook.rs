@@ -5,6 +5,11 @@ use deno_ast::swc::ast;
 use deno_ast::swc::bundler::Hook;
 use deno_ast::swc::bundler::ModuleRecord;
 use deno_ast::swc::common::Span;
+use deno_ast::swc::common::SyntaxContext;
+
+/// The parameter of the module factories of development bundles which the
+/// runtime passes the `main` flag and the HMR API of the module to.
+pub(crate) const META_PARAM: &str = "__deno_meta";
 
 /// This contains the logic for Deno to rewrite the `import.meta` when bundling.
 pub struct BundleHook;
@@ -15,33 +20,75 @@ impl Hook for BundleHook {
     span: Span,
     module_record: &ModuleRecord,
   ) -> Result<Vec<deno_ast::swc::ast::KeyValueProp>> {
-    Ok(vec![
-      ast::KeyValueProp {
-        key: ast::PropName::Ident(ast::IdentName::new("url".into(), span)),
-        value: Box::new(ast::Expr::Lit(ast::Lit::Str(ast::Str {
+    let main = if module_record.is_entry {
+      member(
+        ast::Expr::MetaProp(ast::MetaPropExpr {
           span,
-          value: module_record.file_name.to_string().into(),
-          raw: None,
-        }))),
-      },
-      ast::KeyValueProp {
-        key: ast::PropName::Ident(ast::IdentName::new("main".into(), span)),
-        value: Box::new(if module_record.is_entry {
-          ast::Expr::Member(ast::MemberExpr {
-            span,
-            obj: Box::new(ast::Expr::MetaProp(ast::MetaPropExpr {
-              span,
-              kind: ast::MetaPropKind::ImportMeta,
-            })),
-            prop: ast::MemberProp::Ident(ast::IdentName::new(
-              "main".into(),
-              span,
-            )),
-          })
-        } else {
-          ast::Expr::Lit(ast::Lit::Bool(ast::Bool { span, value: false }))
+          kind: ast::MetaPropKind::ImportMeta,
         }),
-      },
-    ])
+        "main",
+        span,
+      )
+    } else {
+      ast::Expr::Lit(ast::Lit::Bool(ast::Bool { span, value: false }))
+    };
+    Ok(import_meta_props(
+      span,
+      &module_record.file_name.to_string(),
+      main,
+    ))
+  }
+}
+
+/// The properties of the `import.meta` of a bundled module.
+pub(crate) fn import_meta_props(
+  span: Span,
+  url: &str,
+  main: ast::Expr,
+) -> Vec<ast::KeyValueProp> {
+  vec![
+    ast::KeyValueProp {
+      key: ast::PropName::Ident(ast::IdentName::new("url".into(), span)),
+      value: Box::new(ast::Expr::Lit(ast::Lit::Str(ast::Str {
+        span,
+        value: url.into(),
+        raw: None,
+      }))),
+    },
+    ast::KeyValueProp {
+      key: ast::PropName::Ident(ast::IdentName::new("main".into(), span)),
+      value: Box::new(main),
+    },
+  ]
+}
+
+/// A value of the `import.meta` of a module in a development bundle, which
+/// the runtime passes to the module factory.
+pub(crate) fn meta_param(name: &str, span: Span) -> ast::Expr {
+  member(
+    ast::Expr::Ident(ast::Ident::new(
+      META_PARAM.into(),
+      span,
+      SyntaxContext::empty(),
+    )),
+    name,
+    span,
+  )
+}
+
+/// The `import.meta.hot` property of a module in a development bundle with
+/// HMR, which is the API the runtime passes to the module factory.
+pub(crate) fn hot_prop(span: Span) -> ast::KeyValueProp {
+  ast::KeyValueProp {
+    key: ast::PropName::Ident(ast::IdentName::new("hot".into(), span)),
+    value: Box::new(meta_param("hot", span)),
   }
 }
+
+fn member(obj: ast::Expr, prop: &str, span: Span) -> ast::Expr {
+  ast::Expr::Member(ast::MemberExpr {
+    span,
+    obj: Box::new(obj),
+    prop: ast::MemberProp::Ident(ast::IdentName::new(prop.into(), span)),
+  })
+}
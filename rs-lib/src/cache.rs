@@ -0,0 +1,312 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::Result;
+use deno_ast::ModuleSpecifier;
+use parking_lot::Mutex;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// The key which an emit is stored under in an [`EmitCache`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EmitCacheKey {
+  pub specifier: ModuleSpecifier,
+  /// A hash of the source text that was emitted.
+  pub source_hash: String,
+  /// A hash of the options the source was emitted with.
+  pub options_hash: String,
+}
+
+impl EmitCacheKey {
+  /// A single hash which covers all the parts of the key.
+  pub fn to_hash(&self) -> String {
+    hash_parts(&[
+      self.specifier.as_str().as_bytes(),
+      self.source_hash.as_bytes(),
+      self.options_hash.as_bytes(),
+    ])
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedEmit {
+  pub code: Vec<u8>,
+  pub maybe_map: Option<Vec<u8>>,
+}
+
+/// A cache of emitted code which allows unchanged modules to skip being
+/// transpiled again.
+///
+/// When a lookup with [`EmitCache::get`] misses, the module is emitted and
+/// then stored with [`EmitCache::set`].
+pub trait EmitCache: Send + Sync {
+  fn get(&self, key: &EmitCacheKey) -> Option<CachedEmit>;
+  fn set(&self, key: &EmitCacheKey, emit: &CachedEmit);
+}
+
+/// The number of lookups which were found and not found in an
+/// [`EmitCache`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EmitCacheStats {
+  pub hits: usize,
+  pub misses: usize,
+}
+
+#[derive(Debug, Default)]
+struct LookupCounter {
+  hits: AtomicUsize,
+  misses: AtomicUsize,
+}
+
+impl LookupCounter {
+  fn count<T>(&self, maybe_emit: Option<T>) -> Option<T> {
+    let counter = if maybe_emit.is_some() {
+      &self.hits
+    } else {
+      &self.misses
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    maybe_emit
+  }
+
+  fn stats(&self) -> EmitCacheStats {
+    EmitCacheStats {
+      hits: self.hits.load(Ordering::Relaxed),
+      misses: self.misses.load(Ordering::Relaxed),
+    }
+  }
+}
+
+/// Counts the lookups of a single emit in the cache it was given, so that
+/// they can be reported with the output whatever the cache is.
+pub(crate) struct CountingEmitCache<'a> {
+  cache: &'a dyn EmitCache,
+  counter: LookupCounter,
+}
+
+impl<'a> CountingEmitCache<'a> {
+  pub fn new(cache: &'a dyn EmitCache) -> Self {
+    Self {
+      cache,
+      counter: Default::default(),
+    }
+  }
+
+  pub fn stats(&self) -> EmitCacheStats {
+    self.counter.stats()
+  }
+}
+
+impl EmitCache for CountingEmitCache<'_> {
+  fn get(&self, key: &EmitCacheKey) -> Option<CachedEmit> {
+    self.counter.count(self.cache.get(key))
+  }
+
+  fn set(&self, key: &EmitCacheKey, emit: &CachedEmit) {
+    self.cache.set(key, emit);
+  }
+}
+
+/// An [`EmitCache`] which persists emits to a directory on the file system.
+#[derive(Debug)]
+pub struct FsEmitCache {
+  dir: PathBuf,
+  counter: LookupCounter,
+}
+
+impl FsEmitCache {
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self {
+      dir: dir.into(),
+      counter: Default::default(),
+    }
+  }
+
+  /// The number of lookups which were found and not found in the cache since
+  /// it was created.
+  pub fn stats(&self) -> EmitCacheStats {
+    self.counter.stats()
+  }
+
+  fn code_path(&self, key: &EmitCacheKey) -> PathBuf {
+    self.dir.join(format!("{}.js", key.to_hash()))
+  }
+
+  fn map_path(&self, key: &EmitCacheKey) -> PathBuf {
+    self.dir.join(format!("{}.js.map", key.to_hash()))
+  }
+
+  fn read(&self, key: &EmitCacheKey) -> Option<CachedEmit> {
+    let code = std::fs::read(self.code_path(key)).ok()?;
+    let maybe_map = std::fs::read(self.map_path(key)).ok();
+    Some(CachedEmit { code, maybe_map })
+  }
+
+  fn write(&self, key: &EmitCacheKey, emit: &CachedEmit) -> Result<()> {
+    std::fs::create_dir_all(&self.dir)?;
+    // write the map first because the presence of the code file is what
+    // indicates the entry is complete
+    let map_path = self.map_path(key);
+    match &emit.maybe_map {
+      Some(map) => atomic_write_file(&map_path, map)?,
+      None => match std::fs::remove_file(&map_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+      },
+    }
+    atomic_write_file(&self.code_path(key), &emit.code)
+  }
+}
+
+impl EmitCache for FsEmitCache {
+  fn get(&self, key: &EmitCacheKey) -> Option<CachedEmit> {
+    self.counter.count(self.read(key))
+  }
+
+  fn set(&self, key: &EmitCacheKey, emit: &CachedEmit) {
+    // failing to write to the cache should never fail the emit
+    let _ = self.write(key, emit);
+  }
+}
+
+/// An [`EmitCache`] which keeps the latest emit of each module in memory, for
+/// rebuilding the same graph repeatedly, like when watching it.
+#[derive(Debug, Default)]
+pub struct MemoryEmitCache {
+  emits: Mutex<HashMap<ModuleSpecifier, (EmitCacheKey, CachedEmit)>>,
+  counter: LookupCounter,
+}
+
+impl MemoryEmitCache {
+  /// The number of lookups which were found and not found in the cache since
+  /// it was created.
+  pub fn stats(&self) -> EmitCacheStats {
+    self.counter.stats()
+  }
+
+  /// Drops the emits of the modules for which the predicate returns `false`,
+  /// like the modules which are no longer part of a graph.
+  pub fn retain(&self, mut keep: impl FnMut(&ModuleSpecifier) -> bool) {
+    self.emits.lock().retain(|specifier, _| keep(specifier));
+  }
+}
+
+impl EmitCache for MemoryEmitCache {
+  fn get(&self, key: &EmitCacheKey) -> Option<CachedEmit> {
+    let maybe_emit = self
+      .emits
+      .lock()
+      .get(&key.specifier)
+      .filter(|(cached_key, _)| cached_key == key)
+      .map(|(_, emit)| emit.clone());
+    self.counter.count(maybe_emit)
+  }
+
+  fn set(&self, key: &EmitCacheKey, emit: &CachedEmit) {
+    self
+      .emits
+      .lock()
+      .insert(key.specifier.clone(), (key.clone(), emit.clone()));
+  }
+}
+
+fn atomic_write_file(path: &Path, data: &[u8]) -> Result<()> {
+  let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+  std::fs::write(&temp_path, data)?;
+  if let Err(err) = std::fs::rename(&temp_path, path) {
+    let _ = std::fs::remove_file(&temp_path);
+    return Err(err.into());
+  }
+  Ok(())
+}
+
+pub(crate) fn source_hash(source: &str) -> String {
+  hash_parts(&[source.as_bytes()])
+}
+
+/// Hashes the debug representation of the options along with the version of
+/// this crate, so upgrading invalidates previously cached emits.
+pub(crate) fn options_hash(options: &[&dyn Debug]) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+  for option in options {
+    hasher.update(format!("{option:?}").as_bytes());
+  }
+  format!("{:x}", hasher.finalize())
+}
+
+fn hash_parts(parts: &[&[u8]]) -> String {
+  let mut hasher = Sha256::new();
+  update_parts(&mut hasher, parts);
+  format!("{:x}", hasher.finalize())
+}
+
+fn update_parts(hasher: &mut Sha256, parts: &[&[u8]]) {
+  for part in parts {
+    // include the length so the boundaries between parts are unambiguous
+    hasher.update((part.len() as u64).to_le_bytes());
+    hasher.update(part);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn key(source: &str) -> EmitCacheKey {
+    EmitCacheKey {
+      specifier: ModuleSpecifier::parse("file:///a/mod.ts").unwrap(),
+      source_hash: source_hash(source),
+      options_hash: options_hash(&[&true]),
+    }
+  }
+
+  #[test]
+  fn fs_emit_cache_get_set() {
+    let dir = std::env::temp_dir()
+      .join(format!("deno_emit_fs_emit_cache_{}", std::process::id()));
+    let cache = FsEmitCache::new(&dir);
+    let emit = CachedEmit {
+      code: b"export const a = 1;".to_vec(),
+      maybe_map: Some(b"{}".to_vec()),
+    };
+
+    assert_eq!(cache.get(&key("a")), None);
+    cache.set(&key("a"), &emit);
+    assert_eq!(cache.get(&key("a")), Some(emit));
+    assert_eq!(cache.get(&key("b")), None);
+    assert_eq!(cache.stats(), EmitCacheStats { hits: 1, misses: 2 });
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn memory_emit_cache_keeps_latest() {
+    let cache = MemoryEmitCache::default();
+    let emit = |code: &str| CachedEmit {
+      code: code.as_bytes().to_vec(),
+      maybe_map: None,
+    };
+
+    cache.set(&key("a"), &emit("a"));
+    assert_eq!(cache.get(&key("a")), Some(emit("a")));
+    cache.set(&key("b"), &emit("b"));
+    assert_eq!(cache.get(&key("a")), None);
+    assert_eq!(cache.get(&key("b")), Some(emit("b")));
+    assert_eq!(cache.stats(), EmitCacheStats { hits: 2, misses: 1 });
+    cache.retain(|specifier| specifier.path() != "/a/mod.ts");
+    assert_eq!(cache.get(&key("b")), None);
+  }
+
+  #[test]
+  fn options_hash_differs() {
+    assert_eq!(options_hash(&[&true, &"a"]), options_hash(&[&true, &"a"]));
+    assert_ne!(options_hash(&[&true, &"a"]), options_hash(&[&false, &"a"]));
+  }
+}
@@ -0,0 +1,1094 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::bail;
+use anyhow::Result;
+use deno_ast::swc::ast;
+use deno_ast::swc::atoms::Atom;
+use deno_ast::swc::common::Mark;
+use deno_ast::swc::common::Span;
+use deno_ast::swc::common::SyntaxContext;
+use deno_ast::swc::common::DUMMY_SP;
+use deno_ast::swc::transforms::resolver;
+use deno_ast::swc::visit::Visit;
+use deno_ast::swc::visit::VisitMut;
+use deno_ast::swc::visit::VisitMutWith;
+use deno_ast::swc::visit::VisitWith;
+use deno_ast::MediaType;
+use deno_ast::ModuleSpecifier;
+use deno_ast::ParseParams;
+use deno_ast::ParsedSource;
+use deno_ast::SourcePos;
+use deno_ast::SourceRangedForSpanned;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::visit::ClearContexts;
+
+/// The variables of the function that Node.js wraps CommonJS modules in, which
+/// the bindings of a converted module must not redeclare or shadow.
+const COMMONJS_VARIABLES: &[&str] =
+  &["require", "exports", "module", "__filename", "__dirname"];
+
+/// The format of the modules emitted by [`crate::transpile_graph`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ModuleFormat {
+  #[default]
+  Esm,
+  /// CommonJS modules which can be loaded with `require()`. Default and
+  /// namespace imports of modules which aren't converted ES modules go
+  /// through the same interop helpers as TypeScript's `esModuleInterop`, so
+  /// the default import of a CommonJS module is its `module.exports`.
+  ///
+  /// Modules with top-level await or `import.meta` can't be converted and
+  /// fail the emit.
+  CommonJs,
+}
+
+/// Errors when the module uses syntax that only exists in ES modules and
+/// can't be expressed in a CommonJS module.
+pub(crate) fn ensure_commonjs_compatible(
+  parsed_source: &ParsedSource,
+) -> Result<()> {
+  let mut finder = EsmOnlySyntaxFinder::default();
+  parsed_source.program_ref().visit_with(&mut finder);
+  let Some((syntax, pos)) = finder.found else {
+    return Ok(());
+  };
+  let message = match syntax {
+    EsmOnlySyntax::TopLevelAwait => {
+      "Top-level await is not supported when emitting CommonJS."
+    }
+    EsmOnlySyntax::ImportMeta => {
+      "import.meta is not supported when emitting CommonJS."
+    }
+  };
+  let position = parsed_source.text_info_lazy().line_and_column_display(pos);
+  bail!(
+    "{message} Emit ES modules instead.\n    at {}:{}:{}",
+    parsed_source.specifier(),
+    position.line_number,
+    position.column_number
+  )
+}
+
+/// Errors when the module uses top-level await, which can't be expressed in
+/// the module factories of a development bundle. Unlike CommonJS modules,
+/// they support `import.meta`.
+pub(crate) fn ensure_no_top_level_await(
+  parsed_source: &ParsedSource,
+) -> Result<()> {
+  let mut finder = EsmOnlySyntaxFinder {
+    allow_import_meta: true,
+    ..Default::default()
+  };
+  parsed_source.program_ref().visit_with(&mut finder);
+  let Some((_, pos)) = finder.found else {
+    return Ok(());
+  };
+  let position = parsed_source.text_info_lazy().line_and_column_display(pos);
+  bail!(
+    "Top-level await is not supported in development bundles.\n    at {}:{}:{}",
+    parsed_source.specifier(),
+    position.line_number,
+    position.column_number
+  )
+}
+
+enum EsmOnlySyntax {
+  TopLevelAwait,
+  ImportMeta,
+}
+
+#[derive(Default)]
+struct EsmOnlySyntaxFinder {
+  allow_import_meta: bool,
+  function_depth: usize,
+  found: Option<(EsmOnlySyntax, SourcePos)>,
+}
+
+impl EsmOnlySyntaxFinder {
+  fn found_top_level_await(&mut self, pos: SourcePos) {
+    if self.function_depth == 0 && self.found.is_none() {
+      self.found = Some((EsmOnlySyntax::TopLevelAwait, pos));
+    }
+  }
+}
+
+impl Visit for EsmOnlySyntaxFinder {
+  fn visit_function(&mut self, node: &ast::Function) {
+    self.function_depth += 1;
+    node.visit_children_with(self);
+    self.function_depth -= 1;
+  }
+
+  fn visit_arrow_expr(&mut self, node: &ast::ArrowExpr) {
+    self.function_depth += 1;
+    node.visit_children_with(self);
+    self.function_depth -= 1;
+  }
+
+  fn visit_getter_prop(&mut self, node: &ast::GetterProp) {
+    self.function_depth += 1;
+    node.visit_children_with(self);
+    self.function_depth -= 1;
+  }
+
+  fn visit_setter_prop(&mut self, node: &ast::SetterProp) {
+    self.function_depth += 1;
+    node.visit_children_with(self);
+    self.function_depth -= 1;
+  }
+
+  fn visit_await_expr(&mut self, node: &ast::AwaitExpr) {
+    self.found_top_level_await(node.start());
+    node.visit_children_with(self);
+  }
+
+  fn visit_for_of_stmt(&mut self, node: &ast::ForOfStmt) {
+    if node.is_await {
+      self.found_top_level_await(node.start());
+    }
+    node.visit_children_with(self);
+  }
+
+  fn visit_meta_prop_expr(&mut self, node: &ast::MetaPropExpr) {
+    if node.kind == ast::MetaPropKind::ImportMeta
+      && !self.allow_import_meta
+      && self.found.is_none()
+    {
+      self.found = Some((EsmOnlySyntax::ImportMeta, node.start()));
+    }
+  }
+}
+
+/// Converts the imports and exports of a transpiled module to `require()`
+/// calls and properties of `exports`.
+///
+/// Exports are defined as getters up front like ES module exports are, so
+/// they're live bindings and are available to circular imports. Imports are
+/// required in order before the rest of the module runs, and references to
+/// imported bindings read the property of the required module every time.
+/// Bindings named like the variables of a CommonJS module, such as `require`,
+/// are renamed.
+pub(crate) fn esm_to_commonjs(program: &mut ast::Program) -> Result<()> {
+  let ast::Program::Module(module) = program else {
+    return Ok(());
+  };
+  // the module is resolved again, because the transpiler renamed bindings
+  // without keeping their contexts
+  module.visit_mut_with(&mut ClearContexts);
+  let unresolved_mark = Mark::new();
+  module.visit_mut_with(&mut resolver(unresolved_mark, Mark::new(), false));
+
+  let mut names = UsedNames::default();
+  module.visit_with(&mut names);
+  module.visit_mut_with(&mut CommonJsVariableRenamer {
+    unresolved_ctxt: SyntaxContext::empty().apply_mark(unresolved_mark),
+    imported: imported_ids(module),
+    names: &mut names,
+    renamed: HashMap::new(),
+  });
+  let mut dependencies = Dependencies::collect(module);
+  for (specifier, dependency) in &mut dependencies.0 {
+    if dependency.needs_binding() {
+      dependency.binding = names.unique(&binding_name(specifier));
+    }
+  }
+
+  let mut bindings = HashMap::new();
+  let mut exports = Vec::new();
+  let mut body = Vec::new();
+  for item in std::mem::take(&mut module.body) {
+    let decl = match item {
+      ast::ModuleItem::Stmt(stmt) => {
+        body.push(ast::ModuleItem::Stmt(stmt));
+        continue;
+      }
+      ast::ModuleItem::ModuleDecl(decl) => decl,
+    };
+    match decl {
+      ast::ModuleDecl::Import(import) => {
+        let dependency = &dependencies.0[&*import.src.value];
+        for specifier in import.specifiers {
+          let (local, maybe_prop) = match specifier {
+            ast::ImportSpecifier::Named(named) => {
+              let prop = match &named.imported {
+                Some(imported) => export_name(imported),
+                None => named.local.sym.to_string(),
+              };
+              (named.local, Some(prop))
+            }
+            ast::ImportSpecifier::Default(default) => {
+              (default.local, Some("default".to_string()))
+            }
+            ast::ImportSpecifier::Namespace(namespace) => {
+              (namespace.local, None)
+            }
+          };
+          bindings.insert(
+            local.to_id(),
+            ImportedBinding {
+              module: dependency.binding.clone(),
+              maybe_prop,
+            },
+          );
+        }
+      }
+      ast::ModuleDecl::ExportDecl(export) => {
+        for name in declared_names(&export.decl) {
+          exports.push((name.clone(), name));
+        }
+        body.push(ast::ModuleItem::Stmt(ast::Stmt::Decl(export.decl)));
+      }
+      ast::ModuleDecl::ExportDefaultDecl(export) => {
+        let decl = match export.decl {
+          ast::DefaultDecl::Fn(function) => ast::Decl::Fn(ast::FnDecl {
+            ident: function
+              .ident
+              .unwrap_or_else(|| ident(&names.unique("_default"))),
+            declare: false,
+            function: function.function,
+          }),
+          ast::DefaultDecl::Class(class) => ast::Decl::Class(ast::ClassDecl {
+            ident: class
+              .ident
+              .unwrap_or_else(|| ident(&names.unique("_default"))),
+            declare: false,
+            class: class.class,
+          }),
+          ast::DefaultDecl::TsInterfaceDecl(_) => continue,
+        };
+        for name in declared_names(&decl) {
+          exports.push(("default".to_string(), name));
+        }
+        body.push(ast::ModuleItem::Stmt(ast::Stmt::Decl(decl)));
+      }
+      ast::ModuleDecl::ExportDefaultExpr(export) => {
+        let name = names.unique("_default");
+        exports.push(("default".to_string(), name.clone()));
+        body.push(ast::ModuleItem::Stmt(const_decl(
+          export.span,
+          &name,
+          export.expr,
+        )));
+      }
+      ast::ModuleDecl::ExportNamed(export) => {
+        let maybe_dependency =
+          export.src.as_ref().map(|src| &dependencies.0[&*src.value]);
+        for specifier in export.specifiers {
+          match specifier {
+            ast::ExportSpecifier::Named(named) => {
+              let orig = export_name(&named.orig);
+              let exported = match &named.exported {
+                Some(exported) => export_name(exported),
+                None => orig.clone(),
+              };
+              let value = match (maybe_dependency, &named.orig) {
+                (Some(dependency), _) => {
+                  member_text(&dependency.binding, Some(&orig))
+                }
+                (None, ast::ModuleExportName::Ident(local)) => {
+                  match bindings.get(&local.to_id()) {
+                    Some(binding) => binding.text(),
+                    None => orig,
+                  }
+                }
+                (None, ast::ModuleExportName::Str(_)) => orig,
+              };
+              exports.push((exported, value));
+            }
+            ast::ExportSpecifier::Namespace(namespace) => {
+              let dependency = maybe_dependency.unwrap();
+              exports.push((
+                export_name(&namespace.name),
+                dependency.binding.clone(),
+              ));
+            }
+            ast::ExportSpecifier::Default(default) => {
+              let dependency = maybe_dependency.unwrap();
+              exports.push((
+                default.exported.sym.to_string(),
+                member_text(&dependency.binding, Some("default")),
+              ));
+            }
+          }
+        }
+      }
+      // collected with the dependencies
+      ast::ModuleDecl::ExportAll(_) => {}
+      decl @ (ast::ModuleDecl::TsImportEquals(_)
+      | ast::ModuleDecl::TsExportAssignment(_)
+      | ast::ModuleDecl::TsNamespaceExport(_)) => {
+        body.push(ast::ModuleItem::ModuleDecl(decl));
+      }
+    }
+  }
+
+  let mut replacer = ImportReplacer {
+    bindings: &bindings,
+    uses_dynamic_import: false,
+  };
+  body.visit_mut_with(&mut replacer);
+
+  let mut helpers = Helpers::default();
+  let mut prologue =
+    "\"use strict\";\nObject.defineProperty(exports, \"__esModule\", { value: true });\n"
+      .to_string();
+  for (exported, value) in &exports {
+    prologue.push_str(&format!(
+      "Object.defineProperty(exports, {}, {{ enumerable: true, get: function() {{ return {value}; }} }});\n",
+      quote(exported)
+    ));
+  }
+  let mut requires = String::new();
+  for (specifier, dependency) in &dependencies.0 {
+    let require = format!("require({})", quote(specifier));
+    if dependency.needs_binding() {
+      let interop =
+        if dependency.namespace || (dependency.default && dependency.named) {
+          helpers.interop_require_wildcard(&mut names)
+        } else if dependency.default {
+          helpers.interop_require_default(&mut names)
+        } else {
+          ""
+        };
+      requires.push_str(&format!(
+        "const {} = {interop}({require});\n",
+        dependency.binding
+      ));
+      if dependency.export_star {
+        requires.push_str(&format!(
+          "{}({}, exports);\n",
+          helpers.export_star(&mut names),
+          dependency.binding
+        ));
+      }
+    } else if dependency.export_star {
+      requires.push_str(&format!(
+        "{}({require}, exports);\n",
+        helpers.export_star(&mut names)
+      ));
+    } else {
+      requires.push_str(&format!("{require};\n"));
+    }
+  }
+  if replacer.uses_dynamic_import {
+    let name = helpers.interop_require_wildcard(&mut names).to_string();
+    body.visit_mut_with(&mut DynamicImportHelper(&name));
+  }
+  prologue.push_str(&helpers.into_source());
+  prologue.push_str(&requires);
+
+  let mut items = parse_items(&prologue)?;
+  items.extend(body);
+  module.body = items;
+  Ok(())
+}
+
+/// Renames the bindings which have the names of the variables of a CommonJS
+/// module, like swc's module transforms do, so the `require()` calls and the
+/// `exports` of the converted module refer to the ones of CommonJS.
+///
+/// Exported bindings keep their export names, and imported bindings aren't
+/// renamed, because they're replaced with the properties of the required
+/// modules.
+struct CommonJsVariableRenamer<'a> {
+  unresolved_ctxt: SyntaxContext,
+  imported: HashSet<ast::Id>,
+  names: &'a mut UsedNames,
+  renamed: HashMap<ast::Id, Atom>,
+}
+
+impl CommonJsVariableRenamer<'_> {
+  fn new_name(&mut self, ident: &ast::Ident) -> Option<Atom> {
+    // free references are the variables of CommonJS, and labels have no
+    // context
+    if !COMMONJS_VARIABLES.contains(&&*ident.sym)
+      || ident.ctxt == self.unresolved_ctxt
+      || ident.ctxt == SyntaxContext::empty()
+      || self.imported.contains(&ident.to_id())
+    {
+      return None;
+    }
+    let names = &mut self.names;
+    Some(
+      self
+        .renamed
+        .entry(ident.to_id())
+        .or_insert_with(|| names.unique(&format!("_{}", ident.sym)).into())
+        .clone(),
+    )
+  }
+}
+
+impl VisitMut for CommonJsVariableRenamer<'_> {
+  fn visit_mut_module_items(&mut self, items: &mut Vec<ast::ModuleItem>) {
+    for item in std::mem::take(items) {
+      let ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDecl(mut export)) =
+        item
+      else {
+        let mut item = item;
+        item.visit_mut_with(self);
+        items.push(item);
+        continue;
+      };
+      let names = declared_names(&export.decl);
+      export.decl.visit_mut_with(self);
+      let new_names = declared_names(&export.decl);
+      if names == new_names {
+        items.push(ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDecl(
+          export,
+        )));
+        continue;
+      }
+      // `export const require` becomes `const _require` and
+      // `export { _require as require }`
+      items.push(ast::ModuleItem::Stmt(ast::Stmt::Decl(export.decl)));
+      items.push(ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportNamed(
+        ast::NamedExport {
+          span: export.span,
+          specifiers: names
+            .iter()
+            .zip(&new_names)
+            .map(|(name, new_name)| {
+              ast::ExportSpecifier::Named(ast::ExportNamedSpecifier {
+                span: DUMMY_SP,
+                orig: ast::ModuleExportName::Ident(ident(new_name)),
+                exported: (name != new_name)
+                  .then(|| ast::ModuleExportName::Ident(ident(name))),
+                is_type_only: false,
+              })
+            })
+            .collect(),
+          src: None,
+          type_only: false,
+          with: None,
+        },
+      )));
+    }
+  }
+
+  fn visit_mut_import_decl(&mut self, _import: &mut ast::ImportDecl) {}
+
+  fn visit_mut_named_export(&mut self, export: &mut ast::NamedExport) {
+    // re-exports refer to the exports of another module
+    if export.src.is_none() {
+      export.visit_mut_children_with(self);
+    }
+  }
+
+  fn visit_mut_export_named_specifier(
+    &mut self,
+    specifier: &mut ast::ExportNamedSpecifier,
+  ) {
+    let ast::ModuleExportName::Ident(orig) = &mut specifier.orig else {
+      return;
+    };
+    if let Some(new_name) = self.new_name(orig) {
+      if specifier.exported.is_none() {
+        specifier.exported = Some(ast::ModuleExportName::Ident(orig.clone()));
+      }
+      orig.sym = new_name;
+    }
+  }
+
+  fn visit_mut_ident(&mut self, ident: &mut ast::Ident) {
+    if let Some(new_name) = self.new_name(ident) {
+      ident.sym = new_name;
+    }
+  }
+
+  fn visit_mut_prop(&mut self, prop: &mut ast::Prop) {
+    if let ast::Prop::Shorthand(ident) = prop {
+      if let Some(new_name) = self.new_name(ident) {
+        *prop = ast::Prop::KeyValue(ast::KeyValueProp {
+          key: ast::PropName::Ident(ast::IdentName::new(
+            ident.sym.clone(),
+            ident.span,
+          )),
+          value: Box::new(ast::Expr::Ident(ast::Ident {
+            sym: new_name,
+            ..ident.clone()
+          })),
+        });
+        return;
+      }
+    }
+    prop.visit_mut_children_with(self);
+  }
+
+  fn visit_mut_object_pat_prop(&mut self, prop: &mut ast::ObjectPatProp) {
+    if let ast::ObjectPatProp::Assign(assign) = prop {
+      if let Some(new_name) = self.new_name(&assign.key.id) {
+        // `{ require }` becomes `{ require: _require }`
+        let key =
+          ast::IdentName::new(assign.key.id.sym.clone(), assign.key.span);
+        let mut binding = assign.key.clone();
+        binding.id.sym = new_name;
+        let binding = ast::Pat::Ident(binding);
+        let value = match assign.value.take() {
+          Some(mut default) => {
+            default.visit_mut_with(self);
+            ast::Pat::Assign(ast::AssignPat {
+              span: assign.span,
+              left: Box::new(binding),
+              right: default,
+            })
+          }
+          None => binding,
+        };
+        *prop = ast::ObjectPatProp::KeyValue(ast::KeyValuePatProp {
+          key: ast::PropName::Ident(key),
+          value: Box::new(value),
+        });
+        return;
+      }
+    }
+    prop.visit_mut_children_with(self);
+  }
+}
+
+/// The bindings which are declared by the imports of the module.
+fn imported_ids(module: &ast::Module) -> HashSet<ast::Id> {
+  let mut ids = HashSet::new();
+  for item in &module.body {
+    let ast::ModuleItem::ModuleDecl(ast::ModuleDecl::Import(import)) = item
+    else {
+      continue;
+    };
+    for specifier in &import.specifiers {
+      let local = match specifier {
+        ast::ImportSpecifier::Named(named) => &named.local,
+        ast::ImportSpecifier::Default(default) => &default.local,
+        ast::ImportSpecifier::Namespace(namespace) => &namespace.local,
+      };
+      ids.insert(local.to_id());
+    }
+  }
+  ids
+}
+
+/// The modules a module imports or re-exports from, in the order they're
+/// first referenced, which is the order they're evaluated in.
+#[derive(Default)]
+struct Dependencies(IndexMap<String, Dependency>);
+
+#[derive(Default)]
+struct Dependency {
+  /// The name of the variable the required module is assigned to.
+  binding: String,
+  default: bool,
+  named: bool,
+  namespace: bool,
+  export_star: bool,
+}
+
+impl Dependency {
+  fn needs_binding(&self) -> bool {
+    self.default || self.named || self.namespace
+  }
+}
+
+impl Dependencies {
+  fn collect(module: &ast::Module) -> Self {
+    let mut dependencies = Self::default();
+    for item in &module.body {
+      let ast::ModuleItem::ModuleDecl(decl) = item else {
+        continue;
+      };
+      match decl {
+        ast::ModuleDecl::Import(import) => {
+          let dependency = dependencies.get_mut(&import.src.value);
+          for specifier in &import.specifiers {
+            match specifier {
+              ast::ImportSpecifier::Named(named) => {
+                let is_default = named
+                  .imported
+                  .as_ref()
+                  .is_some_and(|name| export_name(name) == "default");
+                if is_default {
+                  dependency.default = true;
+                } else {
+                  dependency.named = true;
+                }
+              }
+              ast::ImportSpecifier::Default(_) => dependency.default = true,
+              ast::ImportSpecifier::Namespace(_) => dependency.namespace = true,
+            }
+          }
+        }
+        ast::ModuleDecl::ExportNamed(export) => {
+          let Some(src) = &export.src else {
+            continue;
+          };
+          let dependency = dependencies.get_mut(&src.value);
+          for specifier in &export.specifiers {
+            match specifier {
+              ast::ExportSpecifier::Named(named) => {
+                if export_name(&named.orig) == "default" {
+                  dependency.default = true;
+                } else {
+                  dependency.named = true;
+                }
+              }
+              ast::ExportSpecifier::Namespace(_) => dependency.namespace = true,
+              ast::ExportSpecifier::Default(_) => dependency.default = true,
+            }
+          }
+        }
+        ast::ModuleDecl::ExportAll(export) => {
+          dependencies.get_mut(&export.src.value).export_star = true;
+        }
+        _ => {}
+      }
+    }
+    dependencies
+  }
+
+  fn get_mut(&mut self, specifier: &str) -> &mut Dependency {
+    self.0.entry(specifier.to_string()).or_default()
+  }
+}
+
+/// Where an imported binding is read from.
+struct ImportedBinding {
+  module: String,
+  /// The imported export, or `None` for a namespace import.
+  maybe_prop: Option<String>,
+}
+
+impl ImportedBinding {
+  fn text(&self) -> String {
+    member_text(&self.module, self.maybe_prop.as_deref())
+  }
+
+  fn expr(&self, span: Span) -> ast::Expr {
+    let obj = ast::Expr::Ident(ast::Ident::new(
+      self.module.as_str().into(),
+      span,
+      SyntaxContext::empty(),
+    ));
+    match &self.maybe_prop {
+      Some(prop) => member(obj, prop),
+      None => obj,
+    }
+  }
+}
+
+struct ImportReplacer<'a> {
+  bindings: &'a HashMap<ast::Id, ImportedBinding>,
+  uses_dynamic_import: bool,
+}
+
+impl ImportReplacer<'_> {
+  fn replace_callee(&mut self, callee: &mut ast::Expr) -> bool {
+    let ast::Expr::Ident(ident) = callee else {
+      return false;
+    };
+    let Some(binding) = self.bindings.get(&ident.to_id()) else {
+      return false;
+    };
+    let expr = binding.expr(ident.span);
+    *callee = if binding.maybe_prop.is_some() {
+      // call imported functions without the module as `this`, like when
+      // they're called as ES module imports
+      ast::Expr::Paren(ast::ParenExpr {
+        span: DUMMY_SP,
+        expr: Box::new(ast::Expr::Seq(ast::SeqExpr {
+          span: DUMMY_SP,
+          exprs: vec![
+            Box::new(ast::Expr::Lit(ast::Lit::Num(ast::Number {
+              span: DUMMY_SP,
+              value: 0.0,
+              raw: None,
+            }))),
+            Box::new(expr),
+          ],
+        })),
+      })
+    } else {
+      expr
+    };
+    true
+  }
+}
+
+impl VisitMut for ImportReplacer<'_> {
+  fn visit_mut_expr(&mut self, expr: &mut ast::Expr) {
+    match expr {
+      ast::Expr::Ident(ident) => {
+        if let Some(binding) = self.bindings.get(&ident.to_id()) {
+          *expr = binding.expr(ident.span);
+        }
+      }
+      ast::Expr::Call(call)
+        if matches!(call.callee, ast::Callee::Import(_)) =>
+      {
+        call.visit_mut_children_with(self);
+        self.uses_dynamic_import = true;
+      }
+      _ => expr.visit_mut_children_with(self),
+    }
+  }
+
+  fn visit_mut_callee(&mut self, callee: &mut ast::Callee) {
+    if let ast::Callee::Expr(expr) = callee {
+      if self.replace_callee(expr) {
+        return;
+      }
+    }
+    callee.visit_mut_children_with(self);
+  }
+
+  fn visit_mut_tagged_tpl(&mut self, tagged_tpl: &mut ast::TaggedTpl) {
+    if !self.replace_callee(&mut tagged_tpl.tag) {
+      tagged_tpl.tag.visit_mut_with(self);
+    }
+    tagged_tpl.tpl.visit_mut_with(self);
+  }
+
+  fn visit_mut_prop(&mut self, prop: &mut ast::Prop) {
+    if let ast::Prop::Shorthand(ident) = prop {
+      if let Some(binding) = self.bindings.get(&ident.to_id()) {
+        *prop = ast::Prop::KeyValue(ast::KeyValueProp {
+          key: ast::PropName::Ident(ast::IdentName::new(
+            ident.sym.clone(),
+            ident.span,
+          )),
+          value: Box::new(binding.expr(ident.span)),
+        });
+        return;
+      }
+    }
+    prop.visit_mut_children_with(self);
+  }
+}
+
+/// Replaces dynamic imports with requiring the module in a promise.
+struct DynamicImportHelper<'a>(&'a str);
+
+impl VisitMut for DynamicImportHelper<'_> {
+  fn visit_mut_expr(&mut self, expr: &mut ast::Expr) {
+    expr.visit_mut_children_with(self);
+    let ast::Expr::Call(call) = expr else {
+      return;
+    };
+    if !matches!(call.callee, ast::Callee::Import(_)) {
+      return;
+    }
+    // the import attributes have no equivalent in `require()`
+    let args = call.args.drain(..).take(1).collect::<Vec<_>>();
+    let require = call_expr(ident_expr("require"), args);
+    let interop = call_expr(ident_expr(self.0), vec![expr_arg(require)]);
+    let then = ast::Expr::Arrow(ast::ArrowExpr {
+      span: DUMMY_SP,
+      ctxt: SyntaxContext::empty(),
+      params: Vec::new(),
+      body: Box::new(ast::BlockStmtOrExpr::Expr(Box::new(interop))),
+      is_async: false,
+      is_generator: false,
+      type_params: None,
+      return_type: None,
+    });
+    let resolved =
+      call_expr(member(ident_expr("Promise"), "resolve"), Vec::new());
+    let span = call.span;
+    *expr = call_expr(member(resolved, "then"), vec![expr_arg(then)]);
+    if let ast::Expr::Call(call) = expr {
+      call.span = span;
+    }
+  }
+}
+
+#[derive(Default)]
+struct Helpers {
+  interop_require_default: Option<String>,
+  interop_require_wildcard: Option<String>,
+  export_star: Option<String>,
+}
+
+impl Helpers {
+  fn interop_require_default(&mut self, names: &mut UsedNames) -> &str {
+    self
+      .interop_require_default
+      .get_or_insert_with(|| names.unique("_interop_require_default"))
+  }
+
+  fn interop_require_wildcard(&mut self, names: &mut UsedNames) -> &str {
+    self
+      .interop_require_wildcard
+      .get_or_insert_with(|| names.unique("_interop_require_wildcard"))
+  }
+
+  fn export_star(&mut self, names: &mut UsedNames) -> &str {
+    self
+      .export_star
+      .get_or_insert_with(|| names.unique("_export_star"))
+  }
+
+  fn into_source(self) -> String {
+    let mut source = String::new();
+    if let Some(name) = self.interop_require_default {
+      source.push_str(&format!(
+        r#"function {name}(obj) {{
+  return obj && obj.__esModule ? obj : {{ default: obj }};
+}}
+"#
+      ));
+    }
+    if let Some(name) = self.interop_require_wildcard {
+      source.push_str(&format!(
+        r#"function {name}(obj) {{
+  if (obj && obj.__esModule) return obj;
+  var newObj = {{ default: obj }};
+  if (obj != null && (typeof obj === "object" || typeof obj === "function")) {{
+    for (var key in obj) {{
+      if (key !== "default" && Object.prototype.hasOwnProperty.call(obj, key)) newObj[key] = obj[key];
+    }}
+  }}
+  return newObj;
+}}
+"#
+      ));
+    }
+    if let Some(name) = self.export_star {
+      source.push_str(&format!(
+        r#"function {name}(from, to) {{
+  Object.keys(from).forEach(function(key) {{
+    if (key !== "default" && !Object.prototype.hasOwnProperty.call(to, key)) {{
+      Object.defineProperty(to, key, {{ enumerable: true, get: function() {{ return from[key]; }} }});
+    }}
+  }});
+  return from;
+}}
+"#
+      ));
+    }
+    source
+  }
+}
+
+/// The names of the identifiers in the module, so generated bindings don't
+/// shadow them.
+#[derive(Default)]
+struct UsedNames(HashSet<String>);
+
+impl UsedNames {
+  fn unique(&mut self, base: &str) -> String {
+    let mut name = base.to_string();
+    let mut i = 1;
+    while !self.0.insert(name.clone()) {
+      name = format!("{base}{i}");
+      i += 1;
+    }
+    name
+  }
+}
+
+impl Visit for UsedNames {
+  fn visit_ident(&mut self, ident: &ast::Ident) {
+    self.0.insert(ident.sym.to_string());
+  }
+}
+
+/// The name of the binding a required module is assigned to, which is based
+/// on its file name, such as `_mod` for `./mod.js`.
+fn binding_name(specifier: &str) -> String {
+  let file_name = specifier
+    .trim_end_matches('/')
+    .rsplit(['/', ':'])
+    .next()
+    .unwrap_or_default();
+  let stem = file_name.split('.').next().unwrap_or_default();
+  let mut name = "_".to_string();
+  for c in stem.chars() {
+    if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+      name.push(c);
+    } else {
+      name.push('_');
+    }
+  }
+  name
+}
+
+fn declared_names(decl: &ast::Decl) -> Vec<String> {
+  match decl {
+    ast::Decl::Class(class) => vec![class.ident.sym.to_string()],
+    ast::Decl::Fn(function) => vec![function.ident.sym.to_string()],
+    ast::Decl::Var(var) => {
+      let mut collector = BindingNames::default();
+      for decl in &var.decls {
+        decl.name.visit_with(&mut collector);
+      }
+      collector.0
+    }
+    ast::Decl::Using(using) => {
+      let mut collector = BindingNames::default();
+      for decl in &using.decls {
+        decl.name.visit_with(&mut collector);
+      }
+      collector.0
+    }
+    ast::Decl::TsEnum(ts_enum) => vec![ts_enum.id.sym.to_string()],
+    ast::Decl::TsModule(_)
+    | ast::Decl::TsInterface(_)
+    | ast::Decl::TsTypeAlias(_) => Vec::new(),
+  }
+}
+
+#[derive(Default)]
+struct BindingNames(Vec<String>);
+
+impl Visit for BindingNames {
+  fn visit_binding_ident(&mut self, ident: &ast::BindingIdent) {
+    self.0.push(ident.id.sym.to_string());
+  }
+
+  // default values and computed keys don't declare bindings
+  fn visit_expr(&mut self, _expr: &ast::Expr) {}
+}
+
+fn export_name(name: &ast::ModuleExportName) -> String {
+  match name {
+    ast::ModuleExportName::Ident(ident) => ident.sym.to_string(),
+    ast::ModuleExportName::Str(str) => str.value.to_string(),
+  }
+}
+
+fn member_text(obj: &str, maybe_prop: Option<&str>) -> String {
+  match maybe_prop {
+    Some(prop) if is_ident_name(prop) => {
+      format!("{obj}.{prop}")
+    }
+    Some(prop) => format!("{obj}[{}]", quote(prop)),
+    None => obj.to_string(),
+  }
+}
+
+/// Whether the text can be used as a property name after a dot, which
+/// unlike a binding may be a reserved word.
+fn is_ident_name(text: &str) -> bool {
+  let mut chars = text.chars();
+  chars.next().is_some_and(ast::Ident::is_valid_start)
+    && chars.all(ast::Ident::is_valid_continue)
+}
+
+fn quote(text: &str) -> String {
+  serde_json::to_string(text).unwrap()
+}
+
+fn ident(name: &str) -> ast::Ident {
+  ast::Ident::new(name.into(), DUMMY_SP, SyntaxContext::empty())
+}
+
+fn ident_expr(name: &str) -> ast::Expr {
+  ast::Expr::Ident(ident(name))
+}
+
+fn member(obj: ast::Expr, prop: &str) -> ast::Expr {
+  let prop = if is_ident_name(prop) {
+    ast::MemberProp::Ident(ast::IdentName::new(prop.into(), DUMMY_SP))
+  } else {
+    ast::MemberProp::Computed(ast::ComputedPropName {
+      span: DUMMY_SP,
+      expr: Box::new(ast::Expr::Lit(ast::Lit::Str(ast::Str {
+        span: DUMMY_SP,
+        value: prop.into(),
+        raw: None,
+      }))),
+    })
+  };
+  ast::Expr::Member(ast::MemberExpr {
+    span: DUMMY_SP,
+    obj: Box::new(obj),
+    prop,
+  })
+}
+
+fn call_expr(callee: ast::Expr, args: Vec<ast::ExprOrSpread>) -> ast::Expr {
+  ast::Expr::Call(ast::CallExpr {
+    span: DUMMY_SP,
+    ctxt: SyntaxContext::empty(),
+    callee: ast::Callee::Expr(Box::new(callee)),
+    args,
+    type_args: None,
+  })
+}
+
+fn expr_arg(expr: ast::Expr) -> ast::ExprOrSpread {
+  ast::ExprOrSpread {
+    spread: None,
+    expr: Box::new(expr),
+  }
+}
+
+pub(crate) fn const_decl(
+  span: Span,
+  name: &str,
+  init: Box<ast::Expr>,
+) -> ast::Stmt {
+  ast::Stmt::Decl(ast::Decl::Var(Box::new(ast::VarDecl {
+    span,
+    ctxt: SyntaxContext::empty(),
+    kind: ast::VarDeclKind::Const,
+    declare: false,
+    decls: vec![ast::VarDeclarator {
+      span,
+      name: ast::Pat::Ident(ast::BindingIdent {
+        id: ident(name),
+        type_ann: None,
+      }),
+      init: Some(init),
+      definite: false,
+    }],
+  })))
+}
+
+/// Parses generated code into module items without source positions, so
+/// they don't map to the wrong place in the source map.
+fn parse_items(source: &str) -> Result<Vec<ast::ModuleItem>> {
+  let parsed_source = deno_ast::parse_module(ParseParams {
+    specifier: ModuleSpecifier::parse("file:///commonjs.js").unwrap(),
+    text: source.into(),
+    media_type: MediaType::JavaScript,
+    capture_tokens: false,
+    scope_analysis: false,
+    maybe_syntax: None,
+  })?;
+  let mut items = match parsed_source.program_ref() {
+    ast::Program::Module(module) => module.body.clone(),
+    ast::Program::Script(script) => script
+      .body
+      .iter()
+      .cloned()
+      .map(ast::ModuleItem::Stmt)
+      .collect(),
+  };
+  items.visit_mut_with(&mut DropSpans);
+  Ok(items)
+}
+
+struct DropSpans;
+
+impl VisitMut for DropSpans {
+  fn visit_mut_span(&mut self, span: &mut Span) {
+    *span = DUMMY_SP;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_binding_name() {
+    assert_eq!(binding_name("./sub/b.js"), "_b");
+    assert_eq!(
+      binding_name("https://esm.sh/react-dom@18/"),
+      "_react_dom_18"
+    );
+    assert_eq!(binding_name("node:fs"), "_fs");
+    assert_eq!(binding_name("./data.json"), "_data");
+  }
+}
@@ -0,0 +1,712 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use deno_ast::EmitOptions;
+use deno_ast::ImportsNotUsedAsValues;
+use deno_ast::ModuleSpecifier;
+use deno_ast::SourceMapOption;
+use deno_ast::TranspileOptions;
+use deno_graph::source::CacheSetting;
+use deno_graph::source::LoadOptions;
+use deno_graph::source::LoadResponse;
+use deno_graph::source::Loader;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use indexmap::IndexMap;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::ImportMapInput;
+
+/// The maximum number of redirects that are followed when loading a
+/// configuration file.
+const MAX_REDIRECTS: usize = 10;
+
+/// This is a deserializable structure of the `"compilerOptions"` section of a
+/// TypeScript or Deno configuration file which can effect how the emitting is
+/// handled, all other options don't impact the output.
+#[derive(serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+#[derive(Clone, Debug)]
+pub struct CompilerOptions {
+  pub check_js: bool,
+  pub experimental_decorators: bool,
+  pub emit_decorator_metadata: bool,
+  pub imports_not_used_as_values: String,
+  pub inline_source_map: bool,
+  pub inline_sources: bool,
+  pub jsx: String,
+  pub jsx_factory: String,
+  pub jsx_fragment_factory: String,
+  pub jsx_import_source: Option<String>,
+  /// The names of the elements which are transformed to calls of the
+  /// factory instead of being precompiled to templates, with
+  /// `jsx: "precompile"`. Deno configuration files name it
+  /// `"jsxPrecompileSkipElements"`.
+  #[serde(alias = "jsxPrecompileSkipElements")]
+  pub precompile_jsx_skip_elements: Option<Vec<String>>,
+  /// The names of the props whose values are always serialized dynamically
+  /// by precompiled templates, with `jsx: "precompile"`.
+  pub precompile_jsx_dynamic_props: Option<Vec<String>>,
+  /// Emit the imports which the JSX transforms inject as `const` declarations
+  /// of dynamic imports instead of import declarations.
+  pub var_decl_imports: bool,
+  pub remove_comments: bool,
+  pub source_map: bool,
+  /// The URL of the directory which non-relative imports are resolved
+  /// against, and which the `paths` are relative to.
+  pub base_url: Option<String>,
+  /// The path aliases, like `"@app/*": ["./src/*"]`, with the targets that
+  /// are tried in order. They're relative to the `base_url`, or to the
+  /// directory of the configuration file which declares them.
+  pub paths: BTreeMap<String, Vec<String>>,
+}
+
+impl CompilerOptions {
+  /// The transpile and emit options, which fails when an option has a value
+  /// that isn't supported.
+  pub fn into_options(self) -> Result<(TranspileOptions, EmitOptions)> {
+    let imports_not_used_as_values =
+      match self.imports_not_used_as_values.as_str() {
+        "remove" => ImportsNotUsedAsValues::Remove,
+        "preserve" => ImportsNotUsedAsValues::Preserve,
+        "error" => ImportsNotUsedAsValues::Error,
+        value => bail!(
+          "Unsupported \"importsNotUsedAsValues\" compiler option \"{value}\". Expected \"remove\", \"preserve\" or \"error\"."
+        ),
+      };
+
+    // copied from the CLI
+    let (transform_jsx, jsx_automatic, jsx_development, precompile_jsx) =
+      match self.jsx.as_str() {
+        "react" => (true, false, false, false),
+        "react-jsx" => (true, true, false, false),
+        "react-jsxdev" => (true, true, true, false),
+        "precompile" => (false, false, false, true),
+        // the JSX is left as is
+        "preserve" | "react-native" => (false, false, false, false),
+        value => bail!(
+          "Unsupported \"jsx\" compiler option \"{value}\". Expected \"react\", \"react-jsx\", \"react-jsxdev\", \"precompile\", \"preserve\" or \"react-native\"."
+        ),
+      };
+    let source_map = if self.inline_source_map {
+      SourceMapOption::Inline
+    } else if self.source_map {
+      SourceMapOption::Separate
+    } else {
+      SourceMapOption::None
+    };
+
+    Ok((
+      TranspileOptions {
+        use_decorators_proposal: !self.experimental_decorators,
+        use_ts_decorators: self.experimental_decorators,
+        emit_metadata: self.emit_decorator_metadata,
+        imports_not_used_as_values,
+        jsx_factory: self.jsx_factory,
+        jsx_fragment_factory: self.jsx_fragment_factory,
+        transform_jsx,
+        var_decl_imports: self.var_decl_imports,
+        jsx_automatic,
+        jsx_development,
+        jsx_import_source: self.jsx_import_source,
+        precompile_jsx,
+        precompile_jsx_skip_elements: self.precompile_jsx_skip_elements,
+        precompile_jsx_dynamic_props: self.precompile_jsx_dynamic_props,
+      },
+      EmitOptions {
+        inline_sources: self.inline_sources,
+        source_map_file: None,
+        source_map_base: None,
+        remove_comments: self.remove_comments,
+        source_map,
+      },
+    ))
+  }
+}
+
+impl Default for CompilerOptions {
+  fn default() -> Self {
+    Self {
+      experimental_decorators: false,
+      check_js: false,
+      emit_decorator_metadata: false,
+      imports_not_used_as_values: "remove".to_string(),
+      inline_source_map: false,
+      inline_sources: false,
+      jsx: "react".to_string(),
+      jsx_factory: "React.createElement".to_string(),
+      jsx_fragment_factory: "React.Fragment".to_string(),
+      jsx_import_source: None,
+      precompile_jsx_skip_elements: None,
+      precompile_jsx_dynamic_props: None,
+      var_decl_imports: false,
+      remove_comments: false,
+      source_map: false,
+      base_url: None,
+      paths: BTreeMap::new(),
+    }
+  }
+}
+
+/// A `deno.json(c)` or `tsconfig.json` configuration file.
+///
+/// The `"compilerOptions"` of the files it `"extends"` are merged into its
+/// own, and its import map is either the embedded `"imports"` and `"scopes"`
+/// or the file referenced by `"importMap"`.
+#[derive(Debug)]
+pub struct ConfigFile {
+  pub specifier: ModuleSpecifier,
+  pub compiler_options: CompilerOptions,
+  pub maybe_import_map: Option<ImportMapInput>,
+  /// The `"name"` of the package, for a workspace member.
+  pub maybe_name: Option<String>,
+  /// The `"exports"` of the package, keyed by their path relative to the
+  /// package, like `"."` or `"./util"`.
+  pub exports: IndexMap<String, String>,
+  /// The directories of the `"workspace"` members, relative to the
+  /// configuration file.
+  pub workspace: Vec<String>,
+  /// The directory of the configuration file which declares the `paths` of
+  /// the compiler options, which may be a file it extends. Like TypeScript's
+  /// `pathsBasePath`, the `paths` are relative to it when there's no
+  /// `baseUrl`.
+  pub(crate) maybe_paths_base: Option<ModuleSpecifier>,
+}
+
+impl ConfigFile {
+  /// Loads the configuration file, and the files it extends and references,
+  /// with the loader.
+  pub async fn load(
+    specifier: ModuleSpecifier,
+    loader: &dyn Loader,
+  ) -> Result<Self> {
+    let (specifier, text) = load_text(loader, specifier).await?;
+    Self::load_from_text(specifier, &text, loader).await
+  }
+
+  /// Parses the text of the configuration file, loading the files it extends
+  /// and references with the loader.
+  pub async fn load_from_text(
+    specifier: ModuleSpecifier,
+    text: &str,
+    loader: &dyn Loader,
+  ) -> Result<Self> {
+    let value = parse_jsonc(&specifier, text)?;
+    let LoadedCompilerOptions {
+      options: compiler_options,
+      maybe_paths_base,
+    } = load_compiler_options(loader, &specifier, &value, &mut Vec::new())
+      .await?;
+    let compiler_options =
+      serde_json::from_value(Value::Object(compiler_options)).with_context(
+        || format!("Invalid \"compilerOptions\" in \"{specifier}\"."),
+      )?;
+    let maybe_import_map = load_import_map(loader, &specifier, &value).await?;
+    let maybe_name = match value.get("name") {
+      None => None,
+      Some(Value::String(name)) => Some(name.clone()),
+      Some(_) => bail!("\"name\" in \"{specifier}\" must be a string."),
+    };
+    let exports = match value.get("exports") {
+      None => IndexMap::new(),
+      Some(Value::String(export)) => {
+        IndexMap::from([(".".to_string(), export.clone())])
+      }
+      Some(Value::Object(exports)) => exports
+        .iter()
+        .map(|(key, value)| match value {
+          Value::String(value) => Ok((key.clone(), value.clone())),
+          _ => {
+            bail!("\"exports\" in \"{specifier}\" must only contain strings.")
+          }
+        })
+        .collect::<Result<_>>()?,
+      Some(_) => {
+        bail!("\"exports\" in \"{specifier}\" must be a string or an object.")
+      }
+    };
+    let workspace = match value.get("workspace") {
+      None => Vec::new(),
+      Some(Value::Array(members)) => string_items(&specifier, members)?,
+      Some(Value::Object(workspace)) => match workspace.get("members") {
+        Some(Value::Array(members)) => string_items(&specifier, members)?,
+        _ => bail!(
+          "\"workspace.members\" in \"{specifier}\" must be an array of strings."
+        ),
+      },
+      Some(_) => bail!(
+        "\"workspace\" in \"{specifier}\" must be an array or an object."
+      ),
+    };
+    Ok(Self {
+      specifier,
+      compiler_options,
+      maybe_import_map,
+      maybe_name,
+      exports,
+      workspace,
+      maybe_paths_base,
+    })
+  }
+
+  /// The transpile and emit options of the configuration file.
+  pub fn to_options(&self) -> Result<(TranspileOptions, EmitOptions)> {
+    self.compiler_options.clone().into_options()
+  }
+}
+
+fn string_items(
+  specifier: &ModuleSpecifier,
+  items: &[Value],
+) -> Result<Vec<String>> {
+  items
+    .iter()
+    .map(|item| match item {
+      Value::String(item) => Ok(item.clone()),
+      _ => bail!("\"workspace\" in \"{specifier}\" must only contain strings."),
+    })
+    .collect()
+}
+
+/// Loads the text of a file, following redirects, and returns the final
+/// specifier with the text.
+async fn load_text(
+  loader: &dyn Loader,
+  specifier: ModuleSpecifier,
+) -> Result<(ModuleSpecifier, String)> {
+  match maybe_load_text(loader, specifier.clone()).await? {
+    Some(loaded) => Ok(loaded),
+    None => bail!("Could not find \"{specifier}\"."),
+  }
+}
+
+/// Loads the text of a file like [`load_text`], but returns `None` when the
+/// file doesn't exist.
+pub(crate) async fn maybe_load_text(
+  loader: &dyn Loader,
+  mut specifier: ModuleSpecifier,
+) -> Result<Option<(ModuleSpecifier, String)>> {
+  for _ in 0..=MAX_REDIRECTS {
+    let response = loader
+      .load(
+        &specifier,
+        LoadOptions {
+          is_dynamic: false,
+          cache_setting: CacheSetting::Use,
+          maybe_checksum: None,
+        },
+      )
+      .await
+      .with_context(|| format!("Failed loading \"{specifier}\"."))?;
+    match response {
+      Some(LoadResponse::Module {
+        content,
+        specifier: found,
+        ..
+      }) => {
+        let text = String::from_utf8(content.to_vec())
+          .with_context(|| format!("\"{found}\" is not valid UTF-8."))?;
+        return Ok(Some((found, text)));
+      }
+      Some(LoadResponse::Redirect { specifier: found }) => specifier = found,
+      Some(LoadResponse::External { .. }) | None => return Ok(None),
+    }
+  }
+  bail!("Too many redirects loading \"{specifier}\".")
+}
+
+fn parse_jsonc(
+  specifier: &ModuleSpecifier,
+  text: &str,
+) -> Result<Map<String, Value>> {
+  let maybe_value =
+    jsonc_parser::parse_to_serde_value(text, &Default::default())
+      .map_err(|err| anyhow!("Failed parsing \"{specifier}\": {err}"))?;
+  match maybe_value {
+    Some(Value::Object(map)) => Ok(map),
+    None => Ok(Map::new()),
+    Some(_) => bail!("\"{specifier}\" must contain a JSON object."),
+  }
+}
+
+/// The `"compilerOptions"` of a configuration file, with the options of the
+/// files it extends merged under its own.
+struct LoadedCompilerOptions {
+  options: Map<String, Value>,
+  /// The directory of the file which declares the `paths`.
+  maybe_paths_base: Option<ModuleSpecifier>,
+}
+
+/// Loads the `"compilerOptions"` of the configuration file. Like TypeScript,
+/// the options are merged shallowly, and later files in an `"extends"` array
+/// win over earlier ones.
+fn load_compiler_options<'a>(
+  loader: &'a dyn Loader,
+  specifier: &'a ModuleSpecifier,
+  value: &'a Map<String, Value>,
+  visited: &'a mut Vec<ModuleSpecifier>,
+) -> LocalBoxFuture<'a, Result<LoadedCompilerOptions>> {
+  async move {
+    visited.push(specifier.clone());
+    let extends = match value.get("extends") {
+      None => Vec::new(),
+      Some(Value::String(extends)) => vec![extends.as_str()],
+      Some(Value::Array(items)) => items
+        .iter()
+        .map(|item| {
+          item.as_str().ok_or_else(|| {
+            anyhow!("\"extends\" in \"{specifier}\" must only contain strings.")
+          })
+        })
+        .collect::<Result<_>>()?,
+      Some(_) => bail!(
+        "\"extends\" in \"{specifier}\" must be a string or an array of strings."
+      ),
+    };
+    let mut options = Map::new();
+    let mut maybe_paths_base = None;
+    for extends in extends {
+      let base = resolve_reference(specifier, extends, "extends")?;
+      if visited.contains(&base) {
+        bail!("\"{specifier}\" extends \"{base}\", which extends it.");
+      }
+      let (base, text) = load_text(loader, base).await?;
+      let base_value = parse_jsonc(&base, &text)?;
+      let loaded =
+        load_compiler_options(loader, &base, &base_value, visited).await?;
+      options.extend(loaded.options);
+      if loaded.maybe_paths_base.is_some() {
+        maybe_paths_base = loaded.maybe_paths_base;
+      }
+    }
+    match value.get("compilerOptions") {
+      None => {}
+      Some(Value::Object(compiler_options)) => {
+        let mut compiler_options = compiler_options.clone();
+        // the paths are relative to the file which declares them
+        let dir = specifier.join("./")?;
+        if let Some(Value::String(base_url)) = compiler_options.get("baseUrl")
+        {
+          let base_url =
+            dir.join(&format!("{}/", base_url.trim_end_matches('/')))?;
+          compiler_options
+            .insert("baseUrl".to_string(), Value::String(base_url.into()));
+        }
+        if compiler_options.contains_key("paths") {
+          maybe_paths_base = Some(dir);
+        }
+        options.extend(compiler_options)
+      }
+      Some(_) => {
+        bail!("\"compilerOptions\" in \"{specifier}\" must be an object.")
+      }
+    }
+    visited.pop();
+    Ok(LoadedCompilerOptions {
+      options,
+      maybe_paths_base,
+    })
+  }
+  .boxed_local()
+}
+
+/// The import map of the configuration file, which is either the embedded
+/// `"imports"` and `"scopes"` or the file referenced by `"importMap"`.
+async fn load_import_map(
+  loader: &dyn Loader,
+  specifier: &ModuleSpecifier,
+  value: &Map<String, Value>,
+) -> Result<Option<ImportMapInput>> {
+  let maybe_imports = value.get("imports");
+  let maybe_scopes = value.get("scopes");
+  match value.get("importMap") {
+    Some(_) if maybe_imports.is_some() || maybe_scopes.is_some() => bail!(
+      "\"{specifier}\" specifies both \"importMap\" and \"imports\" or \"scopes\". Use one or the other."
+    ),
+    Some(Value::String(import_map)) => {
+      let import_map = resolve_reference(specifier, import_map, "importMap")?;
+      let (base_url, json_string) = load_text(loader, import_map).await?;
+      Ok(Some(ImportMapInput {
+        base_url,
+        json_string,
+      }))
+    }
+    Some(_) => bail!("\"importMap\" in \"{specifier}\" must be a string."),
+    None if maybe_imports.is_none() && maybe_scopes.is_none() => Ok(None),
+    None => {
+      let mut import_map = Map::new();
+      if let Some(imports) = maybe_imports {
+        import_map.insert("imports".to_string(), imports.clone());
+      }
+      if let Some(scopes) = maybe_scopes {
+        import_map.insert("scopes".to_string(), scopes.clone());
+      }
+      Ok(Some(ImportMapInput {
+        base_url: specifier.clone(),
+        json_string: Value::Object(import_map).to_string(),
+      }))
+    }
+  }
+}
+
+/// Resolves a file referenced by a configuration file. Only relative paths
+/// and URLs are supported, as packages can't be resolved without a
+/// `node_modules` directory.
+fn resolve_reference(
+  specifier: &ModuleSpecifier,
+  reference: &str,
+  key: &str,
+) -> Result<ModuleSpecifier> {
+  if let Ok(url) = ModuleSpecifier::parse(reference) {
+    return Ok(url);
+  }
+  if !reference.starts_with("./")
+    && !reference.starts_with("../")
+    && !reference.starts_with('/')
+  {
+    bail!(
+      "Unsupported \"{key}\" of \"{reference}\" in \"{specifier}\". Only relative paths and URLs are supported."
+    );
+  }
+  specifier
+    .join(reference)
+    .with_context(|| format!("Invalid \"{key}\" in \"{specifier}\"."))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use pretty_assertions::assert_eq;
+
+  fn setup(sources: Vec<(&str, &str)>) -> MemoryLoader {
+    MemoryLoader::new(
+      sources
+        .into_iter()
+        .map(|(specifier, content)| {
+          (
+            specifier,
+            Source::Module {
+              specifier,
+              maybe_headers: None,
+              content,
+            },
+          )
+        })
+        .collect(),
+      vec![],
+    )
+  }
+
+  #[tokio::test]
+  async fn deno_json_with_imports() {
+    let loader = setup(vec![(
+      "file:///a/deno.jsonc",
+      r#"{
+  // comments and trailing commas are allowed
+  "compilerOptions": {
+    "jsx": "react-jsx",
+    "jsxImportSource": "preact",
+    "inlineSourceMap": true,
+  },
+  "imports": { "@std/": "./vendor/std/" },
+  "scopes": { "./vendor/": { "a": "./b.ts" } },
+}"#,
+    )]);
+    let config = ConfigFile::load(
+      ModuleSpecifier::parse("file:///a/deno.jsonc").unwrap(),
+      &loader,
+    )
+    .await
+    .unwrap();
+    let (transpile_options, emit_options) = config.to_options().unwrap();
+    assert!(transpile_options.jsx_automatic);
+    assert_eq!(
+      transpile_options.jsx_import_source.as_deref(),
+      Some("preact")
+    );
+    assert_eq!(emit_options.source_map, SourceMapOption::Inline);
+    let import_map = config.maybe_import_map.unwrap();
+    assert_eq!(import_map.base_url.as_str(), "file:///a/deno.jsonc");
+    assert_eq!(
+      import_map.json_string,
+      r#"{"imports":{"@std/":"./vendor/std/"},"scopes":{"./vendor/":{"a":"./b.ts"}}}"#
+    );
+  }
+
+  #[tokio::test]
+  async fn tsconfig_extends() {
+    let loader = setup(vec![
+      (
+        "file:///a/tsconfig.json",
+        r#"{
+  "extends": ["./base.json", "../shared/jsx.json"],
+  "compilerOptions": { "sourceMap": true, "pathsBasePath": "file:///b/" }
+}"#,
+      ),
+      (
+        "file:///a/base.json",
+        r#"{ "compilerOptions": { "jsx": "react-jsxdev", "experimentalDecorators": true } }"#,
+      ),
+      (
+        "file:///shared/jsx.json",
+        r#"{ "compilerOptions": { "jsx": "precompile", "paths": { "@app/*": ["./src/*"] } } }"#,
+      ),
+    ]);
+    let config = ConfigFile::load(
+      ModuleSpecifier::parse("file:///a/tsconfig.json").unwrap(),
+      &loader,
+    )
+    .await
+    .unwrap();
+    let (transpile_options, emit_options) = config.to_options().unwrap();
+    assert!(transpile_options.precompile_jsx);
+    assert!(transpile_options.use_ts_decorators);
+    assert_eq!(emit_options.source_map, SourceMapOption::Separate);
+    assert!(config.maybe_import_map.is_none());
+    // the paths are relative to the file which declares them
+    assert_eq!(config.maybe_paths_base.unwrap().as_str(), "file:///shared/");
+  }
+
+  #[tokio::test]
+  async fn import_map_reference() {
+    let loader = setup(vec![
+      (
+        "file:///a/deno.json",
+        r#"{ "importMap": "./maps/import_map.json" }"#,
+      ),
+      (
+        "file:///a/maps/import_map.json",
+        r#"{ "imports": { "b": "./b.ts" } }"#,
+      ),
+    ]);
+    let config = ConfigFile::load(
+      ModuleSpecifier::parse("file:///a/deno.json").unwrap(),
+      &loader,
+    )
+    .await
+    .unwrap();
+    let import_map = config.maybe_import_map.unwrap();
+    assert_eq!(
+      import_map.base_url.as_str(),
+      "file:///a/maps/import_map.json"
+    );
+    assert_eq!(
+      import_map.json_string,
+      r#"{ "imports": { "b": "./b.ts" } }"#
+    );
+  }
+
+  #[tokio::test]
+  async fn invalid_configs() {
+    let loader = setup(vec![
+      (
+        "file:///a/both.json",
+        r#"{ "importMap": "./map.json", "imports": {} }"#,
+      ),
+      ("file:///a/cycle.json", r#"{ "extends": "./cycle2.json" }"#),
+      ("file:///a/cycle2.json", r#"{ "extends": "./cycle.json" }"#),
+      (
+        "file:///a/package.json",
+        r#"{ "extends": "@tsconfig/node" }"#,
+      ),
+    ]);
+    let run = |specifier: &'static str| {
+      let loader = &loader;
+      async move {
+        ConfigFile::load(ModuleSpecifier::parse(specifier).unwrap(), loader)
+          .await
+          .unwrap_err()
+          .to_string()
+      }
+    };
+    assert_eq!(
+      run("file:///a/both.json").await,
+      "\"file:///a/both.json\" specifies both \"importMap\" and \"imports\" or \"scopes\". Use one or the other."
+    );
+    assert_eq!(
+      run("file:///a/cycle.json").await,
+      "\"file:///a/cycle2.json\" extends \"file:///a/cycle.json\", which extends it."
+    );
+    assert_eq!(
+      run("file:///a/package.json").await,
+      "Unsupported \"extends\" of \"@tsconfig/node\" in \"file:///a/package.json\". Only relative paths and URLs are supported."
+    );
+    assert_eq!(
+      run("file:///a/missing.json").await,
+      "Could not find \"file:///a/missing.json\"."
+    );
+  }
+
+  #[test]
+  fn compiler_options_precompile_jsx() {
+    let compiler_options: CompilerOptions = serde_json::from_str(
+      r#"{
+  "jsx": "precompile",
+  "jsxImportSource": "preact",
+  "jsxPrecompileSkipElements": ["a", "img"],
+  "precompileJsxDynamicProps": ["class"],
+  "varDeclImports": true,
+  "removeComments": true
+}"#,
+    )
+    .unwrap();
+    let (transpile_options, emit_options) =
+      compiler_options.into_options().unwrap();
+    assert!(transpile_options.precompile_jsx);
+    assert!(!transpile_options.transform_jsx);
+    assert_eq!(
+      transpile_options.precompile_jsx_skip_elements,
+      Some(vec!["a".to_string(), "img".to_string()])
+    );
+    assert_eq!(
+      transpile_options.precompile_jsx_dynamic_props,
+      Some(vec!["class".to_string()])
+    );
+    assert!(transpile_options.var_decl_imports);
+    assert!(emit_options.remove_comments);
+
+    for jsx in ["preserve", "react-native"] {
+      let (transpile_options, _) = CompilerOptions {
+        jsx: jsx.to_string(),
+        ..Default::default()
+      }
+      .into_options()
+      .unwrap();
+      assert!(!transpile_options.transform_jsx);
+      assert!(!transpile_options.precompile_jsx);
+    }
+  }
+
+  #[test]
+  fn compiler_options_unsupported_values() {
+    let err = CompilerOptions {
+      jsx: "react-jsxprod".to_string(),
+      ..Default::default()
+    }
+    .into_options()
+    .err()
+    .unwrap();
+    assert_eq!(
+      err.to_string(),
+      r#"Unsupported "jsx" compiler option "react-jsxprod". Expected "react", "react-jsx", "react-jsxdev", "precompile", "preserve" or "react-native"."#
+    );
+    let err = CompilerOptions {
+      imports_not_used_as_values: "keep".to_string(),
+      ..Default::default()
+    }
+    .into_options()
+    .err()
+    .unwrap();
+    assert_eq!(
+      err.to_string(),
+      r#"Unsupported "importsNotUsedAsValues" compiler option "keep". Expected "remove", "preserve" or "error"."#
+    );
+  }
+}
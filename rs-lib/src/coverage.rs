@@ -0,0 +1,660 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::Result;
+use deno_ast::swc::ast;
+use deno_ast::swc::common::SourceMap;
+use deno_ast::swc::common::Span;
+use deno_ast::swc::common::Spanned;
+use deno_ast::swc::common::SyntaxContext;
+use deno_ast::swc::common::DUMMY_SP;
+use deno_ast::swc::visit::VisitMut;
+use deno_ast::swc::visit::VisitMutWith;
+use deno_ast::MediaType;
+use deno_ast::ModuleSpecifier;
+use deno_ast::ParseParams;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::cache::source_hash;
+use crate::output_paths::decode;
+
+/// The version of the coverage data format of istanbul.
+const COVERAGE_SCHEMA: &str = "1a1c01bbd47fc00a2c39e90264f33305017ab4a2";
+
+/// Instruments a transpiled module with istanbul-compatible counters of its
+/// statements, functions and branches. The counters are collected in
+/// `globalThis.__coverage__` under the path of the module, so tools like
+/// `nyc report` can report the coverage of a test run.
+///
+/// The locations of the counters are the ones in the original source, which
+/// are looked up in the source map with the spans of the transpiled program.
+/// Code which the transpiler added has no span and isn't counted.
+pub(crate) fn instrument_coverage(
+  program: &mut ast::Program,
+  specifier: &ModuleSpecifier,
+  source_map: &SourceMap,
+) -> Result<()> {
+  let path = coverage_path(specifier);
+  let counter_name = format!("cov_{}", &source_hash(&path)[..8]);
+  let mut instrumenter = Instrumenter {
+    source_map,
+    counter: ast::Ident::new(
+      counter_name.as_str().into(),
+      DUMMY_SP,
+      SyntaxContext::empty(),
+    ),
+    coverage: Coverage {
+      coverage_schema: COVERAGE_SCHEMA,
+      ..Default::default()
+    },
+  };
+  program.visit_mut_with(&mut instrumenter);
+
+  let mut coverage = instrumenter.coverage;
+  coverage.path = path;
+  let data = serde_json::to_string(&coverage)?;
+  let hash = source_hash(&data);
+  let preamble = deno_ast::parse_script(ParseParams {
+    specifier: ModuleSpecifier::parse("file:///coverage.js")?,
+    text: format!(
+      r#"function {counter_name}() {{
+  var path = {path};
+  var hash = "{hash}";
+  var global = globalThis;
+  var gcv = "__coverage__";
+  var coverageData = {data};
+  coverageData.hash = hash;
+  var coverage = global[gcv] || (global[gcv] = {{}});
+  if (!coverage[path] || coverage[path].hash !== hash) {{
+    coverage[path] = coverageData;
+  }}
+  var actualCoverage = coverage[path];
+  {counter_name} = function () {{
+    return actualCoverage;
+  }};
+  return actualCoverage;
+}}
+{counter_name}();
+"#,
+      path = serde_json::to_string(&coverage.path)?,
+    )
+    .into(),
+    media_type: MediaType::JavaScript,
+    capture_tokens: false,
+    scope_analysis: false,
+    maybe_syntax: None,
+  })?;
+  let mut stmts = preamble.script().body.clone();
+  // the preamble has no location in the source of the module
+  for stmt in &mut stmts {
+    stmt.visit_mut_with(&mut ClearSpans);
+  }
+  match program {
+    ast::Program::Module(module) => {
+      module
+        .body
+        .splice(0..0, stmts.into_iter().map(ast::ModuleItem::Stmt));
+    }
+    ast::Program::Script(script) => {
+      let index = directives_len(&script.body);
+      script.body.splice(index..index, stmts);
+    }
+  }
+  Ok(())
+}
+
+/// The path the coverage of the module is collected under, which is the file
+/// path of local modules and the URL of other modules.
+fn coverage_path(specifier: &ModuleSpecifier) -> String {
+  if specifier.scheme() != "file" {
+    return specifier.to_string();
+  }
+  // `Url::to_file_path` isn't available on wasm32-unknown-unknown
+  #[cfg(not(target_arch = "wasm32"))]
+  if let Ok(path) = specifier.to_file_path() {
+    return path.to_string_lossy().into_owned();
+  }
+  decode(specifier.path())
+}
+
+#[derive(Serialize)]
+struct Position {
+  line: usize,
+  column: usize,
+}
+
+#[derive(Serialize)]
+struct Location {
+  start: Position,
+  end: Position,
+}
+
+#[derive(Serialize)]
+struct FunctionEntry {
+  name: String,
+  decl: Location,
+  loc: Location,
+  line: usize,
+}
+
+#[derive(Serialize)]
+struct BranchEntry {
+  loc: Location,
+  #[serde(rename = "type")]
+  kind: &'static str,
+  locations: Vec<Location>,
+  line: usize,
+}
+
+/// The coverage data of a module, in the format of istanbul.
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Coverage {
+  path: String,
+  statement_map: BTreeMap<usize, Location>,
+  fn_map: BTreeMap<usize, FunctionEntry>,
+  branch_map: BTreeMap<usize, BranchEntry>,
+  s: BTreeMap<usize, usize>,
+  f: BTreeMap<usize, usize>,
+  b: BTreeMap<usize, Vec<usize>>,
+  #[serde(rename = "_coverageSchema")]
+  coverage_schema: &'static str,
+}
+
+struct Instrumenter<'a> {
+  source_map: &'a SourceMap,
+  /// The function which returns the counters of the module.
+  counter: ast::Ident,
+  coverage: Coverage,
+}
+
+impl Instrumenter<'_> {
+  fn location(&self, span: Span) -> Location {
+    let start = self.source_map.lookup_char_pos(span.lo);
+    let end = self.source_map.lookup_char_pos(span.hi);
+    Location {
+      start: Position {
+        line: start.line,
+        column: start.col.0,
+      },
+      end: Position {
+        line: end.line,
+        column: end.col.0,
+      },
+    }
+  }
+
+  fn add_statement(&mut self, span: Span) -> ast::Stmt {
+    let index = self.coverage.statement_map.len();
+    self
+      .coverage
+      .statement_map
+      .insert(index, self.location(span));
+    self.coverage.s.insert(index, 0);
+    self.counter_stmt("s", index, None)
+  }
+
+  fn add_function(
+    &mut self,
+    name: Option<String>,
+    decl_span: Span,
+    span: Span,
+  ) -> ast::Stmt {
+    let index = self.coverage.fn_map.len();
+    let loc = self.location(span);
+    self.coverage.fn_map.insert(
+      index,
+      FunctionEntry {
+        name: name.unwrap_or_else(|| format!("(anonymous_{index})")),
+        decl: self.location(decl_span),
+        line: loc.start.line,
+        loc,
+      },
+    );
+    self.coverage.f.insert(index, 0);
+    self.counter_stmt("f", index, None)
+  }
+
+  /// Adds a branch with the spans of its paths, returning its index.
+  fn add_branch(
+    &mut self,
+    kind: &'static str,
+    span: Span,
+    paths: &[Span],
+  ) -> usize {
+    let index = self.coverage.branch_map.len();
+    let loc = self.location(span);
+    self.coverage.branch_map.insert(
+      index,
+      BranchEntry {
+        kind,
+        locations: paths.iter().map(|span| self.location(*span)).collect(),
+        line: loc.start.line,
+        loc,
+      },
+    );
+    self.coverage.b.insert(index, vec![0; paths.len()]);
+    index
+  }
+
+  /// `cov_hash().s[index]++`, or `cov_hash().b[index][path]++` for a path
+  /// of a branch.
+  fn counter(
+    &self,
+    kind: &str,
+    index: usize,
+    maybe_path: Option<usize>,
+  ) -> ast::Expr {
+    let call = ast::Expr::Call(ast::CallExpr {
+      span: DUMMY_SP,
+      ctxt: SyntaxContext::empty(),
+      callee: ast::Callee::Expr(Box::new(ast::Expr::Ident(
+        self.counter.clone(),
+      ))),
+      args: Vec::new(),
+      type_args: None,
+    });
+    let counters = ast::Expr::Member(ast::MemberExpr {
+      span: DUMMY_SP,
+      obj: Box::new(call),
+      prop: ast::MemberProp::Ident(ast::IdentName::new(kind.into(), DUMMY_SP)),
+    });
+    let mut target = computed_member(counters, index);
+    if let Some(path) = maybe_path {
+      target = computed_member(target, path);
+    }
+    ast::Expr::Update(ast::UpdateExpr {
+      span: DUMMY_SP,
+      op: ast::UpdateOp::PlusPlus,
+      prefix: false,
+      arg: Box::new(target),
+    })
+  }
+
+  fn counter_stmt(
+    &self,
+    kind: &str,
+    index: usize,
+    maybe_path: Option<usize>,
+  ) -> ast::Stmt {
+    ast::Stmt::Expr(ast::ExprStmt {
+      span: DUMMY_SP,
+      expr: Box::new(self.counter(kind, index, maybe_path)),
+    })
+  }
+
+  /// `(cov_hash().b[index][path]++, expr)`
+  fn count_expr(&self, index: usize, path: usize, expr: &mut Box<ast::Expr>) {
+    let inner = std::mem::replace(
+      expr,
+      Box::new(ast::Expr::Invalid(ast::Invalid { span: DUMMY_SP })),
+    );
+    *expr = Box::new(ast::Expr::Paren(ast::ParenExpr {
+      span: DUMMY_SP,
+      expr: Box::new(ast::Expr::Seq(ast::SeqExpr {
+        span: DUMMY_SP,
+        exprs: vec![Box::new(self.counter("b", index, Some(path))), inner],
+      })),
+    }));
+  }
+
+  fn cover_function_body(
+    &mut self,
+    name: Option<String>,
+    decl_span: Span,
+    span: Span,
+    body: &mut ast::BlockStmt,
+  ) {
+    if span.is_dummy() {
+      body.visit_mut_with(self);
+      return;
+    }
+    let counter = self.add_function(name, decl_span, span);
+    body.visit_mut_with(self);
+    body.stmts.insert(directives_len(&body.stmts), counter);
+  }
+
+  fn cover_function(
+    &mut self,
+    name: Option<String>,
+    decl_span: Span,
+    function: &mut ast::Function,
+  ) {
+    function.params.visit_mut_with(self);
+    function.decorators.visit_mut_with(self);
+    if let Some(body) = &mut function.body {
+      self.cover_function_body(name, decl_span, function.span, body);
+    }
+  }
+}
+
+impl VisitMut for Instrumenter<'_> {
+  fn visit_mut_module_items(&mut self, items: &mut Vec<ast::ModuleItem>) {
+    let mut instrumented = Vec::with_capacity(items.len());
+    for mut item in std::mem::take(items) {
+      let maybe_counter = match &item {
+        ast::ModuleItem::Stmt(stmt) => {
+          is_counted(stmt).then(|| self.add_statement(stmt.span()))
+        }
+        ast::ModuleItem::ModuleDecl(decl) => match decl {
+          ast::ModuleDecl::ExportDecl(export)
+            if !matches!(export.decl, ast::Decl::Fn(_))
+              && !export.span.is_dummy() =>
+          {
+            Some(self.add_statement(export.span))
+          }
+          ast::ModuleDecl::ExportDefaultExpr(export)
+            if !export.span.is_dummy() =>
+          {
+            Some(self.add_statement(export.span))
+          }
+          ast::ModuleDecl::ExportDefaultDecl(ast::ExportDefaultDecl {
+            span,
+            decl: ast::DefaultDecl::Class(_),
+          }) if !span.is_dummy() => Some(self.add_statement(*span)),
+          _ => None,
+        },
+      };
+      item.visit_mut_with(self);
+      if let Some(counter) = maybe_counter {
+        instrumented.push(ast::ModuleItem::Stmt(counter));
+      }
+      instrumented.push(item);
+    }
+    *items = instrumented;
+  }
+
+  fn visit_mut_stmts(&mut self, stmts: &mut Vec<ast::Stmt>) {
+    let mut instrumented = Vec::with_capacity(stmts.len());
+    for mut stmt in std::mem::take(stmts) {
+      let maybe_counter =
+        is_counted(&stmt).then(|| self.add_statement(stmt.span()));
+      stmt.visit_mut_with(self);
+      if let Some(counter) = maybe_counter {
+        instrumented.push(counter);
+      }
+      instrumented.push(stmt);
+    }
+    *stmts = instrumented;
+  }
+
+  fn visit_mut_stmt(&mut self, stmt: &mut ast::Stmt) {
+    // the bodies of statements are blocks, so their statements are counted
+    match stmt {
+      ast::Stmt::For(ast::ForStmt { body, .. })
+      | ast::Stmt::ForIn(ast::ForInStmt { body, .. })
+      | ast::Stmt::ForOf(ast::ForOfStmt { body, .. })
+      | ast::Stmt::While(ast::WhileStmt { body, .. })
+      | ast::Stmt::DoWhile(ast::DoWhileStmt { body, .. })
+      | ast::Stmt::Labeled(ast::LabeledStmt { body, .. }) => {
+        into_block(body);
+      }
+      _ => {}
+    }
+    stmt.visit_mut_children_with(self);
+  }
+
+  fn visit_mut_if_stmt(&mut self, if_stmt: &mut ast::IfStmt) {
+    if if_stmt.span.is_dummy() {
+      if_stmt.visit_mut_children_with(self);
+      return;
+    }
+    let alt_span = if_stmt.alt.as_ref().map_or(if_stmt.span, |alt| alt.span());
+    let index =
+      self.add_branch("if", if_stmt.span, &[if_stmt.cons.span(), alt_span]);
+    into_block(&mut if_stmt.cons);
+    let alt = if_stmt.alt.get_or_insert_with(|| {
+      Box::new(ast::Stmt::Block(ast::BlockStmt::default()))
+    });
+    into_block(alt);
+    if_stmt.visit_mut_children_with(self);
+    if let ast::Stmt::Block(block) = &mut *if_stmt.cons {
+      block
+        .stmts
+        .insert(0, self.counter_stmt("b", index, Some(0)));
+    }
+    if let Some(ast::Stmt::Block(block)) = if_stmt.alt.as_deref_mut() {
+      block
+        .stmts
+        .insert(0, self.counter_stmt("b", index, Some(1)));
+    }
+  }
+
+  fn visit_mut_switch_stmt(&mut self, switch: &mut ast::SwitchStmt) {
+    if switch.span.is_dummy() {
+      switch.visit_mut_children_with(self);
+      return;
+    }
+    let paths = switch
+      .cases
+      .iter()
+      .map(|case| case.span)
+      .collect::<Vec<_>>();
+    let index = self.add_branch("switch", switch.span, &paths);
+    switch.visit_mut_children_with(self);
+    for (path, case) in switch.cases.iter_mut().enumerate() {
+      case
+        .cons
+        .insert(0, self.counter_stmt("b", index, Some(path)));
+    }
+  }
+
+  fn visit_mut_cond_expr(&mut self, cond: &mut ast::CondExpr) {
+    if cond.span.is_dummy() {
+      cond.visit_mut_children_with(self);
+      return;
+    }
+    let index = self.add_branch(
+      "cond-expr",
+      cond.span,
+      &[cond.cons.span(), cond.alt.span()],
+    );
+    cond.visit_mut_children_with(self);
+    self.count_expr(index, 0, &mut cond.cons);
+    self.count_expr(index, 1, &mut cond.alt);
+  }
+
+  fn visit_mut_bin_expr(&mut self, bin: &mut ast::BinExpr) {
+    if !is_logical(bin.op) || bin.span.is_dummy() {
+      bin.visit_mut_children_with(self);
+      return;
+    }
+    // every operand of a chain of logical expressions is a path
+    let span = bin.span;
+    let mut operands = Vec::new();
+    logical_operands(bin, &mut operands);
+    let paths = operands.iter().map(|expr| expr.span()).collect::<Vec<_>>();
+    let index = self.add_branch("binary-expr", span, &paths);
+    for (path, operand) in operands.into_iter().enumerate() {
+      operand.visit_mut_with(self);
+      self.count_expr(index, path, operand);
+    }
+  }
+
+  fn visit_mut_fn_decl(&mut self, fn_decl: &mut ast::FnDecl) {
+    let name = fn_decl.ident.sym.to_string();
+    self.cover_function(Some(name), fn_decl.ident.span, &mut fn_decl.function);
+  }
+
+  fn visit_mut_fn_expr(&mut self, fn_expr: &mut ast::FnExpr) {
+    let name = fn_expr.ident.as_ref().map(|ident| ident.sym.to_string());
+    let decl_span = fn_expr
+      .ident
+      .as_ref()
+      .map_or(fn_expr.function.span, |ident| ident.span);
+    self.cover_function(name, decl_span, &mut fn_expr.function);
+  }
+
+  fn visit_mut_class_method(&mut self, method: &mut ast::ClassMethod) {
+    method.key.visit_mut_with(self);
+    let name = prop_name(&method.key);
+    self.cover_function(name, method.key.span(), &mut method.function);
+  }
+
+  fn visit_mut_private_method(&mut self, method: &mut ast::PrivateMethod) {
+    let name = format!("#{}", method.key.name);
+    self.cover_function(Some(name), method.key.span, &mut method.function);
+  }
+
+  fn visit_mut_method_prop(&mut self, method: &mut ast::MethodProp) {
+    method.key.visit_mut_with(self);
+    let name = prop_name(&method.key);
+    self.cover_function(name, method.key.span(), &mut method.function);
+  }
+
+  fn visit_mut_getter_prop(&mut self, getter: &mut ast::GetterProp) {
+    getter.key.visit_mut_with(self);
+    if let Some(body) = &mut getter.body {
+      let name = prop_name(&getter.key);
+      self.cover_function_body(name, getter.key.span(), getter.span, body);
+    }
+  }
+
+  fn visit_mut_setter_prop(&mut self, setter: &mut ast::SetterProp) {
+    setter.key.visit_mut_with(self);
+    setter.param.visit_mut_with(self);
+    if let Some(body) = &mut setter.body {
+      let name = prop_name(&setter.key);
+      self.cover_function_body(name, setter.key.span(), setter.span, body);
+    }
+  }
+
+  fn visit_mut_constructor(&mut self, constructor: &mut ast::Constructor) {
+    constructor.params.visit_mut_with(self);
+    if let Some(body) = &mut constructor.body {
+      self.cover_function_body(
+        Some("constructor".to_string()),
+        constructor.key.span(),
+        constructor.span,
+        body,
+      );
+    }
+  }
+
+  fn visit_mut_arrow_expr(&mut self, arrow: &mut ast::ArrowExpr) {
+    arrow.params.visit_mut_with(self);
+    if arrow.span.is_dummy() {
+      arrow.body.visit_mut_with(self);
+      return;
+    }
+    // the expression of the body is counted as a statement
+    if let ast::BlockStmtOrExpr::Expr(expr) = &mut *arrow.body {
+      let expr = std::mem::replace(
+        expr,
+        Box::new(ast::Expr::Invalid(ast::Invalid { span: DUMMY_SP })),
+      );
+      let span = expr.span();
+      *arrow.body = ast::BlockStmtOrExpr::BlockStmt(ast::BlockStmt {
+        span,
+        stmts: vec![ast::Stmt::Return(ast::ReturnStmt {
+          span,
+          arg: Some(expr),
+        })],
+        ..Default::default()
+      });
+    }
+    if let ast::BlockStmtOrExpr::BlockStmt(body) = &mut *arrow.body {
+      self.cover_function_body(None, arrow.span, arrow.span, body);
+    }
+  }
+}
+
+/// Whether the statement has its own counter. Like istanbul, blocks and
+/// function declarations aren't counted as statements, and neither are
+/// directives and statements which the transpiler added.
+fn is_counted(stmt: &ast::Stmt) -> bool {
+  match stmt {
+    ast::Stmt::Block(_)
+    | ast::Stmt::Empty(_)
+    | ast::Stmt::Decl(ast::Decl::Fn(_)) => false,
+    ast::Stmt::Expr(ast::ExprStmt { expr, .. })
+      if matches!(&**expr, ast::Expr::Lit(ast::Lit::Str(_))) =>
+    {
+      false
+    }
+    stmt => !stmt.span().is_dummy(),
+  }
+}
+
+/// The number of directives, like `"use strict"`, at the start of a body.
+fn directives_len(stmts: &[ast::Stmt]) -> usize {
+  stmts
+    .iter()
+    .take_while(|stmt| {
+      matches!(stmt, ast::Stmt::Expr(ast::ExprStmt { expr, .. })
+        if matches!(&**expr, ast::Expr::Lit(ast::Lit::Str(_))))
+    })
+    .count()
+}
+
+fn is_logical(op: ast::BinaryOp) -> bool {
+  matches!(
+    op,
+    ast::BinaryOp::LogicalAnd
+      | ast::BinaryOp::LogicalOr
+      | ast::BinaryOp::NullishCoalescing
+  )
+}
+
+fn logical_operands<'a>(
+  bin: &'a mut ast::BinExpr,
+  operands: &mut Vec<&'a mut Box<ast::Expr>>,
+) {
+  for operand in [&mut bin.left, &mut bin.right] {
+    let is_chained = matches!(
+      &**operand,
+      ast::Expr::Bin(bin) if is_logical(bin.op) && !bin.span.is_dummy()
+    );
+    if !is_chained {
+      operands.push(operand);
+    } else if let ast::Expr::Bin(bin) = &mut **operand {
+      logical_operands(bin, operands);
+    }
+  }
+}
+
+fn into_block(stmt: &mut Box<ast::Stmt>) {
+  if !matches!(**stmt, ast::Stmt::Block(_)) {
+    let inner = std::mem::replace(
+      &mut **stmt,
+      ast::Stmt::Empty(ast::EmptyStmt { span: DUMMY_SP }),
+    );
+    **stmt = ast::Stmt::Block(ast::BlockStmt {
+      span: inner.span(),
+      stmts: vec![inner],
+      ..Default::default()
+    });
+  }
+}
+
+fn computed_member(obj: ast::Expr, index: usize) -> ast::Expr {
+  ast::Expr::Member(ast::MemberExpr {
+    span: DUMMY_SP,
+    obj: Box::new(obj),
+    prop: ast::MemberProp::Computed(ast::ComputedPropName {
+      span: DUMMY_SP,
+      expr: Box::new(ast::Expr::Lit(ast::Lit::Num(ast::Number {
+        span: DUMMY_SP,
+        value: index as f64,
+        raw: None,
+      }))),
+    }),
+  })
+}
+
+fn prop_name(key: &ast::PropName) -> Option<String> {
+  match key {
+    ast::PropName::Ident(ident) => Some(ident.sym.to_string()),
+    ast::PropName::Str(str) => Some(str.value.to_string()),
+    ast::PropName::Num(num) => Some(num.value.to_string()),
+    ast::PropName::Computed(_) | ast::PropName::BigInt(_) => None,
+  }
+}
+
+struct ClearSpans;
+
+impl VisitMut for ClearSpans {
+  fn visit_mut_span(&mut self, span: &mut Span) {
+    *span = DUMMY_SP;
+  }
+}
@@ -0,0 +1,1035 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::bail;
+use anyhow::Result;
+use deno_ast::swc::ast;
+use deno_ast::swc::common::comments::Comments;
+use deno_ast::swc::common::comments::SingleThreadedComments;
+use deno_ast::swc::common::BytePos;
+use deno_ast::swc::common::Globals;
+use deno_ast::swc::common::Mark;
+use deno_ast::swc::common::Spanned;
+use deno_ast::swc::common::SyntaxContext;
+use deno_ast::swc::common::DUMMY_SP;
+use deno_ast::swc::common::GLOBALS;
+use deno_ast::swc::transforms::resolver;
+use deno_ast::swc::visit::Visit;
+use deno_ast::swc::visit::VisitMut;
+use deno_ast::swc::visit::VisitMutWith;
+use deno_ast::swc::visit::VisitWith;
+use deno_ast::EmitOptions;
+use deno_ast::ModuleSpecifier;
+use deno_ast::MultiThreadedComments;
+use deno_ast::ParseParams;
+use deno_ast::SourceMap;
+use deno_ast::SourceMapOption;
+use deno_graph::FastCheckTypeModuleSlot;
+use deno_graph::JsModule;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::rewrite::rewrite_specifiers;
+use crate::transpile::DeclarationDiagnostics;
+use crate::visit::ClearContexts;
+
+/// Rolls the declarations of the root module of the graph and every module
+/// reachable from its exports up into a single declaration file.
+///
+/// Local and remote modules with fast check declarations are inlined, with
+/// colliding top level declarations renamed. Declaration files are kept as
+/// `declare module` blocks, and the types of other modules, like npm
+/// packages, are imported from their specifiers.
+pub(crate) fn bundle_declarations(graph: &ModuleGraph) -> Result<String> {
+  let globals = Globals::new();
+  GLOBALS.set(&globals, || {
+    let mut bundler = DeclarationBundler::new(graph);
+    bundler.collect(&graph.roots[0])?;
+    bundler.emit()
+  })
+}
+
+/// How the name of a module import or export was imported.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Imported {
+  Name(String),
+  Namespace,
+}
+
+enum Export {
+  Local(String),
+  Reexport(usize, Imported),
+}
+
+/// What a name exported from a module ultimately refers to.
+enum Binding {
+  Local(usize, String),
+  External(usize, Imported),
+  Namespace(usize),
+}
+
+struct InlineModule {
+  specifier: ModuleSpecifier,
+  source: Arc<str>,
+  comments: SingleThreadedComments,
+  items: Vec<ast::ModuleItem>,
+  locals: IndexSet<String>,
+  imports: HashMap<String, (usize, Imported)>,
+  exports: IndexMap<String, Export>,
+  star_exports: Vec<usize>,
+  /// The `import("...")` types in the module with the module they refer to.
+  import_types: HashMap<String, usize>,
+}
+
+struct AmbientModule {
+  specifier: ModuleSpecifier,
+  source: Arc<str>,
+  program: ast::Program,
+  comments: SingleThreadedComments,
+}
+
+enum BundleModule {
+  /// The declarations of the module are inlined into the bundle.
+  Inline(Box<InlineModule>),
+  /// A declaration file which is kept as a `declare module` block.
+  Ambient(Box<AmbientModule>),
+  /// A module whose types are imported from its specifier.
+  External(ModuleSpecifier),
+}
+
+struct DeclarationBundler<'a> {
+  graph: &'a ModuleGraph,
+  modules: Vec<BundleModule>,
+  indexes: HashMap<ModuleSpecifier, usize>,
+  pending: VecDeque<usize>,
+  unresolved_mark: Mark,
+  top_level_mark: Mark,
+  diagnostics: DeclarationDiagnostics,
+  /// The names which are used in the bundle, which starts out with the
+  /// globals that are referenced by the modules.
+  used_names: HashSet<String>,
+  local_names: HashMap<(usize, String), String>,
+  external_names: IndexMap<(usize, Imported), String>,
+  namespace_names: IndexMap<usize, String>,
+  /// Top level aliases which allow namespaces to refer to a declaration with
+  /// the same name as their member.
+  aliases: IndexMap<String, String>,
+}
+
+impl<'a> DeclarationBundler<'a> {
+  fn new(graph: &'a ModuleGraph) -> Self {
+    Self {
+      graph,
+      modules: Vec::new(),
+      indexes: HashMap::new(),
+      pending: VecDeque::new(),
+      unresolved_mark: Mark::new(),
+      top_level_mark: Mark::new(),
+      diagnostics: DeclarationDiagnostics::default(),
+      used_names: HashSet::new(),
+      local_names: HashMap::new(),
+      external_names: IndexMap::new(),
+      namespace_names: IndexMap::new(),
+      aliases: IndexMap::new(),
+    }
+  }
+
+  fn top_level_ctxt(&self) -> SyntaxContext {
+    SyntaxContext::empty().apply_mark(self.top_level_mark)
+  }
+
+  /// Adds the module and the modules it depends on to the bundle.
+  fn collect(&mut self, root: &ModuleSpecifier) -> Result<()> {
+    let root = self.graph.resolve(root).clone();
+    self.module_index(root);
+    while let Some(index) = self.pending.pop_front() {
+      let specifier = self.module_specifier(index).clone();
+      self.modules[index] = self.load(&specifier)?;
+    }
+    std::mem::take(&mut self.diagnostics).into_result()
+  }
+
+  /// Gets the index of the module, queuing it to be loaded when it's new.
+  fn module_index(&mut self, specifier: ModuleSpecifier) -> usize {
+    if let Some(index) = self.indexes.get(&specifier) {
+      return *index;
+    }
+    let index = self.modules.len();
+    self.indexes.insert(specifier.clone(), index);
+    self.modules.push(BundleModule::External(specifier));
+    self.pending.push_back(index);
+    index
+  }
+
+  fn dependency_index(
+    &mut self,
+    module: &JsModule,
+    text: &str,
+  ) -> Result<usize> {
+    let Some(resolved) = self.resolve_dependency(module, text) else {
+      bail!(
+        "Could not resolve \"{}\" from \"{}\" when bundling declarations.",
+        text,
+        module.specifier
+      );
+    };
+    Ok(self.module_index(resolved))
+  }
+
+  fn load(&mut self, specifier: &ModuleSpecifier) -> Result<BundleModule> {
+    let graph = self.graph;
+    let Some(Module::Js(module)) = graph.get(specifier) else {
+      return Ok(BundleModule::External(specifier.clone()));
+    };
+    match &module.fast_check {
+      Some(FastCheckTypeModuleSlot::Module(fast_check_module)) => {
+        if let Some(dts) = &fast_check_module.dts {
+          for diagnostic in &dts.diagnostics {
+            self.diagnostics.add(
+              diagnostic.specifier(),
+              diagnostic,
+              diagnostic.range(),
+            );
+          }
+          return self.load_inline(module, dts.program.clone(), &dts.comments);
+        }
+      }
+      Some(FastCheckTypeModuleSlot::Error(diagnostics)) => {
+        for diagnostic in diagnostics {
+          self.diagnostics.add(
+            diagnostic.specifier(),
+            diagnostic,
+            diagnostic.range(),
+          );
+        }
+        return Ok(BundleModule::External(specifier.clone()));
+      }
+      None => {}
+    }
+    if !module.media_type.is_declaration() {
+      return Ok(BundleModule::External(specifier.clone()));
+    }
+    let parsed_source = deno_ast::parse_module(ParseParams {
+      specifier: module.specifier.clone(),
+      text: module.source.clone(),
+      media_type: module.media_type,
+      capture_tokens: false,
+      scope_analysis: false,
+      maybe_syntax: None,
+    })?;
+    // relative specifiers aren't allowed in `declare module` blocks, so
+    // refer to the other modules by their specifiers
+    let mut specifier_map = BTreeMap::new();
+    for text in module.dependencies.keys() {
+      if let Some(resolved) = self.resolve_dependency(module, text) {
+        specifier_map.insert(text.clone(), resolved.to_string());
+        self.module_index(resolved);
+      }
+    }
+    let mut program = (*parsed_source.program()).clone();
+    rewrite_specifiers(&mut program, &specifier_map);
+    program.visit_mut_with(&mut StripDeclare);
+    Ok(BundleModule::Ambient(Box::new(AmbientModule {
+      specifier: module.specifier.clone(),
+      source: module.source.clone(),
+      program,
+      comments: parsed_source.comments().as_single_threaded(),
+    })))
+  }
+
+  fn load_inline(
+    &mut self,
+    module: &JsModule,
+    program: ast::Program,
+    comments: &MultiThreadedComments,
+  ) -> Result<BundleModule> {
+    let ast::Program::Module(mut program) = program else {
+      bail!(
+        "Expected the declarations of \"{}\" to be a module.",
+        module.specifier
+      );
+    };
+    // the declarations are resolved again with marks of this bundle
+    program.visit_mut_with(&mut ClearContexts);
+    program.visit_mut_with(&mut resolver(
+      self.unresolved_mark,
+      self.top_level_mark,
+      true,
+    ));
+    let unresolved_ctxt =
+      SyntaxContext::empty().apply_mark(self.unresolved_mark);
+    let mut globals = CollectGlobals {
+      unresolved_ctxt,
+      names: &mut self.used_names,
+    };
+    program.visit_with(&mut globals);
+
+    let mut inline = InlineModule {
+      specifier: module.specifier.clone(),
+      source: module.source.clone(),
+      comments: comments.as_single_threaded(),
+      items: Vec::new(),
+      locals: IndexSet::new(),
+      imports: HashMap::new(),
+      exports: IndexMap::new(),
+      star_exports: Vec::new(),
+      import_types: HashMap::new(),
+    };
+    let mut import_types = CollectImportTypes(Vec::new());
+    program.visit_with(&mut import_types);
+    for text in import_types.0 {
+      let index = self.dependency_index(module, &text)?;
+      inline.import_types.insert(text, index);
+    }
+
+    for item in program.body {
+      let decl = match item {
+        ast::ModuleItem::ModuleDecl(ast::ModuleDecl::Import(import)) => {
+          let target = self.dependency_index(module, &import.src.value)?;
+          for specifier in import.specifiers {
+            let (local, imported) = match specifier {
+              ast::ImportSpecifier::Named(named) => {
+                let imported = match &named.imported {
+                  Some(imported) => export_name(imported),
+                  None => named.local.sym.to_string(),
+                };
+                (named.local, Imported::Name(imported))
+              }
+              ast::ImportSpecifier::Default(default) => {
+                (default.local, Imported::Name("default".to_string()))
+              }
+              ast::ImportSpecifier::Namespace(namespace) => {
+                (namespace.local, Imported::Namespace)
+              }
+            };
+            inline
+              .imports
+              .insert(local.sym.to_string(), (target, imported));
+          }
+          continue;
+        }
+        ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDecl(export)) => {
+          for name in declared_names(&export.decl) {
+            inline.exports.insert(name.clone(), Export::Local(name));
+          }
+          move_leading_comments(
+            &inline.comments,
+            export.span.lo,
+            export.decl.span().lo,
+          );
+          export.decl
+        }
+        ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportNamed(export)) => {
+          let maybe_target = match &export.src {
+            Some(src) => Some(self.dependency_index(module, &src.value)?),
+            None => None,
+          };
+          for specifier in export.specifiers {
+            match specifier {
+              ast::ExportSpecifier::Named(named) => {
+                let orig = export_name(&named.orig);
+                let exported = match &named.exported {
+                  Some(exported) => export_name(exported),
+                  None => orig.clone(),
+                };
+                let export = match maybe_target {
+                  Some(target) => {
+                    Export::Reexport(target, Imported::Name(orig))
+                  }
+                  None => Export::Local(orig),
+                };
+                inline.exports.insert(exported, export);
+              }
+              ast::ExportSpecifier::Namespace(namespace) => {
+                if let Some(target) = maybe_target {
+                  inline.exports.insert(
+                    export_name(&namespace.name),
+                    Export::Reexport(target, Imported::Namespace),
+                  );
+                }
+              }
+              ast::ExportSpecifier::Default(_) => {}
+            }
+          }
+          continue;
+        }
+        ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportAll(export)) => {
+          let target = self.dependency_index(module, &export.src.value)?;
+          inline.star_exports.push(target);
+          continue;
+        }
+        ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDefaultDecl(
+          export,
+        )) => {
+          let top_level_ctxt = self.top_level_ctxt();
+          let default_ident = |ident: Option<ast::Ident>| {
+            ident.unwrap_or_else(|| {
+              ast::Ident::new("_default".into(), DUMMY_SP, top_level_ctxt)
+            })
+          };
+          let decl = match export.decl {
+            ast::DefaultDecl::Class(class) => {
+              ast::Decl::Class(ast::ClassDecl {
+                ident: default_ident(class.ident),
+                declare: true,
+                class: class.class,
+              })
+            }
+            ast::DefaultDecl::Fn(function) => ast::Decl::Fn(ast::FnDecl {
+              ident: default_ident(function.ident),
+              declare: true,
+              function: function.function,
+            }),
+            ast::DefaultDecl::TsInterfaceDecl(interface) => {
+              ast::Decl::TsInterface(interface)
+            }
+          };
+          move_leading_comments(
+            &inline.comments,
+            export.span.lo,
+            decl.span().lo,
+          );
+          if let Some(name) = declared_names(&decl).into_iter().next() {
+            inline
+              .exports
+              .insert("default".to_string(), Export::Local(name));
+          }
+          decl
+        }
+        ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDefaultExpr(
+          export,
+        )) => {
+          let ast::Expr::Ident(ident) = &*export.expr else {
+            bail!(
+              "Unsupported default export expression in the declarations of \"{}\".",
+              module.specifier
+            );
+          };
+          inline.exports.insert(
+            "default".to_string(),
+            Export::Local(ident.sym.to_string()),
+          );
+          continue;
+        }
+        ast::ModuleItem::ModuleDecl(
+          ast::ModuleDecl::TsImportEquals(_)
+          | ast::ModuleDecl::TsExportAssignment(_)
+          | ast::ModuleDecl::TsNamespaceExport(_),
+        ) => {
+          bail!(
+            "Unsupported module declaration in the declarations of \"{}\".",
+            module.specifier
+          );
+        }
+        ast::ModuleItem::Stmt(ast::Stmt::Decl(decl)) => decl,
+        ast::ModuleItem::Stmt(stmt) => {
+          inline.items.push(ast::ModuleItem::Stmt(stmt));
+          continue;
+        }
+      };
+      inline.locals.extend(declared_names(&decl));
+      inline
+        .items
+        .push(ast::ModuleItem::Stmt(ast::Stmt::Decl(decl)));
+    }
+
+    Ok(BundleModule::Inline(Box::new(inline)))
+  }
+
+  /// Resolves the specifier as written in the module to the module whose
+  /// types it refers to.
+  fn resolve_dependency(
+    &self,
+    module: &JsModule,
+    text: &str,
+  ) -> Option<ModuleSpecifier> {
+    // the declarations may import the types dependency of an import directly
+    let maybe_fast_check_dependency = match &module.fast_check {
+      Some(FastCheckTypeModuleSlot::Module(fast_check_module)) => {
+        fast_check_module.dependencies.get(text)
+      }
+      _ => None,
+    };
+    let dependency =
+      maybe_fast_check_dependency.or_else(|| module.dependencies.get(text))?;
+    let resolved = dependency.get_type().or_else(|| dependency.get_code())?;
+    let resolved = self.graph.resolve(resolved);
+    if let Some(Module::Js(module)) = self.graph.get(resolved) {
+      if let Some(types_specifier) = module
+        .maybe_types_dependency
+        .as_ref()
+        .and_then(|types| types.dependency.maybe_specifier())
+      {
+        return Some(self.graph.resolve(types_specifier).clone());
+      }
+    }
+    Some(resolved.clone())
+  }
+
+  fn emit(&mut self) -> Result<String> {
+    // assign the names of the top level declarations, giving precedence to
+    // the root module so its declarations keep their names
+    for index in 0..self.modules.len() {
+      let BundleModule::Inline(inline) = &self.modules[index] else {
+        continue;
+      };
+      let locals = inline.locals.iter().cloned().collect::<Vec<_>>();
+      for local in locals {
+        let name = self.unique_name(&local);
+        self.local_names.insert((index, local), name);
+      }
+    }
+
+    let mut module_code = Vec::new();
+    for index in (0..self.modules.len()).rev() {
+      match &self.modules[index] {
+        BundleModule::Inline(_) => {
+          module_code.push(self.emit_inline(index)?);
+        }
+        BundleModule::Ambient(ambient) => {
+          module_code.push(format!(
+            "declare module \"{}\" {{\n{}}}\n",
+            ambient.specifier,
+            emit_program(
+              &ambient.specifier,
+              &ambient.source,
+              &ambient.program,
+              &ambient.comments,
+            )?
+          ));
+        }
+        BundleModule::External(_) => {}
+      }
+    }
+
+    let mut exports = Vec::new();
+    let mut external_star_exports = IndexSet::new();
+    for name in self.export_names(0, &mut external_star_exports) {
+      let Some(binding) = self.resolve_export(0, &name, &mut HashSet::new())
+      else {
+        continue;
+      };
+      let local = self.binding_name(binding, &name);
+      if local == name {
+        exports.push(name);
+      } else {
+        exports.push(format!("{local} as {name}"));
+      }
+    }
+
+    // namespaces may refer to other namespaces, so keep declaring them
+    // until all are declared
+    let mut namespaces = Vec::new();
+    let mut declared_count = 0;
+    while declared_count < self.namespace_names.len() {
+      let (index, name) = self
+        .namespace_names
+        .get_index(declared_count)
+        .map(|(index, name)| (*index, name.clone()))
+        .unwrap();
+      namespaces.push(self.emit_namespace(index, &name));
+      declared_count += 1;
+    }
+
+    let mut code = String::new();
+    for ((index, imported), local) in &self.external_names {
+      let specifier = self.module_specifier(*index);
+      match imported {
+        Imported::Name(name) if name == "default" => {
+          code.push_str(&format!("import {local} from \"{specifier}\";\n"));
+        }
+        Imported::Name(name) if name == local => {
+          code
+            .push_str(&format!("import {{ {name} }} from \"{specifier}\";\n"));
+        }
+        Imported::Name(name) => code.push_str(&format!(
+          "import {{ {name} as {local} }} from \"{specifier}\";\n"
+        )),
+        Imported::Namespace => {
+          code
+            .push_str(&format!("import * as {local} from \"{specifier}\";\n"));
+        }
+      }
+    }
+    for source in module_code {
+      code.push_str(&source);
+    }
+    for (alias, target) in &self.aliases {
+      code.push_str(&format!("import {alias} = {target};\n"));
+    }
+    for namespace in namespaces {
+      code.push_str(&namespace);
+    }
+    for index in external_star_exports {
+      code.push_str(&format!(
+        "export * from \"{}\";\n",
+        self.module_specifier(index)
+      ));
+    }
+    if exports.is_empty() {
+      code.push_str("export {};\n");
+    } else {
+      code.push_str(&format!("export {{ {} }};\n", exports.join(", ")));
+    }
+    Ok(code)
+  }
+
+  fn emit_inline(&mut self, index: usize) -> Result<String> {
+    let BundleModule::Inline(inline) = &self.modules[index] else {
+      unreachable!();
+    };
+    let mut names = HashMap::new();
+    for local in &inline.locals {
+      names.insert(
+        local.clone(),
+        self.local_names[&(index, local.clone())].clone(),
+      );
+    }
+    let imports = inline.imports.keys().cloned().collect::<Vec<_>>();
+    for local in imports {
+      if let Some(binding) =
+        self.resolve_local(index, &local, &mut HashSet::new())
+      {
+        let name = self.binding_name(binding, &local);
+        names.insert(local, name);
+      }
+    }
+    let BundleModule::Inline(inline) = &self.modules[index] else {
+      unreachable!();
+    };
+    let mut import_type_targets = Vec::new();
+    let mut collect = CollectImportTypeTargets(Vec::new());
+    for item in &inline.items {
+      item.visit_with(&mut collect);
+    }
+    for (text, maybe_name) in collect.0 {
+      let target = inline.import_types[&text];
+      import_type_targets.push((text, maybe_name, target));
+    }
+    let mut import_types = HashMap::new();
+    for (text, maybe_name, target) in import_type_targets {
+      let replacement = match &maybe_name {
+        Some(name) => self
+          .resolve_export(target, name, &mut HashSet::new())
+          .map(|binding| self.binding_name(binding, name)),
+        None => {
+          let binding = self.namespace_binding(target);
+          Some(self.binding_name(binding, "ns"))
+        }
+      };
+      if let Some(replacement) = replacement {
+        import_types.insert((text, maybe_name), replacement);
+      }
+    }
+
+    let BundleModule::Inline(inline) = &self.modules[index] else {
+      unreachable!();
+    };
+    let mut program = ast::Program::Module(ast::Module {
+      span: DUMMY_SP,
+      body: inline.items.clone(),
+      shebang: None,
+    });
+    let mut renamer = Renamer {
+      top_level_ctxt: self.top_level_ctxt(),
+      names: &names,
+      import_types: &import_types,
+      maybe_missing: None,
+    };
+    program.visit_mut_with(&mut renamer);
+    if let Some(missing) = renamer.maybe_missing {
+      bail!(
+        "Could not find \"{}\" in the declarations of \"{}\".",
+        missing,
+        inline.specifier
+      );
+    }
+    emit_program(
+      &inline.specifier,
+      &inline.source,
+      &program,
+      &inline.comments,
+    )
+  }
+
+  fn emit_namespace(&mut self, index: usize, name: &str) -> String {
+    let mut members = Vec::new();
+    for export_name in self.export_names(index, &mut IndexSet::new()) {
+      if export_name == "default" {
+        continue;
+      }
+      let Some(binding) =
+        self.resolve_export(index, &export_name, &mut HashSet::new())
+      else {
+        continue;
+      };
+      let mut target = self.binding_name(binding, &export_name);
+      if target == export_name {
+        // `export import a = a` would refer to itself
+        let alias = self.unique_name(&export_name);
+        self.aliases.insert(alias.clone(), target);
+        target = alias;
+      }
+      members.push(format!("  export import {export_name} = {target};\n"));
+    }
+    format!("declare namespace {name} {{\n{}}}\n", members.concat())
+  }
+
+  /// The names exported by the module, including those of star exports.
+  fn export_names(
+    &self,
+    index: usize,
+    external_star_exports: &mut IndexSet<usize>,
+  ) -> IndexSet<String> {
+    let mut names = IndexSet::new();
+    let mut visited = HashSet::new();
+    self.collect_export_names(
+      index,
+      true,
+      &mut names,
+      external_star_exports,
+      &mut visited,
+    );
+    names
+  }
+
+  fn collect_export_names(
+    &self,
+    index: usize,
+    include_default: bool,
+    names: &mut IndexSet<String>,
+    external_star_exports: &mut IndexSet<usize>,
+    visited: &mut HashSet<usize>,
+  ) {
+    if !visited.insert(index) {
+      return;
+    }
+    let BundleModule::Inline(inline) = &self.modules[index] else {
+      external_star_exports.insert(index);
+      return;
+    };
+    for name in inline.exports.keys() {
+      if include_default || name != "default" {
+        names.insert(name.clone());
+      }
+    }
+    for target in &inline.star_exports {
+      self.collect_export_names(
+        *target,
+        false,
+        names,
+        external_star_exports,
+        visited,
+      );
+    }
+  }
+
+  fn resolve_export(
+    &self,
+    index: usize,
+    name: &str,
+    visited: &mut HashSet<(usize, String)>,
+  ) -> Option<Binding> {
+    if !visited.insert((index, name.to_string())) {
+      return None;
+    }
+    let BundleModule::Inline(inline) = &self.modules[index] else {
+      return Some(Binding::External(index, Imported::Name(name.to_string())));
+    };
+    match inline.exports.get(name) {
+      Some(Export::Local(local)) => self.resolve_local(index, local, visited),
+      Some(Export::Reexport(target, Imported::Name(imported))) => {
+        self.resolve_export(*target, imported, visited)
+      }
+      Some(Export::Reexport(target, Imported::Namespace)) => {
+        Some(self.namespace_binding(*target))
+      }
+      None if name == "default" => None,
+      None => {
+        // prefer the bundled modules, because any name may be exported
+        // from an external module
+        let is_inline = |target: &&usize| {
+          matches!(self.modules[**target], BundleModule::Inline(_))
+        };
+        let inline_targets = inline.star_exports.iter().filter(is_inline);
+        let external_targets = inline
+          .star_exports
+          .iter()
+          .filter(|target| !is_inline(target));
+        inline_targets
+          .chain(external_targets)
+          .find_map(|target| self.resolve_export(*target, name, visited))
+      }
+    }
+  }
+
+  fn resolve_local(
+    &self,
+    index: usize,
+    local: &str,
+    visited: &mut HashSet<(usize, String)>,
+  ) -> Option<Binding> {
+    let BundleModule::Inline(inline) = &self.modules[index] else {
+      return None;
+    };
+    if inline.locals.contains(local) {
+      return Some(Binding::Local(index, local.to_string()));
+    }
+    match inline.imports.get(local)? {
+      (target, Imported::Name(imported)) => {
+        self.resolve_export(*target, imported, visited)
+      }
+      (target, Imported::Namespace) => Some(self.namespace_binding(*target)),
+    }
+  }
+
+  fn namespace_binding(&self, index: usize) -> Binding {
+    match &self.modules[index] {
+      BundleModule::Inline(_) => Binding::Namespace(index),
+      BundleModule::Ambient(_) | BundleModule::External(_) => {
+        Binding::External(index, Imported::Namespace)
+      }
+    }
+  }
+
+  /// The name of the binding in the bundle, where the hint is used as the
+  /// name of imports and namespaces which don't have a name yet.
+  fn binding_name(&mut self, binding: Binding, hint: &str) -> String {
+    match binding {
+      Binding::Local(index, local) => self.local_names[&(index, local)].clone(),
+      Binding::External(index, imported) => {
+        if let Some(name) = self.external_names.get(&(index, imported.clone()))
+        {
+          return name.clone();
+        }
+        let hint = match &imported {
+          Imported::Name(name) if name != "default" => name.as_str(),
+          _ => hint,
+        };
+        let name = self.unique_name(hint);
+        self.external_names.insert((index, imported), name.clone());
+        name
+      }
+      Binding::Namespace(index) => {
+        if let Some(name) = self.namespace_names.get(&index) {
+          return name.clone();
+        }
+        let name = self.unique_name(hint);
+        self.namespace_names.insert(index, name.clone());
+        name
+      }
+    }
+  }
+
+  fn unique_name(&mut self, name: &str) -> String {
+    let name = if name == "default" { "_default" } else { name };
+    let mut unique_name = name.to_string();
+    let mut count = 1;
+    while self.used_names.contains(&unique_name) {
+      unique_name = format!("{name}${count}");
+      count += 1;
+    }
+    self.used_names.insert(unique_name.clone());
+    unique_name
+  }
+
+  fn module_specifier(&self, index: usize) -> &ModuleSpecifier {
+    match &self.modules[index] {
+      BundleModule::Inline(inline) => &inline.specifier,
+      BundleModule::Ambient(ambient) => &ambient.specifier,
+      BundleModule::External(specifier) => specifier,
+    }
+  }
+}
+
+/// Keeps the comments of an export, like its JSDoc, when only the
+/// declaration within it is kept.
+fn move_leading_comments(
+  comments: &SingleThreadedComments,
+  from: BytePos,
+  to: BytePos,
+) {
+  if let Some(leading_comments) = comments.take_leading(from) {
+    comments.add_leading_comments(to, leading_comments);
+  }
+}
+
+fn emit_program(
+  specifier: &ModuleSpecifier,
+  source: &str,
+  program: &ast::Program,
+  comments: &SingleThreadedComments,
+) -> Result<String> {
+  let emitted = deno_ast::emit(
+    program,
+    comments,
+    &SourceMap::single(specifier.clone(), source.to_string()),
+    &EmitOptions {
+      source_map: SourceMapOption::None,
+      ..Default::default()
+    },
+  )?;
+  Ok(String::from_utf8(emitted.source)?)
+}
+
+fn export_name(name: &ast::ModuleExportName) -> String {
+  match name {
+    ast::ModuleExportName::Ident(ident) => ident.sym.to_string(),
+    ast::ModuleExportName::Str(value) => value.value.to_string(),
+  }
+}
+
+fn declared_names(decl: &ast::Decl) -> Vec<String> {
+  match decl {
+    ast::Decl::Class(class) => vec![class.ident.sym.to_string()],
+    ast::Decl::Fn(function) => vec![function.ident.sym.to_string()],
+    ast::Decl::Var(var) => var
+      .decls
+      .iter()
+      .filter_map(|decl| decl.name.as_ident())
+      .map(|ident| ident.sym.to_string())
+      .collect(),
+    ast::Decl::TsInterface(interface) => vec![interface.id.sym.to_string()],
+    ast::Decl::TsTypeAlias(alias) => vec![alias.id.sym.to_string()],
+    ast::Decl::TsEnum(ts_enum) => vec![ts_enum.id.sym.to_string()],
+    ast::Decl::TsModule(module) => match &module.id {
+      ast::TsModuleName::Ident(ident) => vec![ident.sym.to_string()],
+      ast::TsModuleName::Str(_) => Vec::new(),
+    },
+    ast::Decl::Using(_) => Vec::new(),
+  }
+}
+
+struct StripDeclare;
+
+impl VisitMut for StripDeclare {
+  fn visit_mut_decl(&mut self, decl: &mut ast::Decl) {
+    // the declarations are already ambient within a `declare module` block
+    match decl {
+      ast::Decl::Class(class) => class.declare = false,
+      ast::Decl::Fn(function) => function.declare = false,
+      ast::Decl::Var(var) => var.declare = false,
+      ast::Decl::TsInterface(interface) => interface.declare = false,
+      ast::Decl::TsTypeAlias(alias) => alias.declare = false,
+      ast::Decl::TsEnum(ts_enum) => ts_enum.declare = false,
+      ast::Decl::TsModule(module) => module.declare = false,
+      ast::Decl::Using(_) => {}
+    }
+  }
+}
+
+struct CollectGlobals<'a> {
+  unresolved_ctxt: SyntaxContext,
+  names: &'a mut HashSet<String>,
+}
+
+impl Visit for CollectGlobals<'_> {
+  fn visit_ident(&mut self, ident: &ast::Ident) {
+    if ident.ctxt == self.unresolved_ctxt {
+      self.names.insert(ident.sym.to_string());
+    }
+  }
+}
+
+struct CollectImportTypes(Vec<String>);
+
+impl Visit for CollectImportTypes {
+  fn visit_ts_import_type(&mut self, node: &ast::TsImportType) {
+    node.visit_children_with(self);
+    self.0.push(node.arg.value.to_string());
+  }
+}
+
+/// Collects the import types with the first name of their qualifier.
+struct CollectImportTypeTargets(Vec<(String, Option<String>)>);
+
+impl Visit for CollectImportTypeTargets {
+  fn visit_ts_import_type(&mut self, node: &ast::TsImportType) {
+    node.visit_children_with(self);
+    self.0.push((
+      node.arg.value.to_string(),
+      node.qualifier.as_ref().map(|qualifier| {
+        entity_name_parts(qualifier).first().unwrap().to_string()
+      }),
+    ));
+  }
+}
+
+fn entity_name_parts(name: &ast::TsEntityName) -> Vec<&str> {
+  match name {
+    ast::TsEntityName::Ident(ident) => vec![&ident.sym],
+    ast::TsEntityName::TsQualifiedName(qualified) => {
+      let mut parts = entity_name_parts(&qualified.left);
+      parts.push(&qualified.right.sym);
+      parts
+    }
+  }
+}
+
+/// Renames the references to top level declarations and imports to their
+/// names in the bundle, and replaces import types with references.
+struct Renamer<'a> {
+  top_level_ctxt: SyntaxContext,
+  names: &'a HashMap<String, String>,
+  import_types: &'a HashMap<(String, Option<String>), String>,
+  maybe_missing: Option<String>,
+}
+
+impl VisitMut for Renamer<'_> {
+  fn visit_mut_ident(&mut self, ident: &mut ast::Ident) {
+    if ident.ctxt != self.top_level_ctxt {
+      return;
+    }
+    match self.names.get(&*ident.sym) {
+      Some(name) => ident.sym = name.as_str().into(),
+      None => {
+        self
+          .maybe_missing
+          .get_or_insert_with(|| ident.sym.to_string());
+      }
+    }
+  }
+
+  fn visit_mut_ts_type(&mut self, node: &mut ast::TsType) {
+    node.visit_mut_children_with(self);
+    let ast::TsType::TsImportType(import_type) = node else {
+      return;
+    };
+    let parts = import_type
+      .qualifier
+      .as_ref()
+      .map(entity_name_parts)
+      .unwrap_or_default();
+    let key = (
+      import_type.arg.value.to_string(),
+      parts.first().map(|part| part.to_string()),
+    );
+    let Some(name) = self.import_types.get(&key) else {
+      return;
+    };
+    let mut type_name = ast::TsEntityName::Ident(ast::Ident::new(
+      name.as_str().into(),
+      DUMMY_SP,
+      SyntaxContext::empty(),
+    ));
+    for part in parts.iter().skip(1) {
+      type_name =
+        ast::TsEntityName::TsQualifiedName(Box::new(ast::TsQualifiedName {
+          span: DUMMY_SP,
+          left: type_name,
+          right: ast::IdentName::new((*part).into(), DUMMY_SP),
+        }));
+    }
+    *node = ast::TsType::TsTypeRef(ast::TsTypeRef {
+      span: import_type.span,
+      type_name,
+      type_params: import_type.type_args.take(),
+    });
+  }
+}
@@ -0,0 +1,638 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::bail;
+use anyhow::Result;
+use base64::Engine;
+use deno_ast::swc::ast;
+use deno_ast::swc::common::SyntaxContext;
+use deno_ast::swc::common::DUMMY_SP;
+use deno_ast::swc::visit::VisitMut;
+use deno_ast::swc::visit::VisitMutWith;
+use deno_ast::EmitOptions;
+use deno_ast::EmittedSourceBytes;
+use deno_ast::ModuleSpecifier;
+use deno_ast::ParseParams;
+use deno_ast::SourceMapOption;
+use deno_graph::JsModule;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use std::collections::BTreeMap;
+
+use crate::bundle_hook::hot_prop;
+use crate::bundle_hook::import_meta_props;
+use crate::bundle_hook::meta_param;
+use crate::bundle_hook::META_PARAM;
+use crate::cache::options_hash;
+use crate::cache::source_hash;
+use crate::cache::CachedEmit;
+use crate::cache::EmitCache;
+use crate::cache::EmitCacheKey;
+use crate::commonjs::const_decl;
+use crate::commonjs::ensure_no_top_level_await;
+use crate::commonjs::esm_to_commonjs;
+use crate::emit::shebang_file;
+use crate::text::strip_bom;
+use crate::transpile::transpile_with;
+use crate::transpile::ProgramTransforms;
+use crate::BundleEmit;
+use crate::BundleMode;
+use crate::BundleOptions;
+use crate::BundleType;
+
+/// The runtime which is included at the top of development bundles.
+const RUNTIME: &str = include_str!("dev_bundle_runtime.js");
+
+/// The name `import.meta` is replaced with in the module factories.
+const IMPORT_META: &str = "__deno_import_meta";
+
+/// Emits a development bundle of the graph, in which every module is
+/// transpiled on its own and wrapped in a factory function instead of being
+/// hoisted into the scope of the bundle. The factories are kept in the
+/// registry of the runtime, which evaluates the modules when they're first
+/// imported, and they can be replaced by the updates of [`emit_hmr_update`]
+/// without reloading the page.
+///
+/// Every factory is evaluated as its own script with a `//# sourceURL` of
+/// the module and an inline source map, so the bundle itself has no source
+/// map. The transpiled modules are cached in the emit cache when one is
+/// given.
+pub(crate) fn emit_dev_bundle(
+  graph: &ModuleGraph,
+  options: &BundleOptions,
+  maybe_cache: Option<&dyn EmitCache>,
+) -> Result<BundleEmit> {
+  if options.minify {
+    bail!("Development bundles can't be minified.");
+  }
+  let mut code = String::new();
+  if let Some(shebang) = shebang_file(graph) {
+    code.push_str(&format!("{shebang}\n"));
+  }
+  let externals = graph
+    .modules()
+    .filter(|module| {
+      matches!(
+        module,
+        Module::Npm(_) | Module::Node(_) | Module::External(_)
+      )
+    })
+    .map(|module| module.specifier())
+    .collect::<Vec<_>>();
+  for (i, specifier) in externals.iter().enumerate() {
+    if matches!(options.bundle_type, BundleType::Classic) {
+      bail!(
+        "External module \"{specifier}\" can't be imported by a classic development bundle."
+      );
+    }
+    code.push_str(&format!(
+      "import * as __deno_external{i} from {};\n",
+      quote(specifier.as_str())
+    ));
+  }
+  code.push_str(RUNTIME);
+  for (i, specifier) in externals.iter().enumerate() {
+    code.push_str(&format!(
+      "__deno_emit_dev.external({}, __deno_external{i});\n",
+      quote(specifier.as_str())
+    ));
+  }
+  for module in graph.modules() {
+    if let Some(factory) = emit_factory(graph, module, options, maybe_cache)? {
+      code.push_str(&format!(
+        "__deno_emit_dev.define({});\n",
+        factory.to_args()?
+      ));
+    }
+  }
+  let root = quote(graph.resolve(&graph.roots[0]).as_str());
+  code.push_str(&match options.bundle_type {
+    BundleType::Module => {
+      format!("__deno_emit_dev.start({root}, import.meta.main);\n")
+    }
+    BundleType::Classic => format!("__deno_emit_dev.start({root});\n"),
+  });
+  Ok(BundleEmit {
+    code,
+    maybe_map: None,
+    maybe_declarations: None,
+    cache_stats: Default::default(),
+  })
+}
+
+/// Emits an update for the runtime of a development bundle of the graph,
+/// which contains the factories of the given modules. When it's evaluated,
+/// the modules which were already evaluated are replaced along with their
+/// importers, up to the modules which accept the update.
+pub(crate) fn emit_hmr_update(
+  graph: &ModuleGraph,
+  specifiers: &[ModuleSpecifier],
+  options: &BundleOptions,
+  maybe_cache: Option<&dyn EmitCache>,
+) -> Result<String> {
+  let mut code = "__deno_emit_dev.update([\n".to_string();
+  for specifier in specifiers {
+    let Some(module) = graph.get(specifier) else {
+      continue;
+    };
+    if let Some(factory) = emit_factory(graph, module, options, maybe_cache)? {
+      code.push_str(&format!("[{}],\n", factory.to_args()?));
+    }
+  }
+  code.push_str("]);\n");
+  Ok(code)
+}
+
+/// A module of a development bundle.
+struct Factory {
+  id: String,
+  /// The specifiers of the imports of the module, mapped to the ids of the
+  /// modules they resolve to.
+  dependencies: BTreeMap<String, String>,
+  code: String,
+  maybe_map: Option<Vec<u8>>,
+}
+
+impl Factory {
+  /// The arguments the module is defined with in the runtime. The factory is
+  /// evaluated on its own with an indirect `eval()`, so that it's a separate
+  /// script for debuggers, named after the module, and only sees the globals
+  /// like the module would.
+  fn to_args(&self) -> Result<String> {
+    let mut source = format!(
+      "(function (require, exports, {META_PARAM}) {{\n{}",
+      self.code
+    );
+    if !source.ends_with('\n') {
+      source.push('\n');
+    }
+    source.push_str("})\n//# sourceURL=");
+    source.push_str(&self.id);
+    if let Some(map) = &self.maybe_map {
+      // the mappings start at the line after the one the function starts on
+      let mut map: serde_json::Value = serde_json::from_slice(map)?;
+      if let Some(serde_json::Value::String(mappings)) = map.get_mut("mappings")
+      {
+        mappings.insert(0, ';');
+      }
+      source.push('\n');
+      push_inline_source_map(&mut source, &map.to_string());
+    }
+    Ok(format!(
+      "{}, {}, (0, eval)({})",
+      quote(&self.id),
+      serde_json::to_string(&self.dependencies)?,
+      quote(&source)
+    ))
+  }
+}
+
+fn emit_factory(
+  graph: &ModuleGraph,
+  module: &Module,
+  options: &BundleOptions,
+  maybe_cache: Option<&dyn EmitCache>,
+) -> Result<Option<Factory>> {
+  match module {
+    Module::Js(module) if module.media_type.is_declaration() => Ok(None),
+    Module::Js(module) => {
+      let emitted = transpile_factory(module, options, maybe_cache)?;
+      let dependencies = module
+        .dependencies
+        .iter()
+        .filter_map(|(specifier, dependency)| {
+          let resolved = graph.resolve(dependency.get_code()?);
+          Some((specifier.clone(), resolved.to_string()))
+        })
+        .collect();
+      Ok(Some(Factory {
+        id: module.specifier.to_string(),
+        dependencies,
+        code: String::from_utf8(emitted.source)?,
+        maybe_map: emitted.source_map,
+      }))
+    }
+    Module::Json(module) => Ok(Some(Factory {
+      id: module.specifier.to_string(),
+      dependencies: BTreeMap::new(),
+      code: format!(
+        "\"use strict\";\nObject.defineProperty(exports, \"__esModule\", {{ value: true }});\nexports.default = {};\n",
+        strip_bom(&module.source).trim()
+      ),
+      maybe_map: None,
+    })),
+    Module::Npm(_) | Module::Node(_) | Module::External(_) => Ok(None),
+  }
+}
+
+/// Transpiles the module to the body of its factory, in which the imports
+/// and exports are converted like CommonJS modules and `import.meta` is
+/// replaced with an object whose `main` flag and HMR API are passed to the
+/// factory by the runtime.
+///
+/// The imports are required before the rest of the module runs, in the order
+/// they're declared, and dynamic imports only require their modules when
+/// they're evaluated, which keeps the evaluation order of ES modules.
+fn transpile_factory(
+  module: &JsModule,
+  options: &BundleOptions,
+  maybe_cache: Option<&dyn EmitCache>,
+) -> Result<EmittedSourceBytes> {
+  let emit_options = EmitOptions {
+    source_map: match options.emit_options.source_map {
+      SourceMapOption::None => SourceMapOption::None,
+      SourceMapOption::Inline | SourceMapOption::Separate => {
+        SourceMapOption::Separate
+      }
+    },
+    ..options.emit_options.clone()
+  };
+  let instrument_coverage = options
+    .instrument_coverage
+    .as_ref()
+    .is_some_and(|filter| filter.includes(&module.specifier));
+  let key = EmitCacheKey {
+    specifier: module.specifier.clone(),
+    source_hash: source_hash(&module.source),
+    options_hash: options_hash(&[
+      &"dev_bundle",
+      &options.transpile_options,
+      &emit_options,
+      &options.mode,
+      &options.react_refresh,
+      &instrument_coverage,
+    ]),
+  };
+  if let Some(cached) = maybe_cache.and_then(|cache| cache.get(&key)) {
+    return Ok(EmittedSourceBytes {
+      source: cached.code,
+      source_map: cached.maybe_map,
+    });
+  }
+
+  let parsed_source = deno_ast::parse_module(ParseParams {
+    specifier: module.specifier.clone(),
+    text: module.source.clone(),
+    media_type: module.media_type,
+    capture_tokens: false,
+    scope_analysis: false,
+    maybe_syntax: None,
+  })?;
+  ensure_no_top_level_await(&parsed_source)?;
+  let emitted = transpile_with(
+    &parsed_source,
+    &options.transpile_options,
+    &emit_options,
+    ProgramTransforms {
+      react_refresh: options.react_refresh,
+      instrument_coverage,
+    },
+    |program| {
+      if let ast::Program::Module(module) = program {
+        // the shebang of the entry is written at the top of the bundle
+        module.shebang = None;
+      }
+      let mut replacer = ImportMetaReplacer::default();
+      program.visit_mut_with(&mut replacer);
+      esm_to_commonjs(program)?;
+      if let (ast::Program::Module(program), true) = (program, replacer.found) {
+        let mut props = import_meta_props(
+          DUMMY_SP,
+          module.specifier.as_str(),
+          meta_param("main", DUMMY_SP),
+        );
+        if options.mode == BundleMode::DevHmr {
+          props.push(hot_prop(DUMMY_SP));
+        }
+        let import_meta = ast::Expr::Object(ast::ObjectLit {
+          span: DUMMY_SP,
+          props: props
+            .into_iter()
+            .map(|prop| {
+              ast::PropOrSpread::Prop(Box::new(ast::Prop::KeyValue(prop)))
+            })
+            .collect(),
+        });
+        // after the "use strict" directive
+        program.body.insert(
+          1,
+          ast::ModuleItem::Stmt(const_decl(
+            DUMMY_SP,
+            IMPORT_META,
+            Box::new(import_meta),
+          )),
+        );
+      }
+      Ok(())
+    },
+  )?;
+  if let Some(cache) = maybe_cache {
+    cache.set(
+      &key,
+      &CachedEmit {
+        code: emitted.source.clone(),
+        maybe_map: emitted.source_map.clone(),
+      },
+    );
+  }
+  Ok(emitted)
+}
+
+/// Replaces `import.meta` with the object which is declared at the top of
+/// the module factory.
+#[derive(Default)]
+struct ImportMetaReplacer {
+  found: bool,
+}
+
+impl VisitMut for ImportMetaReplacer {
+  fn visit_mut_expr(&mut self, expr: &mut ast::Expr) {
+    match expr {
+      ast::Expr::MetaProp(ast::MetaPropExpr {
+        span,
+        kind: ast::MetaPropKind::ImportMeta,
+      }) => {
+        self.found = true;
+        *expr = ast::Expr::Ident(ast::Ident::new(
+          IMPORT_META.into(),
+          *span,
+          SyntaxContext::empty(),
+        ));
+      }
+      _ => expr.visit_mut_children_with(self),
+    }
+  }
+}
+
+fn push_inline_source_map(code: &mut String, map: &str) {
+  code.push_str("//# sourceMappingURL=data:application/json;base64,");
+  base64::prelude::BASE64_STANDARD.encode_string(map, code);
+}
+
+fn quote(text: &str) -> String {
+  serde_json::Value::String(text.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use deno_graph::BuildOptions;
+  use deno_graph::GraphKind;
+  use pretty_assertions::assert_eq;
+
+  use crate::MemoryEmitCache;
+
+  async fn setup(sources: Vec<(&str, &str)>) -> ModuleGraph {
+    let loader = MemoryLoader::new(
+      sources
+        .iter()
+        .map(|(specifier, content)| {
+          (
+            *specifier,
+            Source::Module {
+              specifier: *specifier,
+              maybe_headers: None,
+              content: *content,
+            },
+          )
+        })
+        .collect(),
+      vec![],
+    );
+    let mut graph = ModuleGraph::new(GraphKind::CodeOnly);
+    graph
+      .build(
+        vec![ModuleSpecifier::parse(sources[0].0).unwrap()],
+        &loader,
+        BuildOptions::default(),
+      )
+      .await;
+    graph.valid().unwrap();
+    graph
+  }
+
+  /// The sources of the factories which are evaluated in the code.
+  fn factory_sources(code: &str) -> Vec<String> {
+    code
+      .match_indices("(0, eval)(")
+      .map(|(i, prefix)| {
+        serde_json::Deserializer::from_str(&code[i + prefix.len()..])
+          .into_iter::<String>()
+          .next()
+          .unwrap()
+          .unwrap()
+      })
+      .collect()
+  }
+
+  fn inline_source_map(source: &str) -> serde_json::Value {
+    let (_, data) = source.split_once(";base64,").unwrap();
+    serde_json::from_slice(
+      &base64::prelude::BASE64_STANDARD.decode(data).unwrap(),
+    )
+    .unwrap()
+  }
+
+  fn options() -> BundleOptions {
+    BundleOptions {
+      emit_options: EmitOptions {
+        source_map: SourceMapOption::Separate,
+        ..Default::default()
+      },
+      mode: BundleMode::DevHmr,
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn dev_bundle() {
+    let graph = setup(vec![
+      (
+        "file:///a/main.ts",
+        r#"import { count } from "./counter.ts";
+import data from "./data.json" with { type: "json" };
+const label: string = data.label;
+console.log(label, count, import.meta.url);
+import.meta.hot?.accept();
+"#,
+      ),
+      ("file:///a/counter.ts", "export let count: number = 1;\n"),
+      ("file:///a/data.json", "{ \"label\": \"count\" }\n"),
+    ])
+    .await;
+    let cache = MemoryEmitCache::default();
+    let emit = emit_dev_bundle(&graph, &options(), Some(&cache)).unwrap();
+    let code = emit.code;
+    assert!(code.starts_with(RUNTIME), "{code}");
+    assert!(
+      code.contains(
+        r#"__deno_emit_dev.define("file:///a/counter.ts", {}, (0, eval)("(function (require, exports, __deno_meta) {\n"#
+      ),
+      "{code}"
+    );
+    assert!(
+      code.contains(
+        r#"__deno_emit_dev.define("file:///a/main.ts", {"./counter.ts":"file:///a/counter.ts","./data.json":"file:///a/data.json"}, (0, eval)("#
+      ),
+      "{code}"
+    );
+    assert!(code.ends_with(
+      "__deno_emit_dev.start(\"file:///a/main.ts\", import.meta.main);\n"
+    ));
+    assert_eq!(emit.maybe_map, None);
+    let sources = factory_sources(&code);
+    assert_eq!(sources.len(), 3);
+    let main = &sources[2];
+    assert!(main.contains("const _counter = (require(\"./counter.ts\"));"));
+    assert!(
+      main.contains(
+        "console.log(label, _counter.count, __deno_import_meta.url);"
+      ),
+      "{main}"
+    );
+    assert!(main.contains("url: \"file:///a/main.ts\",\n  main: __deno_meta.main,\n  hot: __deno_meta.hot"), "{main}");
+    assert!(main.contains("\n})\n//# sourceURL=file:///a/main.ts\n//# sourceMappingURL=data:application/json;base64,"), "{main}");
+    let map = inline_source_map(main);
+    assert_eq!(map["sources"][0], "file:///a/main.ts");
+    assert!(map["mappings"].as_str().unwrap().starts_with(';'), "{map}");
+    assert!(sources[1].contains("exports.default = { \"label\": \"count\" };"));
+    assert!(sources[1].ends_with("})\n//# sourceURL=file:///a/data.json"));
+    assert_eq!(cache.stats().misses, 2);
+
+    let update = emit_hmr_update(
+      &graph,
+      &[ModuleSpecifier::parse("file:///a/counter.ts").unwrap()],
+      &options(),
+      Some(&cache),
+    )
+    .unwrap();
+    assert!(
+      update.starts_with(
+        r#"__deno_emit_dev.update([
+["file:///a/counter.ts", {}, (0, eval)("(function (require, exports, __deno_meta) {\n"#
+      ),
+      "{update}"
+    );
+    assert!(update.ends_with("],\n]);\n"), "{update}");
+    assert!(!update.contains("main.ts"), "{update}");
+    assert_eq!(factory_sources(&update).len(), 1);
+    assert_eq!(cache.stats().misses, 2);
+  }
+
+  #[tokio::test]
+  async fn dev_bundle_classic() {
+    let graph = setup(vec![
+      (
+        "file:///a/main.ts",
+        "import { b } from \"./b.ts\";\nconsole.log(b, import.meta.main);\nimport(\"./c.ts\");\n",
+      ),
+      ("file:///a/b.ts", "export const b: string = \"b\";\n"),
+      ("file:///a/c.ts", "export const c: string = \"c\";\n"),
+    ])
+    .await;
+    let options = BundleOptions {
+      bundle_type: BundleType::Classic,
+      emit_options: EmitOptions {
+        source_map: SourceMapOption::None,
+        ..Default::default()
+      },
+      mode: BundleMode::Dev,
+      ..options()
+    };
+    let code = emit_dev_bundle(&graph, &options, None).unwrap().code;
+    assert!(code.ends_with("__deno_emit_dev.start(\"file:///a/main.ts\");\n"));
+    let sources = factory_sources(&code);
+    assert_eq!(
+      sources[2],
+      r#"(function (require, exports, __deno_meta) {
+"use strict";
+const __deno_import_meta = {
+  url: "file:///a/main.ts",
+  main: __deno_meta.main
+};
+Object.defineProperty(exports, "__esModule", {
+  value: true
+});
+function _interop_require_wildcard(obj) {
+  if (obj && obj.__esModule) return obj;
+  var newObj = {
+    default: obj
+  };
+  if (obj != null && (typeof obj === "object" || typeof obj === "function")) {
+    for(var key in obj){
+      if (key !== "default" && Object.prototype.hasOwnProperty.call(obj, key)) newObj[key] = obj[key];
+    }
+  }
+  return newObj;
+}
+const _b = (require("./b.ts"));
+console.log(_b.b, __deno_import_meta.main);
+Promise.resolve().then(()=>_interop_require_wildcard(require("./c.ts")));
+})
+//# sourceURL=file:///a/main.ts"#
+    );
+
+    let options = BundleOptions {
+      minify: true,
+      ..options
+    };
+    let err = emit_dev_bundle(&graph, &options, None).err().unwrap();
+    assert_eq!(err.to_string(), "Development bundles can't be minified.");
+  }
+
+  #[tokio::test]
+  async fn dev_bundle_react_refresh() {
+    let graph = setup(vec![
+      (
+        "file:///a/main.tsx",
+        "import { App } from \"./app.tsx\";\nconsole.log(<App />);\n",
+      ),
+      (
+        "file:///a/app.tsx",
+        "export function App() {\n  return <div />;\n}\n",
+      ),
+    ])
+    .await;
+    let options = BundleOptions {
+      emit_options: EmitOptions {
+        source_map: SourceMapOption::None,
+        ..Default::default()
+      },
+      react_refresh: true,
+      ..options()
+    };
+    let code = emit_dev_bundle(&graph, &options, None).unwrap().code;
+    let sources = factory_sources(&code);
+    assert!(
+      sources[0].contains("_c = App;\nvar _c;\n$RefreshReg$(_c, \"App\");\n"),
+      "{}",
+      sources[0]
+    );
+
+    let err = crate::bundle_graph(
+      &graph,
+      BundleOptions {
+        mode: BundleMode::Hoisted,
+        ..options
+      },
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+      err.to_string(),
+      "React Refresh is only supported by development bundles."
+    );
+  }
+
+  #[tokio::test]
+  async fn dev_bundle_top_level_await() {
+    let graph =
+      setup(vec![("file:///a/main.ts", "await Promise.resolve();\n")]).await;
+    let err = emit_dev_bundle(&graph, &options(), None).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Top-level await is not supported in development bundles.\n    at file:///a/main.ts:1:1"
+    );
+  }
+}
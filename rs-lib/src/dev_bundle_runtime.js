@@ -0,0 +1,260 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+// The runtime of development bundles, which keeps the modules of the bundle
+// in a registry of factories, evaluates them when they're first imported and
+// replaces them when an update is applied. Components are refreshed with
+// React Refresh when the page registered its runtime.
+globalThis.__deno_emit_dev ??= (() => {
+  /** The factories and resolved dependencies of the modules by id. */
+  const definitions = new Map();
+  /** The exports of the modules which were evaluated. */
+  const instances = new Map();
+  /** The errors the modules which failed to evaluate threw. */
+  const errors = new Map();
+  /** The ids of the modules which imported a module. */
+  const importers = new Map();
+  /** The HMR state of the evaluated modules. */
+  const hotStates = new Map();
+  /** The data the dispose handlers of replaced modules passed on. */
+  const hotData = new Map();
+  /** The id of the entry module, when the bundle is the main module. */
+  let main;
+  /** The React Refresh runtime the page registered with `refresh()`. */
+  let refreshRuntime;
+
+  function define(id, dependencies, factory) {
+    definitions.set(id, { dependencies, factory });
+  }
+
+  function external(id, namespace) {
+    instances.set(id, namespace);
+  }
+
+  function load(id, importer) {
+    if (importer !== undefined) {
+      let ids = importers.get(id);
+      if (ids === undefined) {
+        ids = new Set();
+        importers.set(id, ids);
+      }
+      ids.add(importer);
+    }
+    // like ES modules, a module which failed is not evaluated again
+    if (errors.has(id)) {
+      throw errors.get(id);
+    }
+    if (instances.has(id)) {
+      return instances.get(id);
+    }
+    const definition = definitions.get(id);
+    if (definition === undefined) {
+      throw new Error(`Module "${id}" is not in the bundle.`);
+    }
+    // registered before the module runs, so circular imports get the exports
+    // which were defined so far
+    const exports = {};
+    instances.set(id, exports);
+    const require = (specifier) => load(resolve(id, specifier), id);
+    const meta = { main: id === main, hot: createHot(id) };
+    // the registrations of modules transpiled with React Refresh are scoped
+    // to the module which is evaluated
+    const { $RefreshReg$, $RefreshSig$ } = globalThis;
+    globalThis.$RefreshReg$ = (type, name) =>
+      refreshRuntime?.register(type, `${id} ${name}`);
+    globalThis.$RefreshSig$ = () =>
+      refreshRuntime?.createSignatureFunctionForTransform() ?? ((type) => type);
+    try {
+      definition.factory(require, exports, meta);
+    } catch (error) {
+      errors.set(id, error);
+      throw error;
+    } finally {
+      Object.assign(globalThis, { $RefreshReg$, $RefreshSig$ });
+    }
+    // modules which only export components are refreshed in place
+    if (refreshRuntime !== undefined && isRefreshBoundary(exports)) {
+      hotStates.get(id).selfAccepted = true;
+    }
+    return exports;
+  }
+
+  function isRefreshBoundary(exports) {
+    const values = Object.values(exports);
+    return values.length > 0 &&
+      values.every((value) => refreshRuntime.isLikelyComponentType(value));
+  }
+
+  function resolve(id, specifier) {
+    const resolved = definitions.get(id)?.dependencies[specifier];
+    if (resolved === undefined) {
+      throw new Error(`Cannot resolve "${specifier}" from "${id}".`);
+    }
+    return resolved;
+  }
+
+  function createHot(id) {
+    const state = {
+      selfAccepted: false,
+      selfCallbacks: [],
+      dependencyCallbacks: new Map(),
+      disposeCallbacks: [],
+      declined: false,
+    };
+    hotStates.set(id, state);
+    const data = hotData.get(id) ?? {};
+    hotData.delete(id);
+    return {
+      data,
+      accept(dependencies, callback) {
+        if (dependencies === undefined || typeof dependencies === "function") {
+          state.selfAccepted = true;
+          if (dependencies !== undefined) {
+            state.selfCallbacks.push(dependencies);
+          }
+          return;
+        }
+        const specifiers = Array.isArray(dependencies)
+          ? dependencies
+          : [dependencies];
+        const ids = specifiers.map((specifier) => resolve(id, specifier));
+        for (const dependency of ids) {
+          state.dependencyCallbacks.set(dependency, {
+            ids,
+            single: !Array.isArray(dependencies),
+            callback,
+          });
+        }
+      },
+      dispose(callback) {
+        state.disposeCallbacks.push(callback);
+      },
+      decline() {
+        state.declined = true;
+      },
+      invalidate() {
+        reload(`"${id}" was invalidated.`);
+      },
+    };
+  }
+
+  /**
+   * Marks the modules which have to be evaluated again when the module
+   * changes, up to the modules which accept the update. Returns `false` when
+   * the update reaches a module that can't be replaced.
+   */
+  function propagate(id, invalidated, boundaries) {
+    if (invalidated.has(id)) {
+      return true;
+    }
+    const state = hotStates.get(id);
+    if (state === undefined || state.declined) {
+      return false;
+    }
+    invalidated.add(id);
+    if (state.selfAccepted) {
+      boundaries.push({ id, self: true });
+      return true;
+    }
+    const ids = importers.get(id);
+    if (ids === undefined || ids.size === 0) {
+      return false;
+    }
+    for (const importer of ids) {
+      const accepted = hotStates.get(importer)?.dependencyCallbacks.get(id);
+      if (accepted !== undefined) {
+        boundaries.push({ id: importer, accepted });
+      } else if (!propagate(importer, invalidated, boundaries)) {
+        return false;
+      }
+    }
+    return true;
+  }
+
+  function dispose(id) {
+    const data = {};
+    for (const callback of hotStates.get(id)?.disposeCallbacks ?? []) {
+      callback(data);
+    }
+    hotData.set(id, data);
+    hotStates.delete(id);
+    instances.delete(id);
+    errors.delete(id);
+    // the module registers its imports again when it's evaluated
+    for (const ids of importers.values()) {
+      ids.delete(id);
+    }
+  }
+
+  /**
+   * Applies an update with the changed and added modules of a rebuild. The
+   * changed modules are evaluated again along with their importers, up to
+   * the modules which accept the update with `import.meta.hot.accept()`. The
+   * page is reloaded when the update reaches a module which doesn't accept
+   * it.
+   */
+  function update(modules) {
+    const invalidated = new Set();
+    const boundaries = [];
+    for (const [id] of modules) {
+      // modules which weren't evaluated yet pick up the update when they're
+      // imported
+      if (instances.has(id) && !propagate(id, invalidated, boundaries)) {
+        return reload(`"${id}" can't be updated.`);
+      }
+    }
+    const selfCallbacks = new Map();
+    for (const boundary of boundaries) {
+      if (boundary.self) {
+        selfCallbacks.set(boundary.id, hotStates.get(boundary.id).selfCallbacks);
+      }
+    }
+    for (const id of invalidated) {
+      dispose(id);
+    }
+    for (const [id, dependencies, factory] of modules) {
+      define(id, dependencies, factory);
+    }
+    for (const boundary of boundaries) {
+      try {
+        if (boundary.self) {
+          const exports = load(boundary.id);
+          for (const callback of selfCallbacks.get(boundary.id)) {
+            callback(exports);
+          }
+        } else {
+          const { ids, single, callback } = boundary.accepted;
+          const exports = ids.map((id) => load(id, boundary.id));
+          callback?.(single ? exports[0] : exports);
+        }
+      } catch (error) {
+        console.error(`[hmr] Failed updating "${boundary.id}".`, error);
+      }
+    }
+    refreshRuntime?.performReactRefresh();
+  }
+
+  function reload(reason) {
+    console.warn(`[hmr] ${reason} Reloading.`);
+    globalThis.location?.reload();
+  }
+
+  return {
+    define,
+    external,
+    update,
+    /**
+     * Registers the React Refresh runtime, the `react-refresh/runtime`
+     * module, before the components are evaluated and React is loaded.
+     */
+    refresh(runtime) {
+      runtime.injectIntoGlobalHook(globalThis);
+      refreshRuntime = runtime;
+    },
+    start(id, isMain = false) {
+      if (isMain) {
+        main = id;
+      }
+      return load(id);
+    },
+  };
+})();
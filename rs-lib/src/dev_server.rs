@@ -0,0 +1,623 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use deno_ast::EmitOptions;
+use deno_ast::MediaType;
+use deno_ast::ModuleSpecifier;
+use deno_ast::SourceMapOption;
+use deno_ast::TranspileOptions;
+use deno_graph::source::LoadFuture;
+use deno_graph::source::LoadOptions;
+use deno_graph::source::LoadResponse;
+use deno_graph::source::Loader;
+use deno_graph::CapturingModuleAnalyzer;
+use deno_graph::JsModule;
+use deno_graph::ParsedSourceStore;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::build_graph;
+use crate::get_import_map_from_input;
+use crate::output_paths::decode;
+use crate::transpile::transpile_module;
+use crate::ImportMapInput;
+use crate::ImportMapResolver;
+use crate::MemoryEmitCache;
+use crate::TranspileGraphOptions;
+
+/// How long a connection may take to send its request before it's closed.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Default)]
+pub struct DevServerOptions {
+  pub transpile_options: TranspileOptions,
+  /// The emit options of the transpiled modules. Unless source maps are
+  /// disabled, they're served separately at the path of the module with a
+  /// `.map` extension appended.
+  pub emit_options: EmitOptions,
+  pub maybe_import_map: Option<ImportMapInput>,
+  /// Register the components and hooks of the JSX and TSX modules with React
+  /// Refresh, like [`TranspileGraphOptions::react_refresh`]. The names they're
+  /// registered with are prefixed with the path the module is served at, like
+  /// `"/src/app.tsx App"`.
+  pub react_refresh: bool,
+}
+
+/// A development server which serves the files of a directory over HTTP.
+///
+/// JavaScript, TypeScript and JSX modules are transpiled when they're
+/// requested, and their imports are resolved with the import map and
+/// rewritten to the paths the modules are served at, so that they can be
+/// imported by a browser without bundling them. Other files are served as
+/// they are.
+pub struct DevServer {
+  listener: TcpListener,
+  root_dir: PathBuf,
+  root: ModuleSpecifier,
+  resolver: ImportMapResolver,
+  options: TranspileGraphOptions,
+}
+
+impl DevServer {
+  pub fn bind(
+    addr: impl ToSocketAddrs,
+    root_dir: &Path,
+    options: DevServerOptions,
+  ) -> Result<Self> {
+    let root_dir = std::fs::canonicalize(root_dir)
+      .with_context(|| format!("Could not find \"{}\".", root_dir.display()))?;
+    let root = ModuleSpecifier::from_directory_path(&root_dir)
+      .map_err(|()| anyhow!("Invalid path \"{}\".", root_dir.display()))?;
+    let resolver =
+      ImportMapResolver(get_import_map_from_input(options.maybe_import_map)?);
+    let mut emit_options = options.emit_options;
+    if emit_options.source_map != SourceMapOption::None {
+      emit_options.source_map = SourceMapOption::Separate;
+    }
+    let listener = TcpListener::bind(addr)?;
+    Ok(Self {
+      listener,
+      root_dir,
+      root,
+      resolver,
+      options: TranspileGraphOptions {
+        transpile_options: options.transpile_options,
+        emit_options,
+        // the source map is requested after the module, so it's reused
+        // from the cache as long as the module doesn't change
+        cache: Some(Arc::new(MemoryEmitCache::default())),
+        react_refresh: options.react_refresh,
+        ..Default::default()
+      },
+    })
+  }
+
+  pub fn local_addr(&self) -> Result<SocketAddr> {
+    Ok(self.listener.local_addr()?)
+  }
+
+  /// Serves requests until accepting a connection fails, blocking the
+  /// current thread. Every connection is handled on a thread of its own, and
+  /// closed after the response.
+  pub fn serve(&self) -> Result<()> {
+    std::thread::scope(|scope| {
+      for stream in self.listener.incoming() {
+        let stream = stream?;
+        scope.spawn(move || {
+          // the client may have gone away, which shouldn't stop the server
+          let _ = futures::executor::block_on(self.handle_connection(stream));
+        });
+      }
+      Ok(())
+    })
+  }
+
+  async fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+      let mut header = String::new();
+      if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+        break;
+      }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let response = match method {
+      "GET" | "HEAD" => self.respond(target).await,
+      _ => Response::text(405, "Method Not Allowed"),
+    };
+    response.write(&mut &stream, method == "HEAD")
+  }
+
+  async fn respond(&self, target: &str) -> Response {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let Some(path) = path.strip_prefix('/') else {
+      return Response::text(400, "Bad Request");
+    };
+    // encoded separators are only decoded when the URL is converted to a
+    // file path, so they're rejected before joining the path to the root
+    if path
+      .split('/')
+      .map(decode)
+      .any(|segment| segment == ".." || segment.contains(['/', '\\']))
+    {
+      return Response::text(403, "Forbidden");
+    }
+    let Ok(specifier) = self.root.join(path) else {
+      return Response::text(400, "Bad Request");
+    };
+    let Ok(file_path) = specifier.to_file_path() else {
+      return Response::text(400, "Bad Request");
+    };
+    if !self.is_served(&file_path) {
+      return Response::text(403, "Forbidden");
+    }
+    match self.serve_file(&specifier).await {
+      Ok(Some(response)) => response,
+      Ok(None) => Response::text(404, &format!("Not found: {target}")),
+      Err(err) => Response::text(500, &format!("{err:#}")),
+    }
+  }
+
+  /// Checks that the path is inside of the served directory once symlinks
+  /// are resolved. Paths which don't exist, like the source maps of
+  /// transpiled modules, are checked by their parent directory.
+  fn is_served(&self, path: &Path) -> bool {
+    let canonical_path = std::fs::canonicalize(path).or_else(|err| {
+      match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => {
+          std::fs::canonicalize(parent).map(|parent| parent.join(file_name))
+        }
+        _ => Err(err),
+      }
+    });
+    match canonical_path {
+      Ok(canonical_path) => canonical_path.starts_with(&self.root_dir),
+      // nothing is read from a directory which doesn't exist
+      Err(_) => true,
+    }
+  }
+
+  async fn serve_file(
+    &self,
+    specifier: &ModuleSpecifier,
+  ) -> Result<Option<Response>> {
+    let path = specifier
+      .to_file_path()
+      .map_err(|()| anyhow!("Invalid file URL \"{specifier}\"."))?;
+    if path.is_dir() {
+      if !specifier.path().ends_with('/') {
+        return Ok(Some(Response::redirect(&format!("{}/", specifier.path()))));
+      }
+      return Box::pin(self.serve_file(&specifier.join("index.html")?)).await;
+    }
+    if path.is_file() {
+      if is_transpiled(specifier) {
+        return self.serve_module(specifier).await.map(Some);
+      }
+      let content = std::fs::read(&path)
+        .with_context(|| format!("Failed reading \"{}\".", path.display()))?;
+      return Ok(Some(Response::ok(content_type(specifier), content)));
+    }
+    // the source maps of transpiled modules don't exist on disk
+    let Some(module_path) = specifier.as_str().strip_suffix(".map") else {
+      return Ok(None);
+    };
+    let module_specifier = ModuleSpecifier::parse(module_path)?;
+    if !is_transpiled(&module_specifier)
+      || !module_specifier
+        .to_file_path()
+        .is_ok_and(|path| path.is_file())
+    {
+      return Ok(None);
+    }
+    let emitted = self.transpile(&module_specifier).await?;
+    Ok(
+      emitted
+        .source_map
+        .map(|source_map| Response::ok("application/json", source_map)),
+    )
+  }
+
+  async fn serve_module(
+    &self,
+    specifier: &ModuleSpecifier,
+  ) -> Result<Response> {
+    let emitted = self.transpile(specifier).await?;
+    let mut code = emitted.source;
+    if emitted.source_map.is_some() {
+      let file_name = specifier.path_segments().unwrap().last().unwrap();
+      if !code.ends_with(b"\n") {
+        code.push(b'\n');
+      }
+      code.extend_from_slice(
+        format!("//# sourceMappingURL={file_name}.map\n").as_bytes(),
+      );
+    }
+    Ok(Response::ok("application/javascript; charset=utf-8", code))
+  }
+
+  /// Transpiles the module with the imports rewritten to the paths they're
+  /// served at. The dependencies aren't loaded, as they're transpiled when
+  /// the browser requests them.
+  async fn transpile(
+    &self,
+    specifier: &ModuleSpecifier,
+  ) -> Result<deno_ast::EmittedSourceBytes> {
+    let analyzer = CapturingModuleAnalyzer::default();
+    let mut loader = RootLoader(specifier.clone());
+    let graph = build_graph(
+      vec![specifier.clone()],
+      &mut loader,
+      self.resolver.as_resolver(),
+      &analyzer,
+      false,
+    )
+    .await;
+    graph.valid()?;
+    let Some(module) = graph.get(specifier).and_then(|module| module.js())
+    else {
+      bail!("\"{specifier}\" is not a JavaScript or TypeScript module.");
+    };
+    let specifier_map = self.specifier_map(module)?;
+    // the modules are registered with React Refresh by the paths they're
+    // served at
+    let module_id = specifier
+      .as_str()
+      .strip_prefix(self.root.as_str())
+      .map(|path| format!("/{path}"))
+      .unwrap_or_else(|| specifier.to_string());
+    transpile_module(
+      module,
+      analyzer.remove_parsed_source(specifier),
+      specifier_map,
+      &module_id,
+      &self.options,
+    )
+  }
+
+  /// Gets the map of the specifiers of the module to the paths of the local
+  /// modules they resolve to, or to the URLs of remote modules, which the
+  /// browser loads directly.
+  fn specifier_map(
+    &self,
+    module: &JsModule,
+  ) -> Result<BTreeMap<String, String>> {
+    let mut map = BTreeMap::new();
+    for (specifier, dependency) in &module.dependencies {
+      let Some(resolved) = dependency.get_code() else {
+        continue;
+      };
+      let new_specifier = match resolved.scheme() {
+        "file" => match resolved.as_str().strip_prefix(self.root.as_str()) {
+          Some(path) => format!("/{path}"),
+          None => bail!(
+            "\"{}\" imports \"{}\", which is outside of the served directory \"{}\".",
+            module.specifier,
+            resolved,
+            self.root
+          ),
+        },
+        "http" | "https" => resolved.to_string(),
+        _ => continue,
+      };
+      map.insert(specifier.clone(), new_specifier);
+    }
+    Ok(map)
+  }
+}
+
+/// Loads the root module from the file system, while its dependencies are
+/// left external.
+struct RootLoader(ModuleSpecifier);
+
+impl Loader for RootLoader {
+  fn load(
+    &self,
+    specifier: &ModuleSpecifier,
+    _options: LoadOptions,
+  ) -> LoadFuture {
+    let result = if *specifier == self.0 {
+      specifier
+        .to_file_path()
+        .map_err(|()| anyhow!("Invalid file URL \"{specifier}\"."))
+        .and_then(|path| {
+          std::fs::read(&path)
+            .with_context(|| format!("Failed reading \"{}\".", path.display()))
+        })
+        .map(|content| {
+          Some(LoadResponse::Module {
+            content: content.into(),
+            specifier: specifier.clone(),
+            maybe_headers: None,
+          })
+        })
+    } else {
+      Ok(Some(LoadResponse::External {
+        specifier: specifier.clone(),
+      }))
+    };
+    Box::pin(futures::future::ready(result))
+  }
+}
+
+fn is_transpiled(specifier: &ModuleSpecifier) -> bool {
+  matches!(
+    MediaType::from_specifier(specifier),
+    MediaType::JavaScript
+      | MediaType::Jsx
+      | MediaType::Mjs
+      | MediaType::TypeScript
+      | MediaType::Mts
+      | MediaType::Tsx
+  )
+}
+
+fn content_type(specifier: &ModuleSpecifier) -> &'static str {
+  let extension = specifier
+    .path()
+    .rsplit_once('.')
+    .map(|(_, extension)| extension.to_ascii_lowercase())
+    .unwrap_or_default();
+  match extension.as_str() {
+    "html" | "htm" => "text/html; charset=utf-8",
+    "css" => "text/css; charset=utf-8",
+    "json" | "map" => "application/json",
+    "txt" => "text/plain; charset=utf-8",
+    "svg" => "image/svg+xml",
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "webp" => "image/webp",
+    "ico" => "image/x-icon",
+    "wasm" => "application/wasm",
+    _ => "application/octet-stream",
+  }
+}
+
+struct Response {
+  status: u16,
+  content_type: &'static str,
+  maybe_location: Option<String>,
+  body: Vec<u8>,
+}
+
+impl Response {
+  fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+    Self {
+      status: 200,
+      content_type,
+      maybe_location: None,
+      body,
+    }
+  }
+
+  fn text(status: u16, text: &str) -> Self {
+    Self {
+      status,
+      content_type: "text/plain; charset=utf-8",
+      maybe_location: None,
+      body: text.as_bytes().to_vec(),
+    }
+  }
+
+  fn redirect(location: &str) -> Self {
+    Self {
+      maybe_location: Some(location.to_string()),
+      ..Self::text(301, "Moved Permanently")
+    }
+  }
+
+  fn write(&self, writer: &mut impl Write, head_only: bool) -> Result<()> {
+    let reason = match self.status {
+      200 => "OK",
+      301 => "Moved Permanently",
+      400 => "Bad Request",
+      403 => "Forbidden",
+      404 => "Not Found",
+      405 => "Method Not Allowed",
+      _ => "Internal Server Error",
+    };
+    let mut head = format!(
+      "HTTP/1.1 {} {reason}\r\ncontent-type: {}\r\ncontent-length: {}\r\ncache-control: no-cache\r\nconnection: close\r\n",
+      self.status,
+      self.content_type,
+      self.body.len()
+    );
+    if let Some(location) = &self.maybe_location {
+      head.push_str(&format!("location: {location}\r\n"));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes())?;
+    if !head_only {
+      writer.write_all(&self.body)?;
+    }
+    writer.flush()?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use std::io::Read;
+
+  fn get(addr: SocketAddr, path: &str) -> (u16, String, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nhost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head[9..12].parse().unwrap();
+    let content_type = head
+      .lines()
+      .find_map(|line| line.strip_prefix("content-type: "))
+      .unwrap()
+      .to_string();
+    (status, content_type, body.to_string())
+  }
+
+  #[test]
+  fn serve_transpiled_modules() {
+    let dir = std::env::temp_dir()
+      .join(format!("deno_emit_dev_server_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(
+      dir.join("index.html"),
+      "<script type=\"module\" src=\"/src/main.tsx\"></script>",
+    )
+    .unwrap();
+    std::fs::write(
+      dir.join("src/main.tsx"),
+      r#"import { greet } from "greet";
+import { VERSION } from "https://example.com/version.ts";
+const name: string = "world";
+export const app = <div>{greet(name)} {VERSION}</div>;
+"#,
+    )
+    .unwrap();
+    std::fs::write(
+      dir.join("src/greet.ts"),
+      "export function greet(name: string): string {\n  return `Hello ${name}`;\n}\n",
+    )
+    .unwrap();
+    let import_map = ImportMapInput {
+      base_url: ModuleSpecifier::from_directory_path(
+        std::fs::canonicalize(&dir).unwrap(),
+      )
+      .unwrap()
+      .join("import_map.json")
+      .unwrap(),
+      json_string: r#"{ "imports": { "greet": "./src/greet.ts" } }"#
+        .to_string(),
+    };
+    let server = DevServer::bind(
+      "127.0.0.1:0",
+      &dir,
+      DevServerOptions {
+        maybe_import_map: Some(import_map),
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.serve());
+
+    let (status, content_type, body) = get(addr, "/");
+    assert_eq!(status, 200);
+    assert_eq!(content_type, "text/html; charset=utf-8");
+    assert!(body.contains("/src/main.tsx"), "{body}");
+
+    let (status, content_type, body) = get(addr, "/src/main.tsx?v=1");
+    assert_eq!(status, 200);
+    assert_eq!(content_type, "application/javascript; charset=utf-8");
+    assert!(body.contains("from \"/src/greet.ts\";"), "{body}");
+    assert!(
+      body.contains("from \"https://example.com/version.ts\";"),
+      "{body}"
+    );
+    assert!(body.contains("React.createElement(\"div\""), "{body}");
+    assert!(!body.contains(": string"), "{body}");
+    assert!(
+      body.ends_with("//# sourceMappingURL=main.tsx.map\n"),
+      "{body}"
+    );
+
+    let (status, content_type, body) = get(addr, "/src/main.tsx.map");
+    assert_eq!(status, 200);
+    assert_eq!(content_type, "application/json");
+    assert!(body.contains("\"mappings\""), "{body}");
+
+    let (status, _, body) = get(addr, "/src/greet.ts");
+    assert_eq!(status, 200);
+    assert!(body.contains("return `Hello ${name}`;"), "{body}");
+
+    assert_eq!(get(addr, "/src").0, 301);
+    assert_eq!(get(addr, "/src/missing.ts").0, 404);
+    assert_eq!(get(addr, "/../outside.ts").0, 403);
+    let outside_file = std::env::temp_dir()
+      .join(format!("deno_emit_dev_server_{}.txt", std::process::id()));
+    std::fs::write(&outside_file, "secret").unwrap();
+    let outside_name = outside_file.file_name().unwrap().to_str().unwrap();
+    let (status, _, body) =
+      get(addr, &format!("/src/..%2F..%2F{outside_name}"));
+    assert_eq!(status, 403);
+    assert!(!body.contains("secret"), "{body}");
+    std::fs::remove_file(&outside_file).unwrap();
+    assert_eq!(get(addr, "/%2E%2E/index.html").0, 403);
+
+    // an idle connection doesn't keep other requests from being served
+    let _idle = TcpStream::connect(addr).unwrap();
+    assert_eq!(get(addr, "/src/greet.ts").0, 200);
+
+    std::fs::write(dir.join("src/greet.ts"), "export const greet = ;").unwrap();
+    let (status, _, body) = get(addr, "/src/greet.ts");
+    assert_eq!(status, 500);
+    assert!(body.contains("Expression expected"), "{body}");
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn serve_react_refresh() {
+    let dir = std::env::temp_dir().join(format!(
+      "deno_emit_dev_server_refresh_{}",
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("a")).unwrap();
+    std::fs::create_dir_all(dir.join("b")).unwrap();
+    for name in ["a", "b"] {
+      std::fs::write(
+        dir.join(name).join("app.tsx"),
+        "export const App = () => <div />;\n",
+      )
+      .unwrap();
+    }
+    let server = DevServer::bind(
+      "127.0.0.1:0",
+      &dir,
+      DevServerOptions {
+        react_refresh: true,
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.serve());
+
+    let (status, _, body) = get(addr, "/a/app.tsx");
+    assert_eq!(status, 200);
+    assert!(
+      body.contains("$RefreshReg$(_c, \"/a/app.tsx App\");"),
+      "{body}"
+    );
+    let (status, _, body) = get(addr, "/b/app.tsx");
+    assert_eq!(status, 200);
+    assert!(
+      body.contains("$RefreshReg$(_c, \"/b/app.tsx App\");"),
+      "{body}"
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -1,6 +1,7 @@
 // Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
 
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use base64::Engine;
@@ -20,10 +21,25 @@ use deno_ast::SourceMap;
 use deno_ast::SourceTextInfo;
 use deno_ast::TranspileOptions;
 use deno_graph::Module;
+use std::cell::Cell;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::bundle_hook::BundleHook;
+use crate::cache::options_hash;
+use crate::cache::source_hash;
+use crate::cache::CachedEmit;
+use crate::cache::CountingEmitCache;
+use crate::cache::EmitCache;
+use crate::cache::EmitCacheKey;
+use crate::cache::EmitCacheStats;
+use crate::cache::MemoryEmitCache;
+use crate::declaration_bundle::bundle_declarations;
+use crate::dev_bundle::emit_dev_bundle;
+use crate::dev_bundle::emit_hmr_update;
+use crate::filter::ModuleFilter;
 use crate::text::strip_bom;
 use crate::text::transform_json_source;
 
@@ -33,9 +49,11 @@ const IGNORE_DIRECTIVES: &[&str] = &[
   "// This code was bundled using `deno bundle` and it's not recommended to edit it manually",
 ];
 
+#[derive(Debug, Clone, Copy, Default)]
 pub enum BundleType {
   /// Return the emitted contents of the program as a single "flattened" ES
   /// module.
+  #[default]
   Module,
   /// Return the emitted contents of the program as a single script that
   /// executes the program using an immediately invoked function execution
@@ -52,24 +70,244 @@ impl From<BundleType> for swc::bundler::ModuleType {
   }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BundleMode {
+  /// Hoist the modules into one scope, renaming their bindings where they
+  /// conflict.
+  #[default]
+  Hoisted,
+  /// Emit a development bundle. Instead of hoisting the modules into one
+  /// scope and renaming their bindings, every module is transpiled on its
+  /// own and wrapped in a factory function, which keeps the original names.
+  /// The factories are evaluated as separate scripts with a `//# sourceURL`
+  /// of the module and an inline source map, so that debuggers show every
+  /// module as its own file. A runtime at the top of the bundle evaluates the
+  /// modules in the same order as ES modules, when they're first imported.
+  ///
+  /// The bundle doesn't export the exports of the root module, modules with
+  /// top-level await are not supported and it can't be minified. The source
+  /// maps are always inline, so [`deno_ast::SourceMapOption::Separate`] is
+  /// emitted like [`deno_ast::SourceMapOption::Inline`] and the bundle has
+  /// no [`BundleEmit::maybe_map`]. The factories are evaluated with
+  /// `eval()`, so the bundle doesn't run on pages whose content security
+  /// policy doesn't allow it.
+  Dev,
+  /// Emit a development bundle for hot module replacement, like
+  /// [`BundleMode::Dev`]. The modules can be replaced while the bundle runs
+  /// by evaluating the updates of [`hmr_update`], and they can accept updates
+  /// and clean up after themselves with `import.meta.hot.accept()` and
+  /// `import.meta.hot.dispose()`.
+  DevHmr,
+}
+
+impl BundleMode {
+  /// Whether the modules are kept in their own functions.
+  pub fn is_dev(self) -> bool {
+    matches!(self, Self::Dev | Self::DevHmr)
+  }
+}
+
+#[derive(Clone, Default)]
 pub struct BundleOptions {
   pub bundle_type: BundleType,
   pub transpile_options: TranspileOptions,
   pub emit_options: EmitOptions,
   pub emit_ignore_directives: bool,
   pub minify: bool,
+  /// A cache of previously transpiled modules. Modules whose source and
+  /// options are unchanged are read from the cache instead of being
+  /// transpiled again, and the source map of the bundle maps their code
+  /// back to their original source.
+  pub cache: Option<Arc<dyn EmitCache>>,
+  /// Also roll the declarations of the root module and the modules it
+  /// exports up into a single declaration file. The graph must have been
+  /// built with types and have had its fast check type graph built with
+  /// declarations for both local and remote modules, which
+  /// [`crate::bundle`] does when this is set.
+  pub declarations: bool,
+  /// How the modules are put together in the bundle.
+  pub mode: BundleMode,
+  /// Register the components and hooks of JSX and TSX modules with React
+  /// Refresh, like [`crate::TranspileGraphOptions::react_refresh`]. This is
+  /// only supported by development bundles, whose runtime passes the
+  /// registrations on to the React Refresh runtime the page registers with
+  /// `__deno_emit_dev.refresh()`, and which refresh the components when an
+  /// update of a module that only exports components is applied.
+  pub react_refresh: bool,
+  /// Instrument the modules the filter includes with coverage counters, like
+  /// [`crate::TranspileGraphOptions::instrument_coverage`], so that the
+  /// coverage of tests which run the bundle can be collected from
+  /// `globalThis.__coverage__`.
+  pub instrument_coverage: Option<ModuleFilter>,
 }
 
 #[derive(Debug)]
 pub struct BundleEmit {
   pub code: String,
   pub maybe_map: Option<String>,
+  /// The declaration bundle, when requested with
+  /// [`BundleOptions::declarations`].
+  pub maybe_declarations: Option<String>,
+  /// The lookups of the modules in [`BundleOptions::cache`].
+  pub cache_stats: EmitCacheStats,
+}
+
+/// The number of source files the source map of a [`BundleModuleCache`] may
+/// have on top of twice the number of cached modules before the cache starts
+/// over.
+const MAX_STALE_SOURCE_FILES: usize = 64;
+
+/// Transpiled modules which are reused by the bundles of a watcher while
+/// their sources don't change.
+///
+/// A transpiled module is only valid with the globals and the source map it
+/// was transpiled with, so every bundle that uses the cache is emitted with
+/// them.
+pub(crate) struct BundleModuleCache {
+  globals: swc::common::Globals,
+  cm: SourceMap,
+  modules: RefCell<HashMap<ModuleSpecifier, CachedModule>>,
+  transpiled: Cell<usize>,
+  /// The transpiled modules of development bundles.
+  pub emit_cache: MemoryEmitCache,
+}
+
+struct CachedModule {
+  source: Arc<str>,
+  media_type: MediaType,
+  source_file: Rc<swc::common::SourceFile>,
+  module: swc::ast::Module,
+  instrument_coverage: bool,
+  maybe_emitted_map: Option<Rc<sourcemap::SourceMap>>,
+}
+
+impl BundleModuleCache {
+  pub fn new() -> Self {
+    Self {
+      globals: swc::common::Globals::new(),
+      cm: SourceMap::default(),
+      modules: Default::default(),
+      transpiled: Default::default(),
+      emit_cache: Default::default(),
+    }
+  }
+
+  /// The number of modules that were transpiled because they weren't in the
+  /// cache, since it was created.
+  pub fn transpiled(&self) -> usize {
+    self.transpiled.get() + self.emit_cache.stats().misses
+  }
+
+  /// Drops the modules which are no longer in the graph.
+  ///
+  /// The source map keeps the source file of every module that was
+  /// transpiled with it, and the globals every mark, so once most of them
+  /// are stale, the cache starts over with new ones.
+  pub fn retain(&mut self, graph: &deno_graph::ModuleGraph) {
+    let modules = self.modules.get_mut();
+    modules.retain(|specifier, _| graph.get(specifier).is_some());
+    self
+      .emit_cache
+      .retain(|specifier| graph.get(specifier).is_some());
+    let source_files = self.cm.inner().files().len();
+    if source_files > 2 * modules.len() + MAX_STALE_SOURCE_FILES {
+      modules.clear();
+      self.globals = swc::common::Globals::new();
+      self.cm = SourceMap::default();
+    }
+  }
 }
 
+/// A transpiled module, along with the source map of its cached emit when it
+/// was parsed from the emit cache instead.
+type LoadedModule = (
+  Rc<swc::common::SourceFile>,
+  swc::ast::Module,
+  Option<Rc<sourcemap::SourceMap>>,
+);
+
 struct BundleLoader<'a> {
   cm: &'a SourceMap,
   transpile_options: &'a TranspileOptions,
   graph: &'a deno_graph::ModuleGraph,
+  maybe_instrument_coverage: Option<&'a ModuleFilter>,
+  maybe_module_cache: Option<&'a BundleModuleCache>,
+  maybe_emit_cache: Option<&'a dyn EmitCache>,
+  /// The source maps of the cached emits of the loaded modules, by
+  /// specifier.
+  emitted_maps: &'a RefCell<HashMap<String, Rc<sourcemap::SourceMap>>>,
+}
+
+impl BundleLoader<'_> {
+  /// Transpiles the module, or parses its emit from the emit cache when its
+  /// source and the options haven't changed.
+  fn load_module(
+    &self,
+    specifier: &ModuleSpecifier,
+    source: &str,
+    media_type: MediaType,
+    instrument_coverage: bool,
+  ) -> Result<LoadedModule> {
+    let Some(emit_cache) = self.maybe_emit_cache else {
+      let (fm, module) =
+        self.transpile(specifier, source, media_type, instrument_coverage)?;
+      return Ok((fm, module, None));
+    };
+    let key = EmitCacheKey {
+      specifier: specifier.clone(),
+      source_hash: source_hash(source),
+      options_hash: options_hash(&[
+        &"bundle_module",
+        self.transpile_options,
+        &instrument_coverage,
+      ]),
+    };
+    if let Some(cached) = emit_cache.get(&key) {
+      let (fm, module, ..) = parse_module(
+        specifier,
+        String::from_utf8(cached.code)?,
+        MediaType::JavaScript,
+        self.cm,
+      )?;
+      let maybe_map = cached
+        .maybe_map
+        .map(|map| sourcemap::SourceMap::from_slice(&map))
+        .transpose()?
+        .map(Rc::new);
+      return Ok((fm, module, maybe_map));
+    }
+    let (fm, module) =
+      self.transpile(specifier, source, media_type, instrument_coverage)?;
+    let (code, map) = emit_module(&module, self.cm)?;
+    emit_cache.set(
+      &key,
+      &CachedEmit {
+        code,
+        maybe_map: Some(map),
+      },
+    );
+    Ok((fm, module, None))
+  }
+
+  fn transpile(
+    &self,
+    specifier: &ModuleSpecifier,
+    source: &str,
+    media_type: MediaType,
+    instrument_coverage: bool,
+  ) -> Result<(Rc<swc::common::SourceFile>, swc::ast::Module)> {
+    if let Some(cache) = self.maybe_module_cache {
+      cache.transpiled.set(cache.transpiled.get() + 1);
+    }
+    transpile_module(
+      specifier,
+      source,
+      media_type,
+      self.transpile_options,
+      instrument_coverage,
+      self.cm,
+    )
+  }
 }
 
 impl swc::bundler::Load for BundleLoader<'_> {
@@ -95,13 +333,55 @@ impl swc::bundler::Load for BundleLoader<'_> {
             ));
           }
         };
-        let (fm, module) = transpile_module(
-          specifier,
-          source.as_ref(),
-          media_type,
-          self.transpile_options,
-          self.cm,
-        )?;
+        let instrument_coverage = media_type != MediaType::Json
+          && self
+            .maybe_instrument_coverage
+            .is_some_and(|filter| filter.includes(specifier));
+        let maybe_cached = self.maybe_module_cache.and_then(|cache| {
+          let modules = cache.modules.borrow();
+          let cached = modules.get(specifier)?;
+          (cached.media_type == media_type
+            && cached.source == *source
+            && cached.instrument_coverage == instrument_coverage)
+            .then(|| {
+              (
+                cached.source_file.clone(),
+                cached.module.clone(),
+                cached.maybe_emitted_map.clone(),
+              )
+            })
+        });
+        let (fm, module, maybe_emitted_map) = match maybe_cached {
+          Some(cached) => cached,
+          None => {
+            let loaded = self.load_module(
+              specifier,
+              source,
+              media_type,
+              instrument_coverage,
+            )?;
+            if let Some(cache) = self.maybe_module_cache {
+              cache.modules.borrow_mut().insert(
+                specifier.clone(),
+                CachedModule {
+                  source: source.clone(),
+                  media_type,
+                  source_file: loaded.0.clone(),
+                  module: loaded.1.clone(),
+                  instrument_coverage,
+                  maybe_emitted_map: loaded.2.clone(),
+                },
+              );
+            }
+            loaded
+          }
+        };
+        if let Some(map) = maybe_emitted_map {
+          self
+            .emitted_maps
+            .borrow_mut()
+            .insert(specifier.to_string(), map);
+        }
         Ok(swc::bundler::ModuleData {
           fm,
           module,
@@ -156,18 +436,91 @@ pub fn bundle_graph(
   graph: &deno_graph::ModuleGraph,
   options: BundleOptions,
 ) -> Result<BundleEmit> {
-  let globals = swc::common::Globals::new();
-  deno_ast::swc::common::GLOBALS.set(&globals, || {
+  bundle_graph_with_cache(graph, options, None)
+}
+
+/// Emits an update for a bundle of the graph with [`BundleMode::DevHmr`],
+/// which contains only the given modules, such as the modules which changed
+/// since the graph was bundled. The update is a script which replaces
+/// the modules when it's evaluated on the page of the bundle.
+pub fn hmr_update(
+  graph: &deno_graph::ModuleGraph,
+  specifiers: &[ModuleSpecifier],
+  options: &BundleOptions,
+) -> Result<String> {
+  emit_hmr_update(graph, specifiers, options, options.cache.as_deref())
+}
+
+/// Bundles the graph like [`bundle_graph`], reusing the transpiled modules of
+/// the module cache whose sources haven't changed.
+pub(crate) fn bundle_graph_with_cache(
+  graph: &deno_graph::ModuleGraph,
+  options: BundleOptions,
+  maybe_module_cache: Option<&BundleModuleCache>,
+) -> Result<BundleEmit> {
+  let maybe_declarations = if options.declarations {
+    Some(bundle_declarations(graph)?)
+  } else {
+    None
+  };
+  let bundle_emit = emit_bundle(graph, options, maybe_module_cache)?;
+  Ok(BundleEmit {
+    maybe_declarations,
+    ..bundle_emit
+  })
+}
+
+fn emit_bundle(
+  graph: &deno_graph::ModuleGraph,
+  options: BundleOptions,
+  maybe_module_cache: Option<&BundleModuleCache>,
+) -> Result<BundleEmit> {
+  if options.react_refresh && !options.mode.is_dev() {
+    bail!("React Refresh is only supported by development bundles.");
+  }
+  if options.mode.is_dev() {
+    let maybe_cache = maybe_module_cache
+      .map(|cache| &cache.emit_cache as &dyn EmitCache)
+      .or(options.cache.as_deref())
+      .map(CountingEmitCache::new);
+    let bundle_emit = emit_dev_bundle(
+      graph,
+      &options,
+      maybe_cache.as_ref().map(|cache| cache as &dyn EmitCache),
+    )?;
+    return Ok(BundleEmit {
+      cache_stats: maybe_cache.map(|cache| cache.stats()).unwrap_or_default(),
+      ..bundle_emit
+    });
+  }
+  let maybe_emit_cache = options.cache.as_deref().map(CountingEmitCache::new);
+  let emitted_maps = RefCell::new(HashMap::new());
+  let owned_globals;
+  let owned_cm;
+  let (globals, cm) = match maybe_module_cache {
+    Some(module_cache) => (&module_cache.globals, &module_cache.cm),
+    None => {
+      owned_globals = swc::common::Globals::new();
+      owned_cm = SourceMap::default();
+      (&owned_globals, &owned_cm)
+    }
+  };
+  deno_ast::swc::common::GLOBALS.set(globals, || {
     let source_map_config = deno_ast::SourceMapConfig {
       inline_sources: options.emit_options.inline_sources,
       maybe_base: None,
     };
 
-    let cm = SourceMap::default();
     let loader = BundleLoader {
       graph,
       transpile_options: &options.transpile_options,
-      cm: &cm,
+      maybe_instrument_coverage: options.instrument_coverage.as_ref(),
+      cm,
+      maybe_module_cache,
+      maybe_emit_cache: maybe_emit_cache
+        .as_ref()
+        .map(|cache| cache as &dyn EmitCache),
+      emitted_maps: &emitted_maps,
     };
     let resolver = BundleResolver(graph);
     let config = swc::bundler::Config {
@@ -187,7 +540,7 @@ pub fn bundle_graph(
     // behavior between bundled and unbundled code.
     let hook = Box::new(BundleHook);
     let mut bundler = swc::bundler::Bundler::new(
-      &globals,
+      globals,
       cm.inner().clone(),
       loader,
       resolver,
@@ -245,9 +598,17 @@ pub fn bundle_graph(
     let mut maybe_map: Option<String> = None;
     {
       let mut buf = Vec::new();
-      cm.inner()
-        .build_source_map_with_config(&srcmap, None, source_map_config)
-        .to_writer(&mut buf)?;
+      let source_map = cm.inner().build_source_map_with_config(
+        &srcmap,
+        None,
+        source_map_config,
+      );
+      remap_emitted_sources(
+        &source_map,
+        &emitted_maps.borrow(),
+        options.emit_options.inline_sources,
+      )
+      .to_writer(&mut buf)?;
       match options.emit_options.source_map {
         deno_ast::SourceMapOption::Inline => {
           code.push_str("//# sourceMappingURL=data:application/json;base64,");
@@ -260,11 +621,18 @@ pub fn bundle_graph(
       }
     }
 
-    Ok(BundleEmit { code, maybe_map })
+    Ok(BundleEmit {
+      code,
+      maybe_map,
+      maybe_declarations: None,
+      cache_stats: maybe_emit_cache
+        .map(|cache| cache.stats())
+        .unwrap_or_default(),
+    })
   })
 }
 
-fn shebang_file(graph: &deno_graph::ModuleGraph) -> Option<String> {
+pub(crate) fn shebang_file(graph: &deno_graph::ModuleGraph) -> Option<String> {
   let module = graph.get(graph.roots.first()?)?.js()?;
   let source = &module.source;
   let first_line = source.lines().next()?;
@@ -275,12 +643,14 @@ fn shebang_file(graph: &deno_graph::ModuleGraph) -> Option<String> {
   }
 }
 
-/// Transpiles a source module into an swc SourceFile.
+/// Transpiles a source module into an swc SourceFile, and instruments it with
+/// coverage counters when `instrument_coverage` is set.
 fn transpile_module(
   specifier: &ModuleSpecifier,
   source: &str,
   media_type: MediaType,
   options: &deno_ast::TranspileOptions,
+  instrument_coverage: bool,
   cm: &SourceMap,
 ) -> Result<(Rc<swc::common::SourceFile>, swc::ast::Module)> {
   let source = strip_bom(source);
@@ -289,15 +659,59 @@ fn transpile_module(
   } else {
     source.to_string()
   };
+  let syntax_media_type = if media_type == MediaType::Json {
+    MediaType::JavaScript
+  } else {
+    media_type
+  };
+  let (source_file, module, comments, diagnostics) =
+    parse_module(specifier, source, syntax_media_type, cm)?;
+
+  let marks = Marks {
+    top_level: Mark::fresh(Mark::root()),
+    unresolved: Mark::new(),
+  };
+  let mut program = deno_ast::fold_program(
+    swc::ast::Program::Module(module),
+    options,
+    cm,
+    &comments,
+    &marks,
+    &diagnostics,
+  )?;
+  if instrument_coverage {
+    crate::coverage::instrument_coverage(&mut program, specifier, cm.inner())?;
+  }
+  let module = match program {
+    swc::ast::Program::Module(module) => module,
+    _ => unreachable!(),
+  };
+
+  Ok((source_file, module))
+}
+
+/// Parses a module into an swc SourceFile, along with its comments and the
+/// diagnostics the parser recovered from.
+fn parse_module(
+  specifier: &ModuleSpecifier,
+  source: String,
+  media_type: MediaType,
+  cm: &SourceMap,
+) -> Result<(
+  Rc<swc::common::SourceFile>,
+  swc::ast::Module,
+  SingleThreadedComments,
+  Vec<ParseDiagnostic>,
+)> {
   let source_file = cm.new_source_file(specifier.clone(), source);
   let input = StringInput::from(&*source_file);
   let comments = SingleThreadedComments::default();
-  let syntax = if media_type == MediaType::Json {
-    get_syntax(MediaType::JavaScript)
-  } else {
-    get_syntax(media_type)
-  };
-  let lexer = Lexer::new(syntax, deno_ast::ES_VERSION, input, Some(&comments));
+  let lexer = Lexer::new(
+    get_syntax(media_type),
+    deno_ast::ES_VERSION,
+    input,
+    Some(&comments),
+  );
   let mut parser = swc::parser::Parser::new_from(lexer);
   let module = parser.parse_module().map_err(|e| {
     ParseDiagnostic::from_swc_error(
@@ -318,25 +732,99 @@ fn transpile_module(
         .collect::<Vec<_>>()
     }
   };
+  Ok((source_file, module, comments, diagnostics))
+}
 
-  let marks = Marks {
-    top_level: Mark::fresh(Mark::root()),
-    unresolved: Mark::new(),
-  };
-  let program = deno_ast::fold_program(
-    swc::ast::Program::Module(module),
-    options,
-    cm,
-    &comments,
-    &marks,
-    &diagnostics,
-  )?;
-  let module = match program {
-    swc::ast::Program::Module(module) => module,
-    _ => unreachable!(),
-  };
+/// Emits a transpiled module for the emit cache, along with a source map to
+/// its original source.
+fn emit_module(
+  module: &swc::ast::Module,
+  cm: &SourceMap,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+  let mut code = Vec::new();
+  let mut mappings = Vec::new();
+  {
+    let mut cfg = swc::codegen::Config::default();
+    cfg.ascii_only = false;
+    cfg.target = deno_ast::ES_VERSION;
+    cfg.omit_last_semi = false;
+    cfg.emit_assert_for_import_attributes = false;
+    let mut emitter = swc::codegen::Emitter {
+      cfg,
+      cm: cm.inner().clone(),
+      comments: None,
+      wr: Box::new(swc::codegen::text_writer::JsWriter::new(
+        cm.inner().clone(),
+        "\n",
+        &mut code,
+        Some(&mut mappings),
+      )),
+    };
+    emitter
+      .emit_module(module)
+      .context("Unable to emit during bundling.")?;
+  }
+  let mut map = Vec::new();
+  cm.inner()
+    .build_source_map_with_config(
+      &mappings,
+      None,
+      deno_ast::SourceMapConfig {
+        inline_sources: true,
+        maybe_base: None,
+      },
+    )
+    .to_writer(&mut map)?;
+  Ok((code, map))
+}
 
-  Ok((source_file, module))
+/// Maps the code of the modules which were parsed from the emit cache back to
+/// their original sources, as the bundle was emitted from their cached
+/// JavaScript.
+fn remap_emitted_sources(
+  source_map: &sourcemap::SourceMap,
+  emitted_maps: &HashMap<String, Rc<sourcemap::SourceMap>>,
+  inline_sources: bool,
+) -> sourcemap::SourceMap {
+  if emitted_maps.is_empty() {
+    return source_map.clone();
+  }
+  let mut builder = sourcemap::SourceMapBuilder::new(None);
+  for token in source_map.tokens() {
+    let maybe_emitted_map = token
+      .get_source()
+      .and_then(|source| emitted_maps.get(source));
+    let (original, maybe_contents) = match maybe_emitted_map {
+      Some(emitted_map) => {
+        let Some(original) =
+          emitted_map.lookup_token(token.get_src_line(), token.get_src_col())
+        else {
+          continue;
+        };
+        (
+          original,
+          emitted_map.get_source_contents(original.get_src_id()),
+        )
+      }
+      None => (token, source_map.get_source_contents(token.get_src_id())),
+    };
+    let raw = builder.add(
+      token.get_dst_line(),
+      token.get_dst_col(),
+      original.get_src_line(),
+      original.get_src_col(),
+      original.get_source(),
+      original.get_name(),
+      false,
+    );
+    if inline_sources
+      && original.get_source().is_some()
+      && !builder.has_source_contents(raw.src_id)
+    {
+      builder.set_source_contents(raw.src_id, maybe_contents);
+    }
+  }
+  builder.into_sourcemap()
 }
 
 #[cfg(test)]
@@ -350,8 +838,15 @@ mod test {
   use deno_graph::ModuleGraph;
   use pretty_assertions::assert_eq;
 
+  use std::sync::Arc;
+
+  use super::bundle_graph_with_cache;
+  use super::BundleModuleCache;
+  use super::MAX_STALE_SOURCE_FILES;
   use crate::bundle_graph;
   use crate::BundleOptions;
+  use crate::EmitCacheStats;
+  use crate::FsEmitCache;
 
   async fn setup<S: AsRef<str> + Copy>(
     root: S,
@@ -402,17 +897,7 @@ export const b = "b";
       ),
     ];
     let graph = setup("file:///a/test01.ts", sources).await.0;
-    let output = bundle_graph(
-      &graph,
-      BundleOptions {
-        bundle_type: crate::BundleType::Module,
-        emit_ignore_directives: false,
-        emit_options: Default::default(),
-        transpile_options: Default::default(),
-        minify: false,
-      },
-    )
-    .unwrap();
+    let output = bundle_graph(&graph, BundleOptions::default()).unwrap();
 
     assert_eq!(
       r#"import "https://example.com/external.ts";
@@ -425,11 +910,8 @@ export { b as b };
     let minified_output = bundle_graph(
       &graph,
       BundleOptions {
-        bundle_type: crate::BundleType::Module,
-        emit_ignore_directives: false,
-        emit_options: Default::default(),
-        transpile_options: Default::default(),
         minify: true,
+        ..Default::default()
       },
     )
     .unwrap();
@@ -457,17 +939,299 @@ export { b as b };
     };
     let graph = setup(root, vec![(root, module)]).await.0;
 
+    let output = bundle_graph(&graph, BundleOptions::default()).unwrap();
+    assert_eq!(&output.code[..input.len()], input);
+  }
+
+  #[tokio::test]
+  async fn bundle_cache() {
+    let root = "file:///a/main.ts";
+    let sources = |value: &'static str| {
+      vec![
+        (
+          root,
+          Source::Module {
+            specifier: root,
+            maybe_headers: None,
+            content: "import { value } from \"./value.ts\";\nconst label: string = \"value\";\nconsole.log(label, value);\n",
+          },
+        ),
+        (
+          "file:///a/value.ts",
+          Source::Module {
+            specifier: "file:///a/value.ts",
+            maybe_headers: None,
+            content: value,
+          },
+        ),
+      ]
+    };
+    let dir = std::env::temp_dir()
+      .join(format!("deno_emit_bundle_cache_{}", std::process::id()));
+    let cache = Arc::new(FsEmitCache::new(&dir));
+    let options = || BundleOptions {
+      cache: Some(cache.clone()),
+      emit_options: deno_ast::EmitOptions {
+        source_map: deno_ast::SourceMapOption::Separate,
+        inline_sources: true,
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+
+    let graph = setup(root, sources("export const value: number = 1;\n"))
+      .await
+      .0;
+    let output = bundle_graph(&graph, options()).unwrap();
+    assert_eq!(output.cache_stats, EmitCacheStats { hits: 0, misses: 2 });
+
+    // only the changed module is transpiled again
+    let graph = setup(root, sources("export const value: number = 2;\n"))
+      .await
+      .0;
+    let output = bundle_graph(&graph, options()).unwrap();
+    assert_eq!(output.cache_stats, EmitCacheStats { hits: 1, misses: 1 });
+    assert_eq!(cache.stats(), EmitCacheStats { hits: 1, misses: 3 });
+    let expected = bundle_graph(
+      &graph,
+      BundleOptions {
+        cache: None,
+        ..options()
+      },
+    )
+    .unwrap();
+    assert_eq!(expected.cache_stats, EmitCacheStats::default());
+    assert_eq!(output.code, expected.code);
+    // the cached module is mapped back to its original source
+    assert_eq!(output.maybe_map, expected.maybe_map);
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn bundle_module_cache_retain() {
+    let root = "file:///a/main.ts";
+    fn sources(content: &str) -> Vec<(&str, Source<&str>)> {
+      vec![
+        (
+          "file:///a/main.ts",
+          Source::Module {
+            specifier: "file:///a/main.ts",
+            maybe_headers: None,
+            content,
+          },
+        ),
+        (
+          "file:///a/value.ts",
+          Source::Module {
+            specifier: "file:///a/value.ts",
+            maybe_headers: None,
+            content: "export const value = 1;\n",
+          },
+        ),
+      ]
+    }
+    let options = || BundleOptions {
+      emit_options: deno_ast::EmitOptions {
+        source_map: deno_ast::SourceMapOption::None,
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let mut module_cache = BundleModuleCache::new();
+
+    let content =
+      "import { value } from \"./value.ts\";\nconsole.log(value);\n";
+    let graph = setup(root, sources(content)).await.0;
+    bundle_graph_with_cache(&graph, options(), Some(&module_cache)).unwrap();
+    module_cache.retain(&graph);
+    assert_eq!(module_cache.modules.borrow().len(), 2);
+
+    // the module which is no longer imported is dropped
+    for i in 0..100 {
+      let content = format!("console.log({i});\n");
+      let graph = setup(root, sources(&content)).await.0;
+      let output =
+        bundle_graph_with_cache(&graph, options(), Some(&module_cache))
+          .unwrap();
+      assert_eq!(output.code, format!("console.log({i});\n"));
+      module_cache.retain(&graph);
+      assert!(module_cache.modules.borrow().len() <= 1);
+      // the source files of the previous versions are dropped eventually
+      assert!(
+        module_cache.cm.inner().files().len() <= 2 + MAX_STALE_SOURCE_FILES
+      );
+    }
+    assert_eq!(module_cache.transpiled(), 102);
+  }
+
+  #[tokio::test]
+  async fn bundle_declarations() {
+    let mut loader = MemoryLoader::new(
+      vec![
+        (
+          "file:///a/mod.ts",
+          Source::Module {
+            specifier: "file:///a/mod.ts",
+            maybe_headers: None,
+            content: r#"import type { B } from "./b.ts";
+import * as util from "./util.ts";
+// @ts-types="./d.d.ts"
+import { d } from "./d.js";
+export { c } from "https://example.com/c.ts";
+export * from "./b.ts";
+export { d };
+
+/** The options. */
+export interface Options {
+  b: B;
+  kind: util.Kind;
+}
+
+export function create(): Options {
+  return { b: { options: { b: 1 } }, kind: "a" };
+}
+"#,
+          },
+        ),
+        (
+          "file:///a/b.ts",
+          Source::Module {
+            specifier: "file:///a/b.ts",
+            maybe_headers: None,
+            content: r#"export interface Options {
+  b: number;
+}
+export interface B {
+  options: Options;
+}
+"#,
+          },
+        ),
+        (
+          "file:///a/util.ts",
+          Source::Module {
+            specifier: "file:///a/util.ts",
+            maybe_headers: None,
+            content: r#"export type Kind = "a" | "b";"#,
+          },
+        ),
+        (
+          "file:///a/d.js",
+          Source::Module {
+            specifier: "file:///a/d.js",
+            maybe_headers: None,
+            content: "export function d() { return 1; }",
+          },
+        ),
+        (
+          "file:///a/d.d.ts",
+          Source::Module {
+            specifier: "file:///a/d.d.ts",
+            maybe_headers: None,
+            content: "export declare function d(): number;",
+          },
+        ),
+        (
+          "https://example.com/c.ts",
+          Source::Module {
+            specifier: "https://example.com/c.ts",
+            maybe_headers: None,
+            content: "export const c: string = \"c\";",
+          },
+        ),
+      ],
+      vec![],
+    );
+    let output = crate::bundle(
+      ModuleSpecifier::parse("file:///a/mod.ts").unwrap(),
+      &mut loader,
+      None,
+      BundleOptions {
+        declarations: true,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      output.maybe_declarations.unwrap(),
+      r#"declare const c: string;
+declare function d(): number;
+type Kind = "a" | "b";
+interface Options$1 {
+  b: number;
+}
+interface B {
+  options: Options$1;
+}
+/** The options. */ interface Options {
+  b: B;
+  kind: util.Kind;
+}
+declare function create(): Options;
+import Kind$1 = Kind;
+declare namespace util {
+  export import Kind = Kind$1;
+}
+export { c, d, Options, create, B };
+"#
+    );
+  }
+
+  #[tokio::test]
+  async fn bundle_instrument_coverage() {
+    let sources = vec![
+      (
+        "file:///a/main.ts",
+        Source::Module {
+          specifier: "file:///a/main.ts",
+          maybe_headers: None,
+          content: r#"import { add } from "./add.ts";
+import { c } from "https://example.com/c.ts";
+export const result: number = add(c, 2);
+"#,
+        },
+      ),
+      (
+        "file:///a/add.ts",
+        Source::Module {
+          specifier: "file:///a/add.ts",
+          maybe_headers: None,
+          content:
+            "export function add(a: number, b: number) {\n  return a + b;\n}\n",
+        },
+      ),
+      (
+        "https://example.com/c.ts",
+        Source::Module {
+          specifier: "https://example.com/c.ts",
+          maybe_headers: None,
+          content: "export const c: number = 1;\n",
+        },
+      ),
+    ];
+    let graph = setup("file:///a/main.ts", sources).await.0;
     let output = bundle_graph(
       &graph,
       BundleOptions {
-        bundle_type: crate::BundleType::Module,
-        emit_ignore_directives: false,
-        emit_options: Default::default(),
-        transpile_options: Default::default(),
-        minify: false,
+        instrument_coverage: Some(crate::ModuleFilter::LocalOnly),
+        ..Default::default()
       },
     )
     .unwrap();
-    assert_eq!(&output.code[..input.len()], input);
+    let code = output.code.split_once("//# sourceMappingURL").unwrap().0;
+    assert!(code.contains("var path = \"/a/add.ts\";"));
+    assert!(code.contains("var path = \"/a/main.ts\";"));
+    // remote modules are excluded by the filter
+    assert!(!code.contains("var path = \"https://example.com/c.ts\";"));
+    assert_eq!(
+      code.rsplit_once("cov_10c598f1();\n").unwrap().1,
+      r#"cov_10c598f1().s[0]++;
+const result = add(1, 2);
+export { result as result };
+"#
+    );
   }
 }
@@ -0,0 +1,144 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use deno_ast::ModuleSpecifier;
+use std::sync::Arc;
+
+/// Which modules of a graph are transpiled and returned. Modules that are
+/// filtered out are still resolved and validated as part of the graph.
+#[derive(Clone, Default)]
+pub enum ModuleFilter {
+  #[default]
+  All,
+  /// Only local `file:` modules.
+  LocalOnly,
+  /// Only modules which are not local `file:` modules.
+  RemoteOnly,
+  /// Skip the modules whose specifier matches any of the globs, where `*`
+  /// matches any characters except `/`, `**` matches any characters and `?`
+  /// matches a single character except `/`. For example,
+  /// `https://deno.land/std*/**` or `**/*_test.ts`.
+  SkipGlobs(Vec<String>),
+  /// Only the modules for which the predicate returns `true`.
+  Predicate(Arc<dyn Fn(&ModuleSpecifier) -> bool + Send + Sync>),
+}
+
+impl ModuleFilter {
+  pub fn includes(&self, specifier: &ModuleSpecifier) -> bool {
+    match self {
+      Self::All => true,
+      Self::LocalOnly => specifier.scheme() == "file",
+      Self::RemoteOnly => specifier.scheme() != "file",
+      Self::SkipGlobs(globs) => !globs.iter().any(|glob| {
+        glob_matches(glob.as_bytes(), specifier.as_str().as_bytes())
+      }),
+      Self::Predicate(predicate) => predicate(specifier),
+    }
+  }
+}
+
+/// Matches the text iteratively, so that patterns with many wildcards don't
+/// take exponential time. On a mismatch, the last `*` of the current segment
+/// consumes one more character, or else the last `**` does.
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+  // the positions in the pattern after the last `**` and `*`, and the
+  // positions in the text where they resume matching
+  let mut maybe_any_star: Option<(usize, usize)> = None;
+  let mut maybe_segment_star: Option<(usize, usize)> = None;
+  let mut pattern_index = 0;
+  let mut text_index = 0;
+  while text_index < text.len() {
+    let c = text[text_index];
+    match pattern.get(pattern_index) {
+      Some(b'*') if pattern.get(pattern_index + 1) == Some(&b'*') => {
+        pattern_index += 2;
+        maybe_any_star = Some((pattern_index, text_index));
+        maybe_segment_star = None;
+        continue;
+      }
+      Some(b'*') => {
+        pattern_index += 1;
+        maybe_segment_star = Some((pattern_index, text_index));
+        continue;
+      }
+      Some(b'?') if c != b'/' => {
+        pattern_index += 1;
+        text_index += 1;
+        continue;
+      }
+      Some(pattern_c) if *pattern_c != b'?' && *pattern_c == c => {
+        pattern_index += 1;
+        text_index += 1;
+        continue;
+      }
+      _ => {}
+    }
+    match (maybe_segment_star, maybe_any_star) {
+      (Some((star_pattern_index, star_text_index)), _)
+        if text[star_text_index] != b'/' =>
+      {
+        maybe_segment_star = Some((star_pattern_index, star_text_index + 1));
+        pattern_index = star_pattern_index;
+        text_index = star_text_index + 1;
+      }
+      (_, Some((star_pattern_index, star_text_index))) => {
+        maybe_any_star = Some((star_pattern_index, star_text_index + 1));
+        maybe_segment_star = None;
+        pattern_index = star_pattern_index;
+        text_index = star_text_index + 1;
+      }
+      _ => return false,
+    }
+  }
+  pattern[pattern_index..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_glob_matches() {
+    let run = |pattern: &str, text: &str| {
+      glob_matches(pattern.as_bytes(), text.as_bytes())
+    };
+    assert!(run("**/*_test.ts", "file:///a/b/mod_test.ts"));
+    assert!(!run("**/*_test.ts", "file:///a/b/mod.ts"));
+    assert!(run(
+      "https://deno.land/std*/**",
+      "https://deno.land/std@0.200.0/path/mod.ts"
+    ));
+    assert!(!run(
+      "https://deno.land/std*/**",
+      "https://deno.land/x/mod.ts"
+    ));
+    assert!(run("file:///a/*.ts", "file:///a/mod.ts"));
+    assert!(!run("file:///a/*.ts", "file:///a/b/mod.ts"));
+    assert!(run("file:///a/mod.?s", "file:///a/mod.js"));
+    assert!(!run("file:///a/?", "file:///a/"));
+    assert!(run("**a*b/**/c", "xa/yab/z/c"));
+    assert!(!run("*a*b/c", "xa/yab/c"));
+    // backtracking over every wildcard would take exponential time
+    let text = format!("file:///{}", "a".repeat(100));
+    assert!(!run(&format!("file:///{}b", "**a".repeat(20)), &text));
+    assert!(!run(&format!("file:///{}b", "*a".repeat(20)), &text));
+  }
+
+  #[test]
+  fn test_module_filter() {
+    let local = ModuleSpecifier::parse("file:///a/mod.ts").unwrap();
+    let remote = ModuleSpecifier::parse("https://deno.land/mod.ts").unwrap();
+    assert!(ModuleFilter::All.includes(&remote));
+    assert!(ModuleFilter::LocalOnly.includes(&local));
+    assert!(!ModuleFilter::LocalOnly.includes(&remote));
+    assert!(!ModuleFilter::RemoteOnly.includes(&local));
+    assert!(ModuleFilter::RemoteOnly.includes(&remote));
+    let filter = ModuleFilter::SkipGlobs(vec!["https://**".to_string()]);
+    assert!(filter.includes(&local));
+    assert!(!filter.includes(&remote));
+    let filter = ModuleFilter::Predicate(Arc::new(|specifier| {
+      specifier.path().starts_with("/a/")
+    }));
+    assert!(filter.includes(&local));
+    assert!(!filter.includes(&remote));
+  }
+}
@@ -4,29 +4,91 @@
 #![deny(clippy::print_stdout)]
 
 mod bundle_hook;
+mod cache;
+mod commonjs;
+mod config;
+mod coverage;
+mod declaration_bundle;
+mod dev_bundle;
+#[cfg(feature = "dev_server")]
+mod dev_server;
 mod emit;
+mod filter;
+mod npm_package;
+mod output_paths;
+mod paths;
+mod rewrite;
+mod sloppy_imports;
 mod text;
+mod transpile;
+mod vendor;
+mod visit;
+mod watch;
+mod workspace;
 
+use anyhow::bail;
 use anyhow::Result;
 use deno_graph::source::ResolveError;
+use deno_graph::BuildFastCheckTypeGraphOptions;
 use deno_graph::BuildOptions;
 use deno_graph::CapturingModuleAnalyzer;
 use deno_graph::GraphKind;
 use deno_graph::ModuleGraph;
+use deno_graph::ModuleParser;
 use deno_graph::ParsedSourceStore;
 use deno_graph::Range;
+use deno_graph::WorkspaceFastCheckOption;
+use deno_graph::WorkspaceMember;
 use import_map::ImportMap;
 use import_map::ImportMapOptions;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use url::Url;
 
+use crate::npm_package::NpmMappingResolver;
+use crate::paths::PathsResolver;
+use crate::workspace::WorkspaceResolver;
+
+pub use cache::CachedEmit;
+pub use cache::EmitCache;
+pub use cache::EmitCacheKey;
+pub use cache::EmitCacheStats;
+pub use cache::FsEmitCache;
+pub use cache::MemoryEmitCache;
+pub use commonjs::ModuleFormat;
+pub use config::CompilerOptions;
+pub use config::ConfigFile;
+#[cfg(feature = "dev_server")]
+pub use dev_server::DevServer;
+#[cfg(feature = "dev_server")]
+pub use dev_server::DevServerOptions;
 pub use emit::bundle_graph;
+pub use emit::hmr_update;
 pub use emit::BundleEmit;
+pub use emit::BundleMode;
 pub use emit::BundleOptions;
 pub use emit::BundleType;
+pub use filter::ModuleFilter;
+pub use npm_package::npm_package_graph;
+pub use npm_package::NpmMapping;
+pub use npm_package::NpmPackage;
+pub use npm_package::NpmPackageOptions;
+pub use sloppy_imports::SloppyImportsLoader;
+pub use transpile::transpile_graph;
+pub use transpile::TranspileGraphOptions;
+pub use transpile::TranspileOutput;
+pub use transpile::TranspiledDeclaration;
+pub use transpile::TranspiledModule;
+pub use vendor::vendor_graph;
+pub use vendor::VendorOutput;
+pub use watch::WatchEvent;
+pub use watch::WatchOutput;
+pub use watch::WatchTarget;
+pub use watch::Watcher;
+pub use workspace::Workspace;
 
 pub use deno_ast::EmitOptions;
 pub use deno_ast::ImportsNotUsedAsValues;
+pub use deno_ast::MediaType;
 pub use deno_ast::ModuleSpecifier;
 pub use deno_ast::SourceMapOption;
 pub use deno_ast::TranspileOptions;
@@ -44,39 +106,186 @@ pub async fn bundle(
 ) -> Result<BundleEmit> {
   let maybe_import_map = get_import_map_from_input(maybe_import_map)?;
   let import_map_resolver = ImportMapResolver(maybe_import_map);
-  let mut graph = ModuleGraph::new(GraphKind::CodeOnly);
+  bundle_with_resolver(root, loader, import_map_resolver.as_resolver(), options)
+    .await
+}
+
+/// Bundles the root module of a workspace, resolving its imports with the
+/// import maps of the workspace, the names of its members and the `paths` of
+/// its root compiler options.
+pub async fn bundle_workspace(
+  root: ModuleSpecifier,
+  loader: &mut dyn Loader,
+  workspace: &Workspace,
+  options: BundleOptions,
+) -> Result<BundleEmit> {
+  let workspace_resolver = WorkspaceResolver::new(workspace)?;
+  let resolver = PathsResolver::new(
+    &workspace_resolver,
+    &workspace.root.compiler_options,
+    workspace.root.maybe_paths_base.as_ref(),
+  )?;
+  let mut loader = resolver.loader(loader);
+  bundle_with_resolver(root, &mut loader, &resolver, options).await
+}
+
+async fn bundle_with_resolver(
+  root: ModuleSpecifier,
+  loader: &mut dyn Loader,
+  resolver: &dyn deno_graph::source::Resolver,
+  options: BundleOptions,
+) -> Result<BundleEmit> {
+  let analyzer = CapturingModuleAnalyzer::default();
+  let mut graph = build_graph(
+    vec![root],
+    loader,
+    resolver,
+    &analyzer,
+    options.declarations,
+  )
+  .await;
+  bundle_built_graph(&mut graph, &analyzer, resolver, options, None)
+}
+
+/// Bundles a graph built by [`build_graph`].
+fn bundle_built_graph(
+  graph: &mut ModuleGraph,
+  analyzer: &CapturingModuleAnalyzer,
+  resolver: &dyn deno_graph::source::Resolver,
+  options: BundleOptions,
+  maybe_module_cache: Option<&emit::BundleModuleCache>,
+) -> Result<BundleEmit> {
+  if options.declarations {
+    graph.valid()?;
+    let roots = graph.roots.iter().cloned().collect::<Vec<_>>();
+    build_declaration_graph(graph, &roots, analyzer, resolver, true)?;
+  }
+
+  emit::bundle_graph_with_cache(graph, options, maybe_module_cache)
+}
+
+/// Transpiles the modules of the graph of one or more roots. Modules that are
+/// shared between the roots are only transpiled once.
+pub async fn transpile(
+  roots: Vec<ModuleSpecifier>,
+  loader: &mut dyn Loader,
+  maybe_import_map: Option<ImportMapInput>,
+  options: TranspileGraphOptions,
+) -> Result<TranspileOutput> {
+  let maybe_import_map = get_import_map_from_input(maybe_import_map)?;
+  let import_map_resolver = ImportMapResolver(maybe_import_map);
+  transpile_with_resolver(
+    roots,
+    loader,
+    import_map_resolver.as_resolver(),
+    options,
+  )
+  .await
+}
+
+/// Transpiles the modules of the graph of one or more roots of a workspace,
+/// resolving their imports with the import maps of the workspace, the names
+/// of its members and the `paths` of its root compiler options.
+pub async fn transpile_workspace(
+  roots: Vec<ModuleSpecifier>,
+  loader: &mut dyn Loader,
+  workspace: &Workspace,
+  options: TranspileGraphOptions,
+) -> Result<TranspileOutput> {
+  let workspace_resolver = WorkspaceResolver::new(workspace)?;
+  let resolver = PathsResolver::new(
+    &workspace_resolver,
+    &workspace.root.compiler_options,
+    workspace.root.maybe_paths_base.as_ref(),
+  )?;
+  let mut loader = resolver.loader(loader);
+  transpile_with_resolver(roots, &mut loader, &resolver, options).await
+}
+
+async fn transpile_with_resolver(
+  roots: Vec<ModuleSpecifier>,
+  loader: &mut dyn Loader,
+  resolver: &dyn deno_graph::source::Resolver,
+  options: TranspileGraphOptions,
+) -> Result<TranspileOutput> {
+  let analyzer = CapturingModuleAnalyzer::default();
+  let mut graph =
+    build_graph(roots, loader, resolver, &analyzer, options.declarations).await;
+  transpile_built_graph(&mut graph, &analyzer, &analyzer, resolver, options)
+}
+
+/// Transpiles a graph built by [`build_graph`], taking the parsed sources out
+/// of the store.
+fn transpile_built_graph(
+  graph: &mut ModuleGraph,
+  module_parser: &dyn ModuleParser,
+  parsed_source_store: &dyn ParsedSourceStore,
+  resolver: &dyn deno_graph::source::Resolver,
+  options: TranspileGraphOptions,
+) -> Result<TranspileOutput> {
+  graph.valid()?;
+
+  if options.declarations {
+    let roots = graph.roots.iter().cloned().collect::<Vec<_>>();
+    build_declaration_graph(graph, &roots, module_parser, resolver, false)?;
+  }
+
+  transpile_graph(graph, Some(parsed_source_store), options)
+}
+
+/// Builds the graph of the roots, which includes the types when declarations
+/// are emitted.
+async fn build_graph(
+  roots: Vec<ModuleSpecifier>,
+  loader: &mut dyn Loader,
+  resolver: &dyn deno_graph::source::Resolver,
+  analyzer: &CapturingModuleAnalyzer,
+  declarations: bool,
+) -> ModuleGraph {
+  let graph_kind = if declarations {
+    GraphKind::All
+  } else {
+    GraphKind::CodeOnly
+  };
+  let mut graph = ModuleGraph::new(graph_kind);
   graph
     .build(
-      vec![root],
+      roots,
       loader,
       BuildOptions {
-        resolver: Some(import_map_resolver.as_resolver()),
+        module_analyzer: analyzer,
+        resolver: Some(resolver),
         ..Default::default()
       },
     )
     .await;
-
-  bundle_graph(&graph, options)
+  graph
 }
 
-pub async fn transpile(
+/// Generates an npm package from the root module and its dependencies. The
+/// dependencies which are mapped to npm packages in the options are left out
+/// of the graph and imported from `node_modules` instead.
+pub async fn npm_package(
   root: ModuleSpecifier,
   loader: &mut dyn Loader,
   maybe_import_map: Option<ImportMapInput>,
-  transpile_options: &TranspileOptions,
-  emit_options: &EmitOptions,
-) -> Result<HashMap<String, Vec<u8>>> {
+  options: NpmPackageOptions,
+) -> Result<NpmPackage> {
   let analyzer = CapturingModuleAnalyzer::default();
   let maybe_import_map = get_import_map_from_input(maybe_import_map)?;
   let import_map_resolver = ImportMapResolver(maybe_import_map);
-  let mut graph = ModuleGraph::new(GraphKind::CodeOnly);
+  let resolver = NpmMappingResolver {
+    resolver: import_map_resolver.as_resolver(),
+    mappings: &options.mappings,
+  };
+  let mut graph = ModuleGraph::new(GraphKind::All);
   graph
     .build(
-      vec![root],
+      vec![root.clone()],
       loader,
       BuildOptions {
         module_analyzer: &analyzer,
-        resolver: Some(import_map_resolver.as_resolver()),
+        resolver: Some(&resolver),
         ..Default::default()
       },
     )
@@ -84,28 +293,132 @@ pub async fn transpile(
 
   graph.valid()?;
 
-  let mut map = HashMap::new();
+  build_declaration_graph(
+    &mut graph,
+    std::slice::from_ref(&root),
+    &analyzer,
+    &resolver,
+    true,
+  )?;
 
-  for module in graph.modules().filter_map(|m| m.js()) {
-    if let Some(parsed_source) =
-      analyzer.remove_parsed_source(&module.specifier)
-    {
-      let transpiled_source = parsed_source
-        .transpile(transpile_options, emit_options)?
-        .into_source();
-
-      map.insert(module.specifier.to_string(), transpiled_source.source);
+  npm_package_graph(&graph, &root, Some(&analyzer), options)
+}
 
-      if let Some(source_map) = transpiled_source.source_map {
-        map.insert(format!("{}.map", module.specifier.as_str()), source_map);
+/// Builds the fast check type graph with declarations for the local modules
+/// of the graph, treating them as a package which exports the roots.
+///
+/// When remote modules are included, the modules of each remote origin are
+/// treated as a package which exports the TypeScript modules that are
+/// imported from outside of it.
+fn build_declaration_graph(
+  graph: &mut ModuleGraph,
+  roots: &[ModuleSpecifier],
+  module_parser: &dyn ModuleParser,
+  resolver: &dyn deno_graph::source::Resolver,
+  include_remote: bool,
+) -> Result<()> {
+  if let Some(root) = roots.iter().find(|root| root.scheme() != "file") {
+    bail!(
+      "Declarations can only be emitted for local modules, but the root \"{}\" is remote.",
+      root
+    );
+  }
+  let Some(base) = output_paths::local_base(graph) else {
+    bail!("The graph contains no local modules.");
+  };
+  let exports = roots
+    .iter()
+    .map(|root| {
+      let root_path = format!("./{}", &root.path()[base.path().len()..]);
+      (root_path.clone(), root_path)
+    })
+    .collect();
+  let mut members = vec![WorkspaceMember {
+    base,
+    name: "@deno-emit/root".to_string(),
+    version: None,
+    exports,
+  }];
+  if include_remote {
+    let mut exports_by_origin: IndexMap<Url, IndexMap<String, String>> =
+      IndexMap::new();
+    for module in graph.modules() {
+      let deno_graph::Module::Js(module) = module else {
+        continue;
+      };
+      for dependency in module.dependencies.values() {
+        let Some(resolved) =
+          dependency.get_type().or_else(|| dependency.get_code())
+        else {
+          continue;
+        };
+        let resolved = graph.resolve(resolved);
+        let is_typescript = matches!(
+          graph.get(resolved),
+          Some(deno_graph::Module::Js(module)) if matches!(
+            module.media_type,
+            MediaType::TypeScript | MediaType::Mts | MediaType::Cts | MediaType::Tsx
+          )
+        );
+        if !is_typescript || !matches!(resolved.scheme(), "http" | "https") {
+          continue;
+        }
+        let origin = vendor::origin(resolved);
+        if origin == vendor::origin(&module.specifier) {
+          continue;
+        }
+        let path = format!(".{}", resolved.path());
+        exports_by_origin
+          .entry(origin)
+          .or_default()
+          .insert(path.clone(), path);
       }
     }
+    for (index, (base, exports)) in exports_by_origin.into_iter().enumerate() {
+      members.push(WorkspaceMember {
+        base,
+        name: format!("@deno-emit/remote-{index}"),
+        version: None,
+        exports,
+      });
+    }
   }
+  graph.build_fast_check_type_graph(BuildFastCheckTypeGraphOptions {
+    fast_check_dts: true,
+    module_parser: Some(module_parser),
+    resolver: Some(resolver),
+    workspace_fast_check: WorkspaceFastCheckOption::Enabled(&members),
+    ..Default::default()
+  });
+  Ok(())
+}
 
-  Ok(map)
+pub async fn vendor(
+  root: ModuleSpecifier,
+  loader: &mut dyn Loader,
+  maybe_import_map: Option<ImportMapInput>,
+  output_dir: &ModuleSpecifier,
+) -> Result<VendorOutput> {
+  let maybe_import_map = get_import_map_from_input(maybe_import_map)?;
+  let import_map_resolver = ImportMapResolver(maybe_import_map);
+  let mut graph = ModuleGraph::new(GraphKind::CodeOnly);
+  graph
+    .build(
+      vec![root],
+      loader,
+      BuildOptions {
+        resolver: Some(import_map_resolver.as_resolver()),
+        ..Default::default()
+      },
+    )
+    .await;
+
+  graph.valid()?;
+
+  vendor_graph(&graph, output_dir)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ImportMapInput {
   pub base_url: Url,
   pub json_string: String,
@@ -0,0 +1,449 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::bail;
+use anyhow::Result;
+use deno_ast::EmitOptions;
+use deno_ast::ModuleSpecifier;
+use deno_ast::TranspileOptions;
+use deno_graph::source::ResolutionMode;
+use deno_graph::source::ResolveError;
+use deno_graph::source::Resolver;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use deno_graph::ParsedSourceStore;
+use deno_graph::Range;
+use std::collections::BTreeMap;
+
+use crate::output_paths::declaration_path;
+use crate::transpile::transpile_graph;
+use crate::transpile::TranspileGraphOptions;
+
+#[derive(Debug, Clone, Default)]
+pub struct NpmPackageOptions {
+  pub name: String,
+  pub version: String,
+  pub description: Option<String>,
+  pub license: Option<String>,
+  /// The npm packages to import instead of remote or `jsr:` modules, keyed
+  /// by the specifier of the module they replace.
+  ///
+  /// A key matches its specifier exactly, and a key ending with a `/`
+  /// matches every specifier it prefixes, with the rest of the specifier
+  /// appended to the sub path of the package. A `jsr:` key without a
+  /// version, like `jsr:@std/path`, matches any version of the package and
+  /// any of its exports, like `jsr:@std/path@^1.0.0/join`.
+  pub mappings: BTreeMap<String, NpmMapping>,
+  pub transpile_options: TranspileOptions,
+  pub emit_options: EmitOptions,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpmMapping {
+  pub name: String,
+  /// The version requirement written to the dependencies of the package.
+  pub version: String,
+  pub sub_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NpmPackage {
+  /// The files of the package keyed by their path relative to the package
+  /// directory, including its `package.json`.
+  pub files: BTreeMap<String, Vec<u8>>,
+}
+
+/// Generates an npm package which exports the root module of the graph.
+///
+/// The modules of the graph are emitted as ES modules with their specifiers
+/// rewritten to the emitted files, along with their declaration files, so
+/// the graph must have had its fast check type graph built with
+/// declarations. Modules which were resolved to `npm:` specifiers, like the
+/// mapped modules of [`crate::npm_package`], are imported from `node_modules`
+/// and added to the dependencies of the package.
+pub fn npm_package_graph(
+  graph: &ModuleGraph,
+  root: &ModuleSpecifier,
+  maybe_parsed_source_store: Option<&dyn ParsedSourceStore>,
+  options: NpmPackageOptions,
+) -> Result<NpmPackage> {
+  if options.name.is_empty() || options.version.is_empty() {
+    bail!("An npm package must have a name and a version.");
+  }
+  let output = transpile_graph(
+    graph,
+    maybe_parsed_source_store,
+    TranspileGraphOptions {
+      transpile_options: options.transpile_options,
+      emit_options: options.emit_options,
+      rewrite_specifiers: true,
+      declarations: true,
+      include_declaration_files: true,
+      bare_npm_specifiers: true,
+      ..Default::default()
+    },
+  )?;
+  let Some(root_module) = output.get(graph.resolve(root)) else {
+    bail!("The root \"{}\" was not emitted.", root);
+  };
+  let root_path =
+    format!("./{}", root_module.maybe_output_path.as_ref().unwrap());
+  let maybe_types_path = root_module
+    .maybe_declaration
+    .as_ref()
+    .map(|_| declaration_path(&root_path));
+
+  let mut package_json = serde_json::Map::new();
+  package_json.insert("name".to_string(), options.name.into());
+  package_json.insert("version".to_string(), options.version.into());
+  if let Some(description) = options.description {
+    package_json.insert("description".to_string(), description.into());
+  }
+  if let Some(license) = options.license {
+    package_json.insert("license".to_string(), license.into());
+  }
+  package_json.insert("type".to_string(), "module".into());
+  package_json.insert("main".to_string(), root_path.clone().into());
+  let mut root_export = serde_json::Map::new();
+  if let Some(types_path) = &maybe_types_path {
+    package_json.insert("types".to_string(), types_path.clone().into());
+    root_export.insert("types".to_string(), types_path.clone().into());
+  }
+  root_export.insert("default".to_string(), root_path.into());
+  package_json.insert(
+    "exports".to_string(),
+    serde_json::json!({ ".": root_export }),
+  );
+  let dependencies = npm_dependencies(graph)?;
+  if !dependencies.is_empty() {
+    package_json.insert(
+      "dependencies".to_string(),
+      dependencies
+        .into_iter()
+        .map(|(name, version)| (name, version.into()))
+        .collect::<serde_json::Map<_, _>>()
+        .into(),
+    );
+  }
+
+  let mut files = output.into_files()?;
+  let mut package_json =
+    serde_json::to_string_pretty(&serde_json::Value::Object(package_json))?;
+  package_json.push('\n');
+  files.insert("package.json".to_string(), package_json.into_bytes());
+  Ok(NpmPackage { files })
+}
+
+/// The npm packages the graph imports, with their version requirements.
+fn npm_dependencies(graph: &ModuleGraph) -> Result<BTreeMap<String, String>> {
+  let mut dependencies = BTreeMap::new();
+  for module in graph.modules() {
+    let Module::External(module) = module else {
+      continue;
+    };
+    let Some((name, version, _)) = parse_npm_specifier(&module.specifier)
+    else {
+      continue;
+    };
+    if version.is_empty() {
+      bail!(
+        "Missing version for the npm package \"{}\". Add a version to the specifier.",
+        module.specifier
+      );
+    }
+    if let Some(other) =
+      dependencies.insert(name.to_string(), version.to_string())
+    {
+      if other != version {
+        bail!(
+          "The npm package \"{name}\" is imported with the versions \"{other}\" and \"{version}\"."
+        );
+      }
+    }
+  }
+  Ok(dependencies)
+}
+
+/// Resolves the specifiers which are mapped to npm packages to `npm:`
+/// specifiers, which are external to the graph, after resolving them with
+/// the inner resolver.
+#[derive(Debug)]
+pub(crate) struct NpmMappingResolver<'a> {
+  pub resolver: &'a dyn Resolver,
+  pub mappings: &'a BTreeMap<String, NpmMapping>,
+}
+
+impl Resolver for NpmMappingResolver<'_> {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer_range: &Range,
+    mode: ResolutionMode,
+  ) -> Result<ModuleSpecifier, ResolveError> {
+    let resolved = self.resolver.resolve(specifier, referrer_range, mode)?;
+    Ok(npm_specifier(self.mappings, &resolved).unwrap_or(resolved))
+  }
+}
+
+/// Gets the `npm:` specifier of the package the specifier is mapped to, if
+/// any, preferring the longest matching key.
+fn npm_specifier(
+  mappings: &BTreeMap<String, NpmMapping>,
+  specifier: &ModuleSpecifier,
+) -> Option<ModuleSpecifier> {
+  let specifier = specifier.as_str();
+  let (mapping, rest) = mappings
+    .iter()
+    .filter_map(|(key, mapping)| {
+      let rest = specifier.strip_prefix(key.as_str())?;
+      let rest = if rest.is_empty() || key.ends_with('/') {
+        rest
+      } else if key.starts_with("jsr:") && rest.starts_with('@') {
+        // skip the version requirement
+        rest.split_once('/').map(|(_, rest)| rest).unwrap_or("")
+      } else if key.starts_with("jsr:") {
+        rest.strip_prefix('/')?
+      } else {
+        return None;
+      };
+      Some((key.len(), mapping, rest))
+    })
+    .max_by_key(|(key_len, _, _)| *key_len)
+    .map(|(_, mapping, rest)| (mapping, rest))?;
+  let mut npm_specifier = format!("npm:{}@{}", mapping.name, mapping.version);
+  let sub_path = [mapping.sub_path.as_deref().unwrap_or(""), rest]
+    .into_iter()
+    .map(|part| part.trim_matches('/'))
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join("/");
+  if !sub_path.is_empty() {
+    npm_specifier.push('/');
+    npm_specifier.push_str(&sub_path);
+  }
+  ModuleSpecifier::parse(&npm_specifier).ok()
+}
+
+/// Gets the bare specifier an `npm:` specifier is imported with from
+/// `node_modules`, like `preact/hooks` for `npm:preact@^10/hooks`.
+pub(crate) fn bare_npm_specifier(
+  specifier: &ModuleSpecifier,
+) -> Option<String> {
+  let (name, _, sub_path) = parse_npm_specifier(specifier)?;
+  Some(match sub_path {
+    Some(sub_path) => format!("{name}/{sub_path}"),
+    None => name.to_string(),
+  })
+}
+
+/// Splits an `npm:` specifier into its package name, version requirement and
+/// sub path.
+fn parse_npm_specifier(
+  specifier: &ModuleSpecifier,
+) -> Option<(&str, &str, Option<&str>)> {
+  let text = specifier.as_str().strip_prefix("npm:")?;
+  let text = text.strip_prefix('/').unwrap_or(text);
+  // the name of a scoped package contains a slash
+  let name_start = if text.starts_with('@') {
+    text.find('/')? + 1
+  } else {
+    0
+  };
+  let (name_and_version, sub_path) = match text[name_start..].find('/') {
+    Some(index) => (
+      &text[..name_start + index],
+      Some(&text[name_start + index + 1..]),
+    ),
+    None => (text, None),
+  };
+  let (name, version) = match name_and_version[name_start..].find('@') {
+    Some(index) => (
+      &name_and_version[..name_start + index],
+      &name_and_version[name_start + index + 1..],
+    ),
+    None => (name_and_version, ""),
+  };
+  Some((
+    name,
+    version,
+    sub_path.filter(|sub_path| !sub_path.is_empty()),
+  ))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use pretty_assertions::assert_eq;
+
+  fn mapping(name: &str, version: &str) -> NpmMapping {
+    NpmMapping {
+      name: name.to_string(),
+      version: version.to_string(),
+      sub_path: None,
+    }
+  }
+
+  #[test]
+  fn test_npm_specifier() {
+    let mappings = BTreeMap::from([
+      ("jsr:@std/path".to_string(), mapping("@std/path", "^1.0.0")),
+      (
+        "https://esm.sh/preact@10.0.0".to_string(),
+        mapping("preact", "^10.0.0"),
+      ),
+      (
+        "https://deno.land/x/lib@1.0.0/".to_string(),
+        NpmMapping {
+          sub_path: Some("dist".to_string()),
+          ..mapping("lib", "1.0.0")
+        },
+      ),
+    ]);
+    let run = |specifier: &str| {
+      npm_specifier(&mappings, &ModuleSpecifier::parse(specifier).unwrap())
+        .map(|specifier| specifier.to_string())
+    };
+    assert_eq!(
+      run("jsr:@std/path@^1.0.0/join").as_deref(),
+      Some("npm:@std/path@^1.0.0/join")
+    );
+    assert_eq!(
+      run("jsr:@std/path").as_deref(),
+      Some("npm:@std/path@^1.0.0")
+    );
+    assert_eq!(run("jsr:@std/path_extra@1"), None);
+    assert_eq!(
+      run("https://esm.sh/preact@10.0.0").as_deref(),
+      Some("npm:preact@^10.0.0")
+    );
+    assert_eq!(run("https://esm.sh/preact@10.0.0/hooks"), None);
+    assert_eq!(
+      run("https://deno.land/x/lib@1.0.0/a/mod.js").as_deref(),
+      Some("npm:lib@1.0.0/dist/a/mod.js")
+    );
+  }
+
+  #[test]
+  fn test_bare_npm_specifier() {
+    let run = |specifier: &str| {
+      bare_npm_specifier(&ModuleSpecifier::parse(specifier).unwrap())
+    };
+    assert_eq!(run("npm:preact@^10/hooks").as_deref(), Some("preact/hooks"));
+    assert_eq!(run("npm:preact").as_deref(), Some("preact"));
+    assert_eq!(run("npm:/@std/path@1.0.0").as_deref(), Some("@std/path"));
+    assert_eq!(
+      run("npm:@std/path@1.0.0/join").as_deref(),
+      Some("@std/path/join")
+    );
+    assert_eq!(run("https://esm.sh/preact"), None);
+  }
+
+  #[tokio::test]
+  async fn npm_package() {
+    let mut loader = MemoryLoader::new(
+      vec![
+        (
+          "file:///a/mod.ts",
+          Source::Module {
+            specifier: "file:///a/mod.ts",
+            maybe_headers: None,
+            content: r#"import { join } from "jsr:@std/path@^1.0.0/join";
+import { h } from "https://esm.sh/preact@10.0.0";
+export { value } from "./sub/value.ts";
+export function render(): unknown {
+  return h("div", null, join("a", "b"));
+}
+"#,
+          },
+        ),
+        (
+          "file:///a/sub/value.ts",
+          Source::Module {
+            specifier: "file:///a/sub/value.ts",
+            maybe_headers: None,
+            content: "export const value: number = 1;\n",
+          },
+        ),
+      ],
+      vec![],
+    );
+    let package = crate::npm_package(
+      ModuleSpecifier::parse("file:///a/mod.ts").unwrap(),
+      &mut loader,
+      None,
+      NpmPackageOptions {
+        name: "@scope/pkg".to_string(),
+        version: "1.0.0".to_string(),
+        license: Some("MIT".to_string()),
+        mappings: BTreeMap::from([
+          ("jsr:@std/path".to_string(), mapping("@std/path", "^1.0.0")),
+          (
+            "https://esm.sh/preact@10.0.0".to_string(),
+            mapping("preact", "^10.0.0"),
+          ),
+        ]),
+        emit_options: EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      package
+        .files
+        .keys()
+        .map(|key| key.as_str())
+        .collect::<Vec<_>>(),
+      vec![
+        "mod.d.ts",
+        "mod.js",
+        "package.json",
+        "sub/value.d.ts",
+        "sub/value.js"
+      ]
+    );
+    assert_eq!(
+      String::from_utf8(package.files["package.json"].clone()).unwrap(),
+      r#"{
+  "name": "@scope/pkg",
+  "version": "1.0.0",
+  "license": "MIT",
+  "type": "module",
+  "main": "./mod.js",
+  "types": "./mod.d.ts",
+  "exports": {
+    ".": {
+      "types": "./mod.d.ts",
+      "default": "./mod.js"
+    }
+  },
+  "dependencies": {
+    "@std/path": "^1.0.0",
+    "preact": "^10.0.0"
+  }
+}
+"#
+    );
+    assert_eq!(
+      String::from_utf8(package.files["mod.js"].clone()).unwrap(),
+      r#"import { join } from "@std/path/join";
+import { h } from "preact";
+export { value } from "./sub/value.js";
+export function render() {
+  return h("div", null, join("a", "b"));
+}
+"#
+    );
+    assert_eq!(
+      String::from_utf8(package.files["mod.d.ts"].clone()).unwrap(),
+      r#"export { value } from "./sub/value.js";
+export declare function render(): unknown;
+"#
+    );
+  }
+}
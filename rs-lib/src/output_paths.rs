@@ -0,0 +1,375 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::bail;
+use anyhow::Result;
+use deno_ast::MediaType;
+use deno_ast::ModuleSpecifier;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::cache::source_hash;
+use crate::commonjs::ModuleFormat;
+use crate::filter::ModuleFilter;
+
+/// The directory that remote modules are written to.
+const REMOTE_DIR: &str = "remote";
+
+/// Extensions which are replaced by the extension of the emitted file. The
+/// declaration file extensions come first so they're stripped completely.
+const SOURCE_EXTENSIONS: &[&str] = &[
+  ".d.ts", ".d.mts", ".d.cts", ".ts", ".tsx", ".mts", ".cts", ".js", ".jsx",
+  ".mjs", ".cjs", ".json",
+];
+
+/// The relative paths that the JavaScript and JSON modules of a graph which
+/// are included by the filter are written to when the output is meant to be
+/// run from disk.
+///
+/// Local modules keep their layout relative to the closest directory that
+/// contains all of them, while remote modules are placed in
+/// `remote/<host>/<path>`.
+pub(crate) struct OutputPaths(HashMap<ModuleSpecifier, String>);
+
+impl OutputPaths {
+  pub fn new(
+    graph: &ModuleGraph,
+    filter: &ModuleFilter,
+    module_format: ModuleFormat,
+  ) -> Result<Self> {
+    let maybe_local_base = local_base(graph);
+    let mut paths = HashMap::new();
+    let mut specifiers_by_path: HashMap<String, &ModuleSpecifier> =
+      HashMap::new();
+    for module in graph.modules() {
+      if !filter.includes(module.specifier()) {
+        continue;
+      }
+      let media_type = match module {
+        Module::Js(module) => module.media_type,
+        Module::Json(module) => module.media_type,
+        Module::Npm(_) | Module::Node(_) | Module::External(_) => continue,
+      };
+      let specifier = module.specifier();
+      let path = match &maybe_local_base {
+        Some(local_base) if specifier.scheme() == "file" => {
+          decode_path(&specifier.path()[local_base.path().len()..])
+        }
+        _ => remote_path(specifier, media_type),
+      };
+      let path = with_emitted_extension(&path, media_type, module_format);
+      if let Some(other) = specifiers_by_path.insert(path.clone(), specifier) {
+        bail!(
+          "Modules \"{}\" and \"{}\" would both be emitted to \"{}\".",
+          other,
+          specifier,
+          path
+        );
+      }
+      paths.insert(specifier.clone(), path);
+    }
+    Ok(Self(paths))
+  }
+
+  pub fn get(&self, specifier: &ModuleSpecifier) -> Option<&str> {
+    self.0.get(specifier).map(|path| path.as_str())
+  }
+}
+
+/// Gets the relative specifier to import the file at `to` from the file
+/// at `from`, where both are relative paths within the output.
+pub(crate) fn relative_specifier(from: &str, to: &str) -> String {
+  let from_dirs = from.split('/').collect::<Vec<_>>();
+  let from_dirs = &from_dirs[..from_dirs.len() - 1];
+  let to_parts = to.split('/').collect::<Vec<_>>();
+  let common_len = from_dirs
+    .iter()
+    .zip(&to_parts[..to_parts.len() - 1])
+    .take_while(|(a, b)| a == b)
+    .count();
+  let mut specifier = if common_len == from_dirs.len() {
+    "./".to_string()
+  } else {
+    "../".repeat(from_dirs.len() - common_len)
+  };
+  specifier.push_str(&to_parts[common_len..].join("/"));
+  specifier
+}
+
+/// The path of the declaration file emitted for the module at the path,
+/// which is next to its emitted JavaScript file.
+pub(crate) fn declaration_path(path: &str) -> String {
+  let file_name_start = path.rfind('/').map(|i| i + 1).unwrap_or(0);
+  let (stem, ext) = SOURCE_EXTENSIONS
+    .iter()
+    .find_map(|ext| {
+      path[file_name_start..]
+        .strip_suffix(ext)
+        .map(|_| (&path[..path.len() - ext.len()], *ext))
+    })
+    .unwrap_or((path, ""));
+  let declaration_ext = match ext {
+    ".d.mts" | ".mts" | ".mjs" => ".d.mts",
+    ".d.cts" | ".cts" | ".cjs" => ".d.cts",
+    _ => ".d.ts",
+  };
+  format!("{stem}{declaration_ext}")
+}
+
+/// The closest directory which contains all the local modules of the graph.
+pub(crate) fn local_base(graph: &ModuleGraph) -> Option<ModuleSpecifier> {
+  let mut maybe_base: Option<ModuleSpecifier> = None;
+  for module in graph.modules() {
+    let specifier = module.specifier();
+    if specifier.scheme() != "file" {
+      continue;
+    }
+    let mut base = match maybe_base {
+      Some(base) => base,
+      None => specifier.join("./").ok()?,
+    };
+    while !specifier.path().starts_with(base.path()) {
+      base = base.join("../").ok()?;
+    }
+    maybe_base = Some(base);
+  }
+  maybe_base
+}
+
+/// The path of a remote module, without changing its extension. Urls which
+/// don't end with the extension of the module's media type, like
+/// `https://esm.sh/react`, get it appended.
+pub(crate) fn remote_path(
+  specifier: &ModuleSpecifier,
+  media_type: MediaType,
+) -> String {
+  let mut path = remote_dir(specifier);
+  let specifier_path = decode_path(specifier.path().trim_start_matches('/'));
+  if specifier_path.is_empty() || specifier.cannot_be_a_base() {
+    // data urls and the like, which have no meaningful path
+    path.push_str(&format!("/{}", short_hash(specifier.as_str())));
+  } else {
+    path.push('/');
+    path.push_str(&specifier_path);
+    if path.ends_with('/') {
+      path.push_str("index");
+    }
+  }
+  if MediaType::from_path(Path::new(&path)) != media_type {
+    path.push_str(media_type.as_ts_extension());
+  }
+  if let Some(query) = specifier.query() {
+    path = with_suffix(&path, &format!("_{}", short_hash(query)));
+  }
+  path
+}
+
+/// The directory that the remote modules of the specifier's origin are
+/// written to.
+pub(crate) fn remote_dir(specifier: &ModuleSpecifier) -> String {
+  match specifier.host_str() {
+    Some(host) => match specifier.port() {
+      Some(port) => format!("{REMOTE_DIR}/{host}_{port}"),
+      None => format!("{REMOTE_DIR}/{host}"),
+    },
+    None => format!("{REMOTE_DIR}/{}", specifier.scheme()),
+  }
+}
+
+fn with_emitted_extension(
+  path: &str,
+  media_type: MediaType,
+  module_format: ModuleFormat,
+) -> String {
+  let stem = SOURCE_EXTENSIONS
+    .iter()
+    .find_map(|ext| path.strip_suffix(ext))
+    .unwrap_or(path);
+  format!("{stem}{}", emitted_extension(media_type, module_format))
+}
+
+fn emitted_extension(
+  media_type: MediaType,
+  module_format: ModuleFormat,
+) -> &'static str {
+  match media_type {
+    // modules which were explicitly ES modules are explicitly CommonJS
+    // modules once converted
+    MediaType::Mjs | MediaType::Mts
+      if module_format == ModuleFormat::CommonJs =>
+    {
+      ".cjs"
+    }
+    MediaType::Mjs | MediaType::Mts => ".mjs",
+    MediaType::Cjs | MediaType::Cts => ".cjs",
+    MediaType::Json => ".json",
+    // declaration files are passed through as-is
+    MediaType::Dts => ".d.ts",
+    MediaType::Dmts => ".d.mts",
+    MediaType::Dcts => ".d.cts",
+    MediaType::JavaScript
+    | MediaType::Jsx
+    | MediaType::TypeScript
+    | MediaType::Tsx
+    | MediaType::Wasm
+    | MediaType::TsBuildInfo
+    | MediaType::SourceMap
+    | MediaType::Unknown => ".js",
+  }
+}
+
+/// Inserts the suffix before the extension of the last path segment.
+fn with_suffix(path: &str, suffix: &str) -> String {
+  let file_name_start = path.rfind('/').map(|i| i + 1).unwrap_or(0);
+  match SOURCE_EXTENSIONS
+    .iter()
+    .find_map(|ext| path[file_name_start..].strip_suffix(ext).map(|_| ext))
+  {
+    Some(ext) => format!("{}{suffix}{ext}", &path[..path.len() - ext.len()]),
+    None => format!("{path}{suffix}"),
+  }
+}
+
+fn short_hash(text: &str) -> String {
+  source_hash(text)[..8].to_string()
+}
+
+pub(crate) fn decode(path: &str) -> String {
+  percent_decode_str(path).decode_utf8_lossy().into_owned()
+}
+
+/// Decodes the segments of a URL path for a relative output path. Segments
+/// which would leave their directory once decoded, like `..%2F`, are replaced
+/// with a hash of the encoded segment.
+pub(crate) fn decode_path(path: &str) -> String {
+  path
+    .split('/')
+    .map(|segment| {
+      let decoded = decode(segment);
+      if decoded == "." || decoded == ".." || decoded.contains(['/', '\\']) {
+        format!("_{}", short_hash(segment))
+      } else {
+        decoded
+      }
+    })
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_relative_specifier() {
+    assert_eq!(relative_specifier("mod.js", "foo.js"), "./foo.js");
+    assert_eq!(relative_specifier("mod.js", "a/foo.js"), "./a/foo.js");
+    assert_eq!(relative_specifier("a/mod.js", "foo.js"), "../foo.js");
+    assert_eq!(
+      relative_specifier("a/b/mod.js", "a/c/foo.js"),
+      "../c/foo.js"
+    );
+    assert_eq!(
+      relative_specifier("a/mod.js", "remote/deno.land/mod.js"),
+      "../remote/deno.land/mod.js"
+    );
+  }
+
+  #[test]
+  fn test_remote_path() {
+    let run = |specifier: &str| {
+      let specifier = ModuleSpecifier::parse(specifier).unwrap();
+      let media_type = MediaType::from_specifier(&specifier);
+      remote_path(&specifier, media_type)
+    };
+    assert_eq!(
+      run("https://deno.land/std@0.140.0/path/mod.ts"),
+      "remote/deno.land/std@0.140.0/path/mod.ts"
+    );
+    assert_eq!(
+      run("http://localhost:8000/a%20b.ts"),
+      "remote/localhost_8000/a b.ts"
+    );
+    assert_eq!(
+      run("https://esm.sh/react/index.js"),
+      "remote/esm.sh/react/index.js"
+    );
+    assert_eq!(
+      run("https://esm.sh/react.js?target=deno"),
+      format!("remote/esm.sh/react_{}.js", short_hash("target=deno"))
+    );
+    let specifier = ModuleSpecifier::parse("https://esm.sh/react/").unwrap();
+    assert_eq!(
+      remote_path(&specifier, MediaType::JavaScript),
+      "remote/esm.sh/react/index.js"
+    );
+    let specifier =
+      ModuleSpecifier::parse("https://esm.sh/react?target=deno").unwrap();
+    assert_eq!(
+      remote_path(&specifier, MediaType::JavaScript),
+      format!("remote/esm.sh/react_{}.js", short_hash("target=deno"))
+    );
+    assert_eq!(
+      run("https://ex.com/..%2F..%2F..%2F..%2Fescape.ts"),
+      format!(
+        "remote/ex.com/_{}.ts",
+        short_hash("..%2F..%2F..%2F..%2Fescape.ts")
+      )
+    );
+    assert_eq!(
+      run("https://ex.com/a/%2E%2E%5Cescape.ts"),
+      format!("remote/ex.com/a/_{}.ts", short_hash("%2E%2E%5Cescape.ts"))
+    );
+    let specifier = ModuleSpecifier::parse("https://esm.sh/v135/react.d.ts");
+    assert_eq!(
+      remote_path(&specifier.unwrap(), MediaType::Dts),
+      "remote/esm.sh/v135/react.d.ts"
+    );
+  }
+
+  #[test]
+  fn test_declaration_path() {
+    assert_eq!(declaration_path("a/mod.js"), "a/mod.d.ts");
+    assert_eq!(declaration_path("file:///a/mod.tsx"), "file:///a/mod.d.ts");
+    assert_eq!(declaration_path("mod.mjs"), "mod.d.mts");
+    assert_eq!(declaration_path("mod.d.cts"), "mod.d.cts");
+    assert_eq!(
+      declaration_path("react@18.js/index"),
+      "react@18.js/index.d.ts"
+    );
+  }
+
+  #[test]
+  fn test_with_emitted_extension() {
+    assert_eq!(
+      with_emitted_extension(
+        "a/mod.ts",
+        MediaType::TypeScript,
+        ModuleFormat::Esm
+      ),
+      "a/mod.js"
+    );
+    assert_eq!(
+      with_emitted_extension("a/mod.d.ts", MediaType::Dts, ModuleFormat::Esm),
+      "a/mod.d.ts"
+    );
+    assert_eq!(
+      with_emitted_extension("mod.mts", MediaType::Mts, ModuleFormat::Esm),
+      "mod.mjs"
+    );
+    assert_eq!(
+      with_emitted_extension(
+        "react@18",
+        MediaType::JavaScript,
+        ModuleFormat::Esm
+      ),
+      "react@18.js"
+    );
+    assert_eq!(
+      with_emitted_extension("data.json", MediaType::Json, ModuleFormat::Esm),
+      "data.json"
+    );
+  }
+}
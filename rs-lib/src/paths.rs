@@ -0,0 +1,468 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use deno_ast::ModuleSpecifier;
+use deno_graph::source::LoadFuture;
+use deno_graph::source::LoadOptions;
+use deno_graph::source::Loader;
+use deno_graph::source::ResolutionMode;
+use deno_graph::source::ResolveError;
+use deno_graph::source::Resolver;
+use deno_graph::Range;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::CompilerOptions;
+
+/// An alias of the `paths` compiler option, like `"@app/*"`.
+#[derive(Debug)]
+struct PathAlias {
+  pattern: String,
+  /// The text before and after the `*` of a wildcard pattern.
+  maybe_wildcard: Option<(String, String)>,
+  targets: Vec<String>,
+}
+
+/// An import which matched a path alias, or the `baseUrl`.
+#[derive(Clone, Debug)]
+struct PathsMatch {
+  specifier: String,
+  /// The pattern of the alias, or `None` for the `baseUrl`.
+  maybe_alias: Option<String>,
+  /// The modules the import may refer to, in the order they're tried.
+  candidates: Vec<ModuleSpecifier>,
+}
+
+impl PathsMatch {
+  fn describe_alias(&self) -> String {
+    match &self.maybe_alias {
+      Some(alias) => format!("the path alias \"{alias}\""),
+      None => "the \"baseUrl\"".to_string(),
+    }
+  }
+}
+
+/// Resolves the imports which the inner resolver can't resolve with the
+/// `paths` and `baseUrl` compiler options, like TypeScript does.
+///
+/// An alias with several targets resolves to the first one, and
+/// [`PathsResolver::loader`] falls back to the others when the first one
+/// doesn't exist. The graph records the fallback as a redirect.
+#[derive(Debug)]
+pub(crate) struct PathsResolver<'a> {
+  resolver: &'a dyn Resolver,
+  maybe_base_url: Option<ModuleSpecifier>,
+  maybe_paths_base: Option<ModuleSpecifier>,
+  aliases: Vec<PathAlias>,
+  /// The aliased imports by the candidate they were resolved to.
+  matches: RefCell<HashMap<ModuleSpecifier, PathsMatch>>,
+}
+
+impl<'a> PathsResolver<'a> {
+  /// Creates the resolver for the compiler options, whose `paths` are
+  /// relative to the `maybe_paths_base` directory when there's no `baseUrl`.
+  pub fn new(
+    resolver: &'a dyn Resolver,
+    compiler_options: &CompilerOptions,
+    maybe_paths_base: Option<&ModuleSpecifier>,
+  ) -> Result<Self> {
+    let maybe_base_url = compiler_options
+      .base_url
+      .as_deref()
+      .map(ModuleSpecifier::parse)
+      .transpose()
+      .context("Invalid \"baseUrl\" compiler option.")?;
+    let maybe_paths_base =
+      maybe_base_url.as_ref().or(maybe_paths_base).cloned();
+    let mut aliases = Vec::with_capacity(compiler_options.paths.len());
+    for (pattern, targets) in &compiler_options.paths {
+      if targets.is_empty() {
+        bail!("The path alias \"{pattern}\" has no targets.");
+      }
+      let maybe_wildcard = match pattern.split_once('*') {
+        Some((_, suffix)) if suffix.contains('*') => {
+          bail!("The path alias \"{pattern}\" can only contain one \"*\".")
+        }
+        Some((prefix, suffix)) => {
+          Some((prefix.to_string(), suffix.to_string()))
+        }
+        None => None,
+      };
+      aliases.push(PathAlias {
+        pattern: pattern.clone(),
+        maybe_wildcard,
+        targets: targets.clone(),
+      });
+    }
+    if !aliases.is_empty() && maybe_paths_base.is_none() {
+      bail!(
+        "The \"paths\" compiler option requires a \"baseUrl\" when it's not loaded from a configuration file."
+      );
+    }
+    Ok(Self {
+      resolver,
+      maybe_base_url,
+      maybe_paths_base,
+      aliases,
+      matches: Default::default(),
+    })
+  }
+
+  /// Wraps the loader to fall back to the other targets of an alias when
+  /// its first target doesn't exist.
+  pub fn loader<'b>(&'b self, loader: &'b dyn Loader) -> PathsLoader<'b> {
+    PathsLoader {
+      loader,
+      matches: &self.matches,
+    }
+  }
+
+  /// Matches a non-relative import against the aliases, preferring an exact
+  /// alias and then the wildcard alias with the longest prefix.
+  fn match_specifier(&self, specifier: &str) -> Result<Option<PathsMatch>> {
+    if specifier.starts_with("./")
+      || specifier.starts_with("../")
+      || specifier.starts_with('/')
+      || ModuleSpecifier::parse(specifier).is_ok()
+    {
+      return Ok(None);
+    }
+    let maybe_exact = self
+      .aliases
+      .iter()
+      .find(|alias| {
+        alias.maybe_wildcard.is_none() && alias.pattern == specifier
+      })
+      .map(|alias| (alias, ""));
+    let maybe_alias = maybe_exact.or_else(|| {
+      self
+        .aliases
+        .iter()
+        .filter_map(|alias| {
+          let (prefix, suffix) = alias.maybe_wildcard.as_ref()?;
+          let matched = specifier
+            .strip_prefix(prefix.as_str())?
+            .strip_suffix(suffix.as_str())?;
+          Some((alias, prefix.len(), matched))
+        })
+        .max_by_key(|(_, prefix_len, _)| *prefix_len)
+        .map(|(alias, _, matched)| (alias, matched))
+    });
+    if let Some((alias, matched)) = maybe_alias {
+      // the base is checked when the resolver is created
+      let base = self.maybe_paths_base.as_ref().unwrap();
+      let candidates = alias
+        .targets
+        .iter()
+        .map(|target| {
+          let target = target.replacen('*', matched, 1);
+          base.join(&target).with_context(|| {
+            format!(
+              "Failed resolving \"{specifier}\" with the path alias \"{}\" to \"{target}\".",
+              alias.pattern
+            )
+          })
+        })
+        .collect::<Result<Vec<_>>>()?;
+      return Ok(Some(PathsMatch {
+        specifier: specifier.to_string(),
+        maybe_alias: Some(alias.pattern.clone()),
+        candidates,
+      }));
+    }
+    match &self.maybe_base_url {
+      Some(base_url) => Ok(Some(PathsMatch {
+        specifier: specifier.to_string(),
+        maybe_alias: None,
+        candidates: vec![base_url.join(specifier)?],
+      })),
+      None => Ok(None),
+    }
+  }
+}
+
+impl Resolver for PathsResolver<'_> {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer_range: &Range,
+    mode: ResolutionMode,
+  ) -> Result<ModuleSpecifier, ResolveError> {
+    let err = match self.resolver.resolve(specifier, referrer_range, mode) {
+      Ok(resolved) => return Ok(resolved),
+      Err(err) => err,
+    };
+    match self.match_specifier(specifier) {
+      Ok(Some(paths_match)) => {
+        let resolved = paths_match.candidates[0].clone();
+        self
+          .matches
+          .borrow_mut()
+          .entry(resolved.clone())
+          .or_insert(paths_match);
+        Ok(resolved)
+      }
+      Ok(None) => Err(err),
+      Err(err) => Err(ResolveError::Other(err)),
+    }
+  }
+}
+
+/// A loader which tries the targets of a path alias in order.
+pub(crate) struct PathsLoader<'a> {
+  loader: &'a dyn Loader,
+  matches: &'a RefCell<HashMap<ModuleSpecifier, PathsMatch>>,
+}
+
+impl Loader for PathsLoader<'_> {
+  fn load(
+    &self,
+    specifier: &ModuleSpecifier,
+    options: LoadOptions,
+  ) -> LoadFuture {
+    let Some(paths_match) = self.matches.borrow().get(specifier).cloned()
+    else {
+      return self.loader.load(specifier, options);
+    };
+    let loads = paths_match
+      .candidates
+      .iter()
+      .map(|candidate| self.loader.load(candidate, options.clone()))
+      .collect::<Vec<_>>();
+    Box::pin(async move {
+      for load in loads {
+        if let Ok(Some(response)) = load.await {
+          return Ok(Some(response));
+        }
+      }
+      let mut tried = String::new();
+      for candidate in &paths_match.candidates {
+        tried.push_str("\n    ");
+        tried.push_str(candidate.as_str());
+      }
+      Err(anyhow!(
+        "Could not find \"{}\", which matched {}. Tried:{tried}",
+        paths_match.specifier,
+        paths_match.describe_alias(),
+      ))
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use pretty_assertions::assert_eq;
+
+  use crate::TranspileGraphOptions;
+  use crate::Workspace;
+
+  #[derive(Debug)]
+  struct NoResolver;
+
+  impl Resolver for NoResolver {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer_range: &Range,
+      _mode: ResolutionMode,
+    ) -> Result<ModuleSpecifier, ResolveError> {
+      deno_graph::resolve_import(specifier, &referrer_range.specifier)
+        .map_err(|err| err.into())
+    }
+  }
+
+  #[test]
+  fn test_match_specifier() {
+    let compiler_options = CompilerOptions {
+      paths: [
+        ("*", vec!["./types/*"]),
+        ("@app/*", vec!["./src/*", "./generated/*"]),
+        ("@app/ui/*", vec!["./ui/*.tsx"]),
+        ("config", vec!["./config/index.ts"]),
+      ]
+      .into_iter()
+      .map(|(pattern, targets)| {
+        (
+          pattern.to_string(),
+          targets.into_iter().map(|t| t.to_string()).collect(),
+        )
+      })
+      .collect(),
+      ..Default::default()
+    };
+    let paths_base = ModuleSpecifier::parse("file:///a/").unwrap();
+    let resolver =
+      PathsResolver::new(&NoResolver, &compiler_options, Some(&paths_base))
+        .unwrap();
+    let run = |specifier: &str| {
+      resolver
+        .match_specifier(specifier)
+        .unwrap()
+        .map(|paths_match| {
+          (
+            paths_match.maybe_alias.unwrap(),
+            paths_match
+              .candidates
+              .into_iter()
+              .map(|candidate| candidate.to_string())
+              .collect::<Vec<_>>(),
+          )
+        })
+    };
+    assert_eq!(
+      run("@app/a.ts"),
+      Some((
+        "@app/*".to_string(),
+        vec![
+          "file:///a/src/a.ts".to_string(),
+          "file:///a/generated/a.ts".to_string()
+        ]
+      ))
+    );
+    assert_eq!(
+      run("@app/ui/button"),
+      Some((
+        "@app/ui/*".to_string(),
+        vec!["file:///a/ui/button.tsx".to_string()]
+      ))
+    );
+    assert_eq!(
+      run("config"),
+      Some((
+        "config".to_string(),
+        vec!["file:///a/config/index.ts".to_string()]
+      ))
+    );
+    assert_eq!(
+      run("react"),
+      Some(("*".to_string(), vec!["file:///a/types/react".to_string()]))
+    );
+    assert_eq!(run("./a.ts"), None);
+    assert_eq!(run("https://deno.land/x/mod.ts"), None);
+  }
+
+  #[tokio::test]
+  async fn transpile_paths() {
+    let sources = vec![
+      (
+        "file:///a/tsconfig.json",
+        r#"{
+  "extends": "./configs/base.json",
+  "compilerOptions": {
+    "paths": {
+      "@app/*": ["src/*", "generated/*"],
+      "config": ["./config/index.ts"]
+    }
+  }
+}"#,
+      ),
+      (
+        "file:///a/configs/base.json",
+        r#"{ "compilerOptions": { "baseUrl": "../lib" } }"#,
+      ),
+      (
+        "file:///a/main.ts",
+        r#"import { a } from "@app/a.ts";
+import { b } from "@app/b.ts";
+import { config } from "config";
+import { util } from "util.ts";
+console.log(a, b, config, util);
+"#,
+      ),
+      ("file:///a/missing.ts", "import \"@app/missing.ts\";\n"),
+      ("file:///a/lib/src/a.ts", "export const a = 1;\n"),
+      ("file:///a/lib/generated/b.ts", "export const b = 2;\n"),
+      (
+        "file:///a/lib/config/index.ts",
+        "export const config = 3;\n",
+      ),
+      ("file:///a/lib/util.ts", "export const util = 4;\n"),
+    ];
+    let mut loader = MemoryLoader::new(
+      sources
+        .into_iter()
+        .map(|(specifier, content)| {
+          (
+            specifier,
+            Source::Module {
+              specifier,
+              maybe_headers: None,
+              content,
+            },
+          )
+        })
+        .collect(),
+      vec![],
+    );
+    let workspace = Workspace::load(
+      ModuleSpecifier::parse("file:///a/tsconfig.json").unwrap(),
+      &loader,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+      workspace.root.compiler_options.base_url.as_deref(),
+      Some("file:///a/lib/")
+    );
+
+    let output = crate::transpile_workspace(
+      vec![ModuleSpecifier::parse("file:///a/main.ts").unwrap()],
+      &mut loader,
+      &workspace,
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        rewrite_specifiers: true,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap()
+    .into_files()
+    .unwrap();
+    assert_eq!(
+      output.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+      vec![
+        "lib/config/index.js",
+        "lib/generated/b.js",
+        "lib/src/a.js",
+        "lib/util.js",
+        "main.js",
+      ]
+    );
+    assert_eq!(
+      String::from_utf8(output["main.js"].clone()).unwrap(),
+      r#"import { a } from "./lib/src/a.js";
+import { b } from "./lib/generated/b.js";
+import { config } from "./lib/config/index.js";
+import { util } from "./lib/util.js";
+console.log(a, b, config, util);
+"#
+    );
+
+    let err = crate::transpile_workspace(
+      vec![ModuleSpecifier::parse("file:///a/missing.ts").unwrap()],
+      &mut loader,
+      &workspace,
+      Default::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(
+      err.to_string().contains(
+        "Could not find \"@app/missing.ts\", which matched the path alias \"@app/*\". Tried:
+    file:///a/lib/src/missing.ts
+    file:///a/lib/generated/missing.ts"
+      ),
+      "{err}"
+    );
+  }
+}
@@ -0,0 +1,199 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use deno_ast::swc::ast;
+use deno_ast::swc::visit::VisitMut;
+use deno_ast::swc::visit::VisitMutWith;
+use deno_ast::ModuleSpecifier;
+use deno_graph::Dependency;
+use deno_graph::FastCheckTypeModuleSlot;
+use deno_graph::JsModule;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use indexmap::IndexMap;
+use std::collections::BTreeMap;
+
+use crate::npm_package::bare_npm_specifier;
+use crate::output_paths::relative_specifier;
+use crate::output_paths::OutputPaths;
+
+/// Gets the map of the specifiers as written in the module to the relative
+/// specifiers of the emitted files they resolve to.
+///
+/// When `bare_npm_specifiers` is set, `npm:` specifiers are mapped to bare
+/// specifiers which are resolved from `node_modules`.
+pub(crate) fn get_specifier_map(
+  graph: &ModuleGraph,
+  module: &JsModule,
+  output_paths: &OutputPaths,
+  bare_npm_specifiers: bool,
+) -> BTreeMap<String, String> {
+  let mut map = BTreeMap::new();
+  let Some(referrer_path) = output_paths.get(&module.specifier) else {
+    return map;
+  };
+  for (specifier, dependency) in &module.dependencies {
+    let Some(resolved) = dependency.get_code() else {
+      continue;
+    };
+    if let Some(new_specifier) = rewritten_specifier(
+      graph,
+      output_paths,
+      referrer_path,
+      resolved,
+      bare_npm_specifiers,
+    ) {
+      map.insert(specifier.clone(), new_specifier);
+    }
+  }
+  map
+}
+
+/// Gets the specifier map for the declaration file of the module, which
+/// prefers the types dependency of an import over its code dependency.
+///
+/// Declaration files refer to the emitted JavaScript files, which the
+/// TypeScript compiler resolves to the declaration files next to them.
+pub(crate) fn get_declaration_specifier_map(
+  graph: &ModuleGraph,
+  module: &JsModule,
+  output_paths: &OutputPaths,
+  bare_npm_specifiers: bool,
+) -> BTreeMap<String, String> {
+  let mut map = build_specifier_map(
+    graph,
+    module,
+    &module.dependencies,
+    output_paths,
+    bare_npm_specifiers,
+  );
+  // the declarations may import the types dependency of an import directly
+  if let Some(FastCheckTypeModuleSlot::Module(fast_check_module)) =
+    &module.fast_check
+  {
+    map.extend(build_specifier_map(
+      graph,
+      module,
+      &fast_check_module.dependencies,
+      output_paths,
+      bare_npm_specifiers,
+    ));
+  }
+  map
+}
+
+fn build_specifier_map(
+  graph: &ModuleGraph,
+  module: &JsModule,
+  dependencies: &IndexMap<String, Dependency>,
+  output_paths: &OutputPaths,
+  bare_npm_specifiers: bool,
+) -> BTreeMap<String, String> {
+  let mut map = BTreeMap::new();
+  let Some(referrer_path) = output_paths.get(&module.specifier) else {
+    return map;
+  };
+  for (specifier, dependency) in dependencies {
+    let Some(resolved) =
+      dependency.get_type().or_else(|| dependency.get_code())
+    else {
+      continue;
+    };
+    if let Some(new_specifier) = rewritten_specifier(
+      graph,
+      output_paths,
+      referrer_path,
+      resolved,
+      bare_npm_specifiers,
+    ) {
+      map.insert(specifier.clone(), new_specifier);
+    }
+  }
+  map
+}
+
+fn rewritten_specifier(
+  graph: &ModuleGraph,
+  output_paths: &OutputPaths,
+  referrer_path: &str,
+  resolved: &ModuleSpecifier,
+  bare_npm_specifiers: bool,
+) -> Option<String> {
+  let resolved = graph.resolve(resolved);
+  if let Some(path) = output_paths.get(resolved) {
+    return Some(relative_specifier(referrer_path, path));
+  }
+  match graph.get(resolved) {
+    // modules which were filtered out of the output are imported from where
+    // they were loaded from
+    Some(Module::Js(_) | Module::Json(_)) => Some(resolved.to_string()),
+    Some(Module::External(_))
+      if bare_npm_specifiers && resolved.scheme() == "npm" =>
+    {
+      bare_npm_specifier(resolved)
+    }
+    _ => None,
+  }
+}
+
+/// Rewrites the specifiers of static imports, re-exports and dynamic imports
+/// with a string literal argument.
+struct SpecifierRewriter<'a>(&'a BTreeMap<String, String>);
+
+impl SpecifierRewriter<'_> {
+  fn rewrite_str(&self, value: &mut ast::Str) {
+    if let Some(new_specifier) = self.0.get(&*value.value) {
+      value.value = new_specifier.as_str().into();
+      value.raw = None;
+    }
+  }
+}
+
+impl VisitMut for SpecifierRewriter<'_> {
+  fn visit_mut_import_decl(&mut self, node: &mut ast::ImportDecl) {
+    self.rewrite_str(&mut node.src);
+  }
+
+  fn visit_mut_named_export(&mut self, node: &mut ast::NamedExport) {
+    if let Some(src) = &mut node.src {
+      self.rewrite_str(src);
+    }
+  }
+
+  fn visit_mut_export_all(&mut self, node: &mut ast::ExportAll) {
+    self.rewrite_str(&mut node.src);
+  }
+
+  fn visit_mut_ts_import_type(&mut self, node: &mut ast::TsImportType) {
+    node.visit_mut_children_with(self);
+    self.rewrite_str(&mut node.arg);
+  }
+
+  fn visit_mut_call_expr(&mut self, node: &mut ast::CallExpr) {
+    node.visit_mut_children_with(self);
+    if !matches!(node.callee, ast::Callee::Import(_)) {
+      return;
+    }
+    let Some(arg) = node.args.first_mut() else {
+      return;
+    };
+    match &mut *arg.expr {
+      ast::Expr::Lit(ast::Lit::Str(value)) => self.rewrite_str(value),
+      ast::Expr::Tpl(tpl) if tpl.exprs.is_empty() && tpl.quasis.len() == 1 => {
+        let quasi = &mut tpl.quasis[0];
+        let value = quasi.cooked.as_ref().unwrap_or(&quasi.raw);
+        if let Some(new_specifier) = self.0.get(&**value) {
+          quasi.raw = new_specifier.as_str().into();
+          quasi.cooked = Some(new_specifier.as_str().into());
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+pub(crate) fn rewrite_specifiers(
+  program: &mut ast::Program,
+  specifier_map: &BTreeMap<String, String>,
+) {
+  program.visit_mut_with(&mut SpecifierRewriter(specifier_map));
+}
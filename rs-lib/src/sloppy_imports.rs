@@ -0,0 +1,232 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use deno_ast::ModuleSpecifier;
+use deno_graph::source::LoadFuture;
+use deno_graph::source::LoadOptions;
+use deno_graph::source::Loader;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// The extensions probed for an extensionless import, in order.
+const SLOPPY_EXTENSIONS: &[&str] = &[".ts", ".tsx", ".js", ".mts", ".d.ts"];
+
+/// A loader which resolves extensionless and directory imports of local
+/// modules like Node does, for code written for Node.
+///
+/// When the inner loader can't find a `file:` module, the module is probed
+/// with the `.ts`, `.tsx`, `.js`, `.mts` and `.d.ts` extensions, and then as
+/// a directory with an `index` module with those extensions. The first module
+/// that's found is loaded in its place, which the graph records as a
+/// redirect, so rewritten specifiers and bundles refer to the found module.
+///
+/// Every sloppy import is reported by [`SloppyImportsLoader::warnings`], so
+/// the imports can be fixed.
+pub struct SloppyImportsLoader<L: Loader> {
+  loader: Rc<L>,
+  /// The sloppy imports and the modules they were resolved to.
+  resolved: Rc<RefCell<BTreeMap<ModuleSpecifier, ModuleSpecifier>>>,
+}
+
+impl<L: Loader + 'static> SloppyImportsLoader<L> {
+  pub fn new(loader: L) -> Self {
+    Self {
+      loader: Rc::new(loader),
+      resolved: Default::default(),
+    }
+  }
+
+  /// The warnings about the imports that were resolved sloppily, ordered by
+  /// their specifiers.
+  pub fn warnings(&self) -> Vec<String> {
+    self
+      .resolved
+      .borrow()
+      .iter()
+      .map(|(specifier, resolved)| {
+        format!(
+          "Sloppy import \"{specifier}\" was resolved to \"{resolved}\". Add the full path to the import."
+        )
+      })
+      .collect()
+  }
+}
+
+impl<L: Loader + 'static> Loader for SloppyImportsLoader<L> {
+  fn load(
+    &self,
+    specifier: &ModuleSpecifier,
+    options: LoadOptions,
+  ) -> LoadFuture {
+    let future = self.loader.load(specifier, options.clone());
+    if specifier.scheme() != "file" {
+      return future;
+    }
+    let loader = self.loader.clone();
+    let resolved = self.resolved.clone();
+    let specifier = specifier.clone();
+    Box::pin(async move {
+      // a native loader may error for a directory
+      let maybe_err = match future.await {
+        Ok(Some(response)) => return Ok(Some(response)),
+        Ok(None) => None,
+        Err(err) => Some(err),
+      };
+      for candidate in sloppy_candidates(&specifier) {
+        if let Ok(Some(response)) =
+          loader.load(&candidate, options.clone()).await
+        {
+          resolved.borrow_mut().insert(specifier, candidate);
+          return Ok(Some(response));
+        }
+      }
+      match maybe_err {
+        Some(err) => Err(err),
+        None => Ok(None),
+      }
+    })
+  }
+}
+
+/// The specifiers that a sloppy import of the specifier may refer to, in the
+/// order they're probed.
+fn sloppy_candidates(specifier: &ModuleSpecifier) -> Vec<ModuleSpecifier> {
+  let path = specifier.path();
+  let mut paths = Vec::new();
+  if let Some(dir_path) = path.strip_suffix('/') {
+    paths.extend(
+      SLOPPY_EXTENSIONS
+        .iter()
+        .map(|ext| format!("{dir_path}/index{ext}")),
+    );
+  } else {
+    paths.extend(SLOPPY_EXTENSIONS.iter().map(|ext| format!("{path}{ext}")));
+    paths.extend(
+      SLOPPY_EXTENSIONS
+        .iter()
+        .map(|ext| format!("{path}/index{ext}")),
+    );
+  }
+  paths
+    .into_iter()
+    .map(|path| {
+      let mut candidate = specifier.clone();
+      candidate.set_path(&path);
+      candidate
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use pretty_assertions::assert_eq;
+
+  use crate::TranspileGraphOptions;
+
+  #[test]
+  fn test_sloppy_candidates() {
+    let run = |specifier: &str| {
+      sloppy_candidates(&ModuleSpecifier::parse(specifier).unwrap())
+        .into_iter()
+        .map(|candidate| candidate.to_string())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      run("file:///a/utils"),
+      vec![
+        "file:///a/utils.ts",
+        "file:///a/utils.tsx",
+        "file:///a/utils.js",
+        "file:///a/utils.mts",
+        "file:///a/utils.d.ts",
+        "file:///a/utils/index.ts",
+        "file:///a/utils/index.tsx",
+        "file:///a/utils/index.js",
+        "file:///a/utils/index.mts",
+        "file:///a/utils/index.d.ts",
+      ]
+    );
+    assert_eq!(run("file:///a/lib/")[0], "file:///a/lib/index.ts");
+    assert_eq!(run("file:///a/lib/").len(), 5);
+  }
+
+  #[tokio::test]
+  async fn transpile_sloppy_imports() {
+    let sources = || {
+      vec![
+        (
+          "file:///a/mod.ts",
+          Source::Module {
+            specifier: "file:///a/mod.ts",
+            maybe_headers: None,
+            content: r#"import { a } from "./utils";
+import { b } from "./lib";
+console.log(a, b);
+"#,
+          },
+        ),
+        (
+          "file:///a/utils.ts",
+          Source::Module {
+            specifier: "file:///a/utils.ts",
+            maybe_headers: None,
+            content: "export const a: number = 1;",
+          },
+        ),
+        (
+          "file:///a/lib/index.js",
+          Source::Module {
+            specifier: "file:///a/lib/index.js",
+            maybe_headers: None,
+            content: "export const b = 2;",
+          },
+        ),
+      ]
+    };
+    let root = ModuleSpecifier::parse("file:///a/mod.ts").unwrap();
+    let options = TranspileGraphOptions {
+      emit_options: deno_ast::EmitOptions {
+        source_map: deno_ast::SourceMapOption::None,
+        ..Default::default()
+      },
+      rewrite_specifiers: true,
+      ..Default::default()
+    };
+
+    let mut loader = MemoryLoader::new(sources(), vec![]);
+    let err =
+      crate::transpile(vec![root.clone()], &mut loader, None, options.clone())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Module not found"), "{err}");
+
+    let mut loader =
+      SloppyImportsLoader::new(MemoryLoader::new(sources(), vec![]));
+    let output = crate::transpile(vec![root], &mut loader, None, options)
+      .await
+      .unwrap()
+      .into_files()
+      .unwrap();
+    assert_eq!(
+      output.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+      vec!["lib/index.js", "mod.js", "utils.js"]
+    );
+    assert_eq!(
+      String::from_utf8(output["mod.js"].clone()).unwrap(),
+      r#"import { a } from "./utils.js";
+import { b } from "./lib/index.js";
+console.log(a, b);
+"#
+    );
+    assert_eq!(
+      loader.warnings(),
+      vec![
+        "Sloppy import \"file:///a/lib\" was resolved to \"file:///a/lib/index.js\". Add the full path to the import.",
+        "Sloppy import \"file:///a/utils\" was resolved to \"file:///a/utils.ts\". Add the full path to the import.",
+      ]
+    );
+  }
+}
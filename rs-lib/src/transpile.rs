@@ -0,0 +1,2246 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::bail;
+use anyhow::Result;
+use deno_ast::swc::ast;
+use deno_ast::swc::ast::Program;
+use deno_ast::swc::common::comments::SingleThreadedComments;
+use deno_ast::swc::common::Mark;
+use deno_ast::swc::transforms::hygiene;
+use deno_ast::swc::transforms::react;
+use deno_ast::swc::transforms::resolver;
+use deno_ast::swc::visit::VisitMut;
+use deno_ast::swc::visit::VisitMutWith;
+use deno_ast::EmitOptions;
+use deno_ast::EmittedSourceBytes;
+use deno_ast::MediaType;
+use deno_ast::ModuleSpecifier;
+use deno_ast::MultiThreadedComments;
+use deno_ast::ParseParams;
+use deno_ast::ParsedSource;
+use deno_ast::SourceMap;
+use deno_ast::SourceMapOption;
+use deno_ast::TranspileOptions;
+use deno_graph::FastCheckDiagnosticRange;
+use deno_graph::FastCheckTypeModuleSlot;
+use deno_graph::JsModule;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use deno_graph::ParsedSourceStore;
+use indexmap::IndexSet;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::cache::options_hash;
+use crate::cache::source_hash;
+use crate::cache::CachedEmit;
+use crate::cache::CountingEmitCache;
+use crate::cache::EmitCache;
+use crate::cache::EmitCacheKey;
+use crate::cache::EmitCacheStats;
+use crate::commonjs::ensure_commonjs_compatible;
+use crate::commonjs::esm_to_commonjs;
+use crate::commonjs::ModuleFormat;
+use crate::coverage::instrument_coverage;
+use crate::filter::ModuleFilter;
+use crate::output_paths::declaration_path;
+use crate::output_paths::OutputPaths;
+use crate::rewrite::get_declaration_specifier_map;
+use crate::rewrite::get_specifier_map;
+use crate::rewrite::rewrite_specifiers;
+use crate::text::strip_bom;
+use crate::text::transform_json_source;
+use crate::visit::ClearContexts;
+
+#[derive(Clone, Default)]
+pub struct TranspileGraphOptions {
+  pub transpile_options: TranspileOptions,
+  pub emit_options: EmitOptions,
+  /// Transpile the modules of the graph concurrently on a pool of threads.
+  /// The output is identical to transpiling sequentially. This has no effect
+  /// in wasm builds, where modules are always transpiled sequentially.
+  pub parallel: bool,
+  /// A cache of previously emitted modules. Modules whose source and options
+  /// are unchanged are read from the cache instead of being transpiled.
+  pub cache: Option<Arc<dyn EmitCache>>,
+  /// Rewrite the specifiers of imports, re-exports and dynamic imports with a
+  /// string literal argument to the relative paths of the emitted files, so
+  /// the output can be written to disk and run as-is.
+  ///
+  /// When set, the keys of the output are the relative paths the modules
+  /// should be written to instead of their specifiers. Local modules keep
+  /// their layout relative to the closest directory containing all of them,
+  /// remote modules are placed in `remote/<host>/<path>`, and JSON modules
+  /// are emitted as-is so they can still be imported with a JSON import
+  /// attribute.
+  pub rewrite_specifiers: bool,
+  /// Emit a declaration file (and declaration map, depending on the source
+  /// map option) next to every module which has fast check type information
+  /// in the graph, similar to TypeScript's isolated declarations.
+  ///
+  /// The graph must have been built with types and have had its fast check
+  /// type graph built with `fast_check_dts` enabled, which [`crate::transpile`]
+  /// does when this is set. Modules whose public API needs explicit type
+  /// annotations fail the emit with an error listing what is missing.
+  pub declarations: bool,
+  /// Which modules to transpile and return. Every module of the graph is
+  /// still resolved and validated, and when rewriting specifiers, imports of
+  /// filtered out modules are rewritten to their absolute specifiers.
+  pub filter: ModuleFilter,
+  /// Emit JSON modules as-is instead of as JavaScript modules which export
+  /// the parsed JSON as their default export. JSON modules are always
+  /// emitted as-is when rewriting specifiers, because they are imported
+  /// with a JSON import attribute.
+  pub json_as_is: bool,
+  /// Pass the declaration files of the graph through to the output as-is.
+  /// Declaration files contain no code, so they are otherwise left out.
+  pub include_declaration_files: bool,
+  /// The format of the emitted JavaScript modules.
+  ///
+  /// Emitting CommonJS requires rewriting specifiers. Modules are emitted to
+  /// `.js` files, except for `.mjs` and `.mts` modules which are emitted to
+  /// `.cjs` files, and imports are rewritten to `require()` calls of those
+  /// files.
+  pub module_format: ModuleFormat,
+  /// Rewrite `npm:` specifiers to bare specifiers which are resolved from
+  /// `node_modules`, like `npm:preact@^10/hooks` to `preact/hooks`. This only
+  /// has an effect when rewriting specifiers.
+  pub bare_npm_specifiers: bool,
+  /// Register the components and hooks of JSX and TSX modules with React
+  /// Refresh, by injecting `$RefreshReg$()` and `$RefreshSig$()` calls like
+  /// `react-refresh/babel` does. The page has to define both functions
+  /// globally before the modules run, usually with `react-refresh/runtime`.
+  pub react_refresh: bool,
+  /// Instrument the modules the filter includes, usually
+  /// [`ModuleFilter::LocalOnly`], with istanbul-compatible statement,
+  /// function and branch counters, which are collected in
+  /// `globalThis.__coverage__`. The counters refer to the locations in the
+  /// original sources of the modules.
+  pub instrument_coverage: Option<ModuleFilter>,
+}
+
+/// The modules emitted by [`transpile_graph`], ordered by specifier.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranspileOutput {
+  pub modules: Vec<TranspiledModule>,
+  /// The lookups of the modules in [`TranspileGraphOptions::cache`].
+  pub cache_stats: EmitCacheStats,
+}
+
+impl TranspileOutput {
+  pub fn get(&self, specifier: &ModuleSpecifier) -> Option<&TranspiledModule> {
+    self
+      .modules
+      .binary_search_by(|module| {
+        module.specifier.as_str().cmp(specifier.as_str())
+      })
+      .ok()
+      .map(|index| &self.modules[index])
+  }
+
+  /// Flattens the output into files keyed by their output path, or by their
+  /// specifier when specifiers were not rewritten. Source maps are keyed by
+  /// the key of their file with a `.map` suffix. Errors when two files have
+  /// the same key, like a passed through `mod.d.ts` and the declaration
+  /// generated for `mod.ts`.
+  pub fn into_files(self) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    let mut specifiers_by_key: HashMap<String, ModuleSpecifier> =
+      HashMap::new();
+    let mut insert = |specifier: &ModuleSpecifier, key: String, file| {
+      if let Some(other) =
+        specifiers_by_key.insert(key.clone(), specifier.clone())
+      {
+        bail!(
+          "Modules \"{}\" and \"{}\" would both be emitted to \"{}\".",
+          other,
+          specifier,
+          key
+        );
+      }
+      files.insert(key, file);
+      Ok(())
+    };
+    for module in self.modules {
+      let specifier = &module.specifier;
+      let key = module
+        .maybe_output_path
+        .unwrap_or_else(|| specifier.to_string());
+      if let Some(declaration) = module.maybe_declaration {
+        let declaration_key = declaration_path(&key);
+        if let Some(source_map) = declaration.maybe_source_map {
+          insert(specifier, format!("{declaration_key}.map"), source_map)?;
+        }
+        insert(specifier, declaration_key, declaration.code)?;
+      }
+      if let Some(source_map) = module.maybe_source_map {
+        insert(specifier, format!("{key}.map"), source_map)?;
+      }
+      insert(specifier, key, module.code)?;
+    }
+    Ok(files)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranspiledModule {
+  pub specifier: ModuleSpecifier,
+  /// The path relative to the output directory that the module should be
+  /// written to, when specifiers were rewritten.
+  pub maybe_output_path: Option<String>,
+  /// The media type of the emitted code, which differs from that of the
+  /// module when it was transpiled.
+  pub media_type: MediaType,
+  pub code: Vec<u8>,
+  pub maybe_source_map: Option<Vec<u8>>,
+  pub maybe_declaration: Option<TranspiledDeclaration>,
+  /// The resolved specifiers of the modules the module depends on.
+  pub dependencies: Vec<ModuleSpecifier>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranspiledDeclaration {
+  pub media_type: MediaType,
+  pub code: Vec<u8>,
+  pub maybe_source_map: Option<Vec<u8>>,
+}
+
+/// Given a module graph, transpile every JavaScript and JSON module in the
+/// graph and return the emitted modules in memory.
+///
+/// When a parsed source store is provided (for example the
+/// `CapturingModuleAnalyzer` used to build the graph), parsed sources are
+/// taken out of the store instead of parsing the modules again. Modules that
+/// are not found in the store are parsed on demand.
+pub fn transpile_graph(
+  graph: &ModuleGraph,
+  maybe_parsed_source_store: Option<&dyn ParsedSourceStore>,
+  options: TranspileGraphOptions,
+) -> Result<TranspileOutput> {
+  let mut modules = BTreeMap::new();
+  let mut declarations = HashMap::new();
+  let mut jobs = Vec::new();
+  let mut job_modules = Vec::new();
+  if options.module_format == ModuleFormat::CommonJs
+    && !options.rewrite_specifiers
+  {
+    // the required files would keep the extensions of the modules, like
+    // `require("./mod.ts")`, which aren't the files that are emitted
+    bail!("Emitting CommonJS requires rewriting specifiers.");
+  }
+  let maybe_output_paths = if options.rewrite_specifiers {
+    Some(OutputPaths::new(
+      graph,
+      &options.filter,
+      options.module_format,
+    )?)
+  } else {
+    None
+  };
+  let output_path = |specifier: &ModuleSpecifier| {
+    maybe_output_paths
+      .as_ref()
+      .map(|output_paths| output_paths.get(specifier).unwrap().to_string())
+  };
+
+  let mut declaration_diagnostics = DeclarationDiagnostics::default();
+
+  for module in graph.modules() {
+    if !options.filter.includes(module.specifier()) {
+      continue;
+    }
+    match module {
+      Module::Js(module) if module.media_type.is_declaration() => {
+        if options.include_declaration_files {
+          let maybe_specifier_map =
+            maybe_output_paths.as_ref().map(|output_paths| {
+              get_declaration_specifier_map(
+                graph,
+                module,
+                output_paths,
+                options.bare_npm_specifiers,
+              )
+            });
+          let code = match maybe_specifier_map {
+            // the declaration file is passed through as-is, unless its
+            // specifiers have to refer to the output paths
+            Some(specifier_map) if !specifier_map.is_empty() => {
+              rewrite_declaration_file(
+                module,
+                maybe_parsed_source_store,
+                &specifier_map,
+                &options.emit_options,
+              )?
+            }
+            _ => module.source.as_bytes().to_vec(),
+          };
+          modules.insert(
+            module.specifier.to_string(),
+            TranspiledModule {
+              specifier: module.specifier.clone(),
+              maybe_output_path: output_path(&module.specifier),
+              media_type: module.media_type,
+              code,
+              maybe_source_map: None,
+              maybe_declaration: None,
+              dependencies: resolved_dependencies(graph, module),
+            },
+          );
+        }
+      }
+      Module::Js(module) => {
+        if options.declarations {
+          match &module.fast_check {
+            Some(FastCheckTypeModuleSlot::Module(fast_check_module)) => {
+              let Some(dts) = &fast_check_module.dts else {
+                bail!(
+                  "Missing declaration for \"{}\". The fast check type graph must be built with declarations.",
+                  module.specifier
+                );
+              };
+              if dts.diagnostics.is_empty() {
+                let maybe_specifier_map =
+                  maybe_output_paths.as_ref().map(|output_paths| {
+                    get_declaration_specifier_map(
+                      graph,
+                      module,
+                      output_paths,
+                      options.bare_npm_specifiers,
+                    )
+                  });
+                let emitted_declaration = emit_declaration(
+                  module,
+                  &dts.program,
+                  &dts.comments,
+                  maybe_specifier_map.as_ref(),
+                  &options.emit_options,
+                )?;
+                declarations.insert(
+                  module.specifier.clone(),
+                  TranspiledDeclaration {
+                    media_type: emitted_declaration_media_type(
+                      module.media_type,
+                      options.module_format,
+                    ),
+                    code: emitted_declaration.source,
+                    maybe_source_map: emitted_declaration.source_map,
+                  },
+                );
+              } else {
+                for diagnostic in &dts.diagnostics {
+                  declaration_diagnostics.add(
+                    diagnostic.specifier(),
+                    diagnostic,
+                    diagnostic.range(),
+                  );
+                }
+              }
+            }
+            Some(FastCheckTypeModuleSlot::Error(diagnostics)) => {
+              for diagnostic in diagnostics {
+                declaration_diagnostics.add(
+                  diagnostic.specifier(),
+                  diagnostic,
+                  diagnostic.range(),
+                );
+              }
+            }
+            // not part of the public API, like remote modules
+            None => {}
+          }
+        }
+        job_modules.push(module);
+        jobs.push(TranspileJob {
+          specifier: module.specifier.clone(),
+          source: module.source.clone(),
+          media_type: module.media_type,
+          maybe_parsed_source: maybe_parsed_source_store
+            .and_then(|store| store.remove_parsed_source(&module.specifier)),
+          maybe_refresh_module_id: None,
+          instrument_coverage: instruments_coverage(
+            &options,
+            &module.specifier,
+          ),
+          maybe_specifier_map: maybe_output_paths.as_ref().map(
+            |output_paths| {
+              get_specifier_map(
+                graph,
+                module,
+                output_paths,
+                options.bare_npm_specifiers,
+              )
+            },
+          ),
+        });
+      }
+      Module::Json(module) => {
+        let source = strip_bom(&module.source);
+        let (media_type, code) =
+          if maybe_output_paths.is_some() || options.json_as_is {
+            (MediaType::Json, source.as_bytes().to_vec())
+          } else {
+            (
+              MediaType::JavaScript,
+              transform_json_source(source).into_bytes(),
+            )
+          };
+        modules.insert(
+          module.specifier.to_string(),
+          TranspiledModule {
+            specifier: module.specifier.clone(),
+            maybe_output_path: output_path(&module.specifier),
+            media_type,
+            code,
+            maybe_source_map: None,
+            maybe_declaration: None,
+            dependencies: Vec::new(),
+          },
+        );
+      }
+      Module::Npm(_) | Module::Node(_) | Module::External(_) => {}
+    }
+  }
+
+  declaration_diagnostics.into_result()?;
+
+  let maybe_cache = JobCache::new(&options);
+  let results = transpile_jobs(jobs, &options, maybe_cache.as_ref());
+  for (module, result) in job_modules.into_iter().zip(results) {
+    let emitted_source = result?;
+    modules.insert(
+      module.specifier.to_string(),
+      TranspiledModule {
+        specifier: module.specifier.clone(),
+        maybe_output_path: output_path(&module.specifier),
+        media_type: emitted_media_type(
+          module.media_type,
+          options.module_format,
+        ),
+        code: emitted_source.source,
+        maybe_source_map: emitted_source.source_map,
+        maybe_declaration: declarations.remove(&module.specifier),
+        dependencies: resolved_dependencies(graph, module),
+      },
+    );
+  }
+
+  Ok(TranspileOutput {
+    modules: modules.into_values().collect(),
+    cache_stats: maybe_cache
+      .map(|job_cache| job_cache.cache.stats())
+      .unwrap_or_default(),
+  })
+}
+
+/// Transpiles a single module of a graph, rewriting its specifiers with the
+/// specifier map instead of the output paths of the graph.
+#[cfg(feature = "dev_server")]
+pub(crate) fn transpile_module(
+  module: &JsModule,
+  maybe_parsed_source: Option<ParsedSource>,
+  specifier_map: BTreeMap<String, String>,
+  module_id: &str,
+  options: &TranspileGraphOptions,
+) -> Result<EmittedSourceBytes> {
+  let job = TranspileJob {
+    specifier: module.specifier.clone(),
+    source: module.source.clone(),
+    media_type: module.media_type,
+    maybe_parsed_source,
+    maybe_specifier_map: Some(specifier_map),
+    maybe_refresh_module_id: options
+      .react_refresh
+      .then(|| module_id.to_string()),
+    instrument_coverage: instruments_coverage(options, &module.specifier),
+  };
+  job.transpile(options, JobCache::new(options).as_ref())
+}
+
+fn instruments_coverage(
+  options: &TranspileGraphOptions,
+  specifier: &ModuleSpecifier,
+) -> bool {
+  options
+    .instrument_coverage
+    .as_ref()
+    .is_some_and(|filter| filter.includes(specifier))
+}
+
+fn resolved_dependencies(
+  graph: &ModuleGraph,
+  module: &JsModule,
+) -> Vec<ModuleSpecifier> {
+  let mut dependencies = IndexSet::new();
+  for dependency in module.dependencies.values() {
+    if let Some(resolved) =
+      dependency.get_code().or_else(|| dependency.get_type())
+    {
+      dependencies.insert(graph.resolve(resolved).clone());
+    }
+  }
+  dependencies.into_iter().collect()
+}
+
+/// The media type of the JavaScript emitted for a module.
+fn emitted_media_type(
+  media_type: MediaType,
+  module_format: ModuleFormat,
+) -> MediaType {
+  match media_type {
+    MediaType::Mjs | MediaType::Mts if module_format == ModuleFormat::Esm => {
+      MediaType::Mjs
+    }
+    MediaType::Mjs | MediaType::Mts | MediaType::Cjs | MediaType::Cts => {
+      MediaType::Cjs
+    }
+    _ => MediaType::JavaScript,
+  }
+}
+
+/// The media type of the declaration file emitted for a module.
+fn emitted_declaration_media_type(
+  media_type: MediaType,
+  module_format: ModuleFormat,
+) -> MediaType {
+  match emitted_media_type(media_type, module_format) {
+    MediaType::Mjs => MediaType::Dmts,
+    MediaType::Cjs => MediaType::Dcts,
+    _ => MediaType::Dts,
+  }
+}
+
+fn emit_declaration(
+  module: &JsModule,
+  program: &Program,
+  comments: &MultiThreadedComments,
+  maybe_specifier_map: Option<&BTreeMap<String, String>>,
+  emit_options: &EmitOptions,
+) -> Result<EmittedSourceBytes> {
+  // the declaration's spans are in the original source, so the source map
+  // maps the declarations back to it
+  let source_map =
+    SourceMap::single(module.specifier.clone(), module.source.to_string());
+  let mut program = program.clone();
+  if let Some(specifier_map) = maybe_specifier_map {
+    rewrite_specifiers(&mut program, specifier_map);
+  }
+  Ok(deno_ast::emit(
+    &program,
+    &comments.as_single_threaded(),
+    &source_map,
+    emit_options,
+  )?)
+}
+
+/// Emits the declaration file with its specifiers rewritten. It has no
+/// source map, like the declaration files which are passed through as-is.
+fn rewrite_declaration_file(
+  module: &JsModule,
+  maybe_parsed_source_store: Option<&dyn ParsedSourceStore>,
+  specifier_map: &BTreeMap<String, String>,
+  emit_options: &EmitOptions,
+) -> Result<Vec<u8>> {
+  let parsed_source = match maybe_parsed_source_store
+    .and_then(|store| store.remove_parsed_source(&module.specifier))
+  {
+    Some(parsed_source) => parsed_source,
+    None => deno_ast::parse_module(ParseParams {
+      specifier: module.specifier.clone(),
+      text: module.source.clone(),
+      media_type: module.media_type,
+      capture_tokens: false,
+      scope_analysis: false,
+      maybe_syntax: None,
+    })?,
+  };
+  let emitted = emit_declaration(
+    module,
+    &parsed_source.program(),
+    parsed_source.comments(),
+    Some(specifier_map),
+    &EmitOptions {
+      source_map: SourceMapOption::None,
+      ..emit_options.clone()
+    },
+  )?;
+  Ok(emitted.source)
+}
+
+/// The reasons declarations could not be emitted, grouped by module.
+#[derive(Default)]
+pub(crate) struct DeclarationDiagnostics(BTreeMap<String, BTreeSet<String>>);
+
+impl DeclarationDiagnostics {
+  pub fn add(
+    &mut self,
+    specifier: &ModuleSpecifier,
+    message: &dyn std::fmt::Display,
+    maybe_range: Option<&FastCheckDiagnosticRange>,
+  ) {
+    let message = match maybe_range {
+      Some(range) => {
+        let position =
+          range.text_info.line_and_column_display(range.range.start);
+        format!(
+          "{message} at {}:{}:{}",
+          range.specifier, position.line_number, position.column_number
+        )
+      }
+      None => message.to_string(),
+    };
+    // errors are copied to every module which exposes them, so dedupe them
+    self
+      .0
+      .entry(specifier.to_string())
+      .or_default()
+      .insert(message);
+  }
+
+  pub fn into_result(self) -> Result<()> {
+    if self.0.is_empty() {
+      return Ok(());
+    }
+    let mut message = "Failed emitting declarations. Add explicit types to the public API of the following modules:".to_string();
+    for (specifier, messages) in self.0 {
+      message.push_str(&format!("\n\n{specifier}"));
+      for diagnostic in messages {
+        message.push_str(&format!("\n  {diagnostic}"));
+      }
+    }
+    bail!(message)
+  }
+}
+
+/// A JavaScript module to transpile, detached from the module graph so it
+/// can be sent to another thread.
+struct TranspileJob {
+  specifier: ModuleSpecifier,
+  source: Arc<str>,
+  media_type: MediaType,
+  maybe_parsed_source: Option<ParsedSource>,
+  maybe_specifier_map: Option<BTreeMap<String, String>>,
+  /// Prefix the React Refresh registrations with the id, for modules which
+  /// aren't registered by a runtime that scopes them to the module.
+  maybe_refresh_module_id: Option<String>,
+  instrument_coverage: bool,
+}
+
+struct JobCache<'a> {
+  cache: CountingEmitCache<'a>,
+  options_hash: String,
+}
+
+impl<'a> JobCache<'a> {
+  fn new(options: &'a TranspileGraphOptions) -> Option<Self> {
+    options.cache.as_deref().map(|cache| JobCache {
+      cache: CountingEmitCache::new(cache),
+      options_hash: options_hash(&[
+        &options.transpile_options,
+        &options.emit_options,
+        &options.module_format,
+        &options.react_refresh,
+      ]),
+    })
+  }
+}
+
+impl TranspileJob {
+  fn transpile(
+    self,
+    options: &TranspileGraphOptions,
+    maybe_cache: Option<&JobCache>,
+  ) -> Result<EmittedSourceBytes> {
+    let Some(job_cache) = maybe_cache else {
+      return self.transpile_uncached(options);
+    };
+    let mut job_options_hash = match &self.maybe_specifier_map {
+      // the rewritten specifiers affect the output like an option would
+      Some(specifier_map) => {
+        options_hash(&[&job_cache.options_hash, specifier_map])
+      }
+      None => job_cache.options_hash.clone(),
+    };
+    if let Some(module_id) = &self.maybe_refresh_module_id {
+      job_options_hash = options_hash(&[&job_options_hash, module_id]);
+    }
+    if self.instrument_coverage {
+      job_options_hash =
+        options_hash(&[&job_options_hash, &"instrument_coverage"]);
+    }
+    let key = EmitCacheKey {
+      specifier: self.specifier.clone(),
+      source_hash: source_hash(&self.source),
+      options_hash: job_options_hash,
+    };
+    if let Some(cached) = job_cache.cache.get(&key) {
+      return Ok(EmittedSourceBytes {
+        source: cached.code,
+        source_map: cached.maybe_map,
+      });
+    }
+    let emitted_source = self.transpile_uncached(options)?;
+    job_cache.cache.set(
+      &key,
+      &CachedEmit {
+        code: emitted_source.source.clone(),
+        maybe_map: emitted_source.source_map.clone(),
+      },
+    );
+    Ok(emitted_source)
+  }
+
+  fn transpile_uncached(
+    self,
+    options: &TranspileGraphOptions,
+  ) -> Result<EmittedSourceBytes> {
+    let parsed_source = match self.maybe_parsed_source {
+      Some(parsed_source) => parsed_source,
+      None => deno_ast::parse_module(ParseParams {
+        specifier: self.specifier,
+        text: self.source,
+        media_type: self.media_type,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+      })?,
+    };
+    let is_commonjs = options.module_format == ModuleFormat::CommonJs;
+    if is_commonjs {
+      ensure_commonjs_compatible(&parsed_source)?;
+    }
+    if self.maybe_specifier_map.is_none()
+      && !is_commonjs
+      && !options.react_refresh
+      && !self.instrument_coverage
+    {
+      return Ok(
+        parsed_source
+          .transpile(&options.transpile_options, &options.emit_options)?
+          .into_source(),
+      );
+    }
+    transpile_with(
+      &parsed_source,
+      &options.transpile_options,
+      &options.emit_options,
+      ProgramTransforms {
+        react_refresh: options.react_refresh,
+        instrument_coverage: self.instrument_coverage,
+      },
+      |program| {
+        if let Some(specifier_map) = &self.maybe_specifier_map {
+          rewrite_specifiers(program, specifier_map);
+        }
+        if let Some(module_id) = &self.maybe_refresh_module_id {
+          program.visit_mut_with(&mut ScopeRefreshRegistrations(module_id));
+        }
+        if is_commonjs {
+          esm_to_commonjs(program)?;
+        }
+        Ok(())
+      },
+    )
+  }
+}
+
+/// The transforms of [`transpile_with`] which aren't part of the transpile
+/// options.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ProgramTransforms {
+  /// Inject the React Refresh registrations into JSX and TSX modules before
+  /// they're folded.
+  pub react_refresh: bool,
+  /// Instrument the module with coverage counters after it's folded.
+  pub instrument_coverage: bool,
+}
+
+/// Transpiles the parsed source like `ParsedSource::transpile`, but allows
+/// modifying the program after it has been folded and before it's emitted.
+///
+/// This copies `transpile` and `resolve_transpile_options` of deno_ast 0.42.0
+/// (`src/transpiling/mod.rs`), so it has to be compared with them again when
+/// deno_ast is upgraded. `transpile_with_matches_parsed_source` checks that
+/// the output of both is the same for plain modules.
+pub(crate) fn transpile_with(
+  parsed_source: &ParsedSource,
+  transpile_options: &TranspileOptions,
+  emit_options: &EmitOptions,
+  transforms: ProgramTransforms,
+  modify_program: impl FnOnce(&mut Program) -> Result<()>,
+) -> Result<EmittedSourceBytes> {
+  if transpile_options.use_decorators_proposal
+    && transpile_options.use_ts_decorators
+  {
+    bail!("Can't use TranspileOptions::use_decorators_proposal and TranspileOptions::use_ts_decorators together.");
+  }
+  let transpile_options = if transpile_options.transform_jsx
+    && !matches!(parsed_source.media_type(), MediaType::Jsx | MediaType::Tsx)
+  {
+    // the source cannot contain jsx, so skip the jsx transforms like
+    // `ParsedSource::transpile` does
+    Cow::Owned(TranspileOptions {
+      transform_jsx: false,
+      ..transpile_options.clone()
+    })
+  } else {
+    Cow::Borrowed(transpile_options)
+  };
+  let source_map = SourceMap::single(
+    parsed_source.specifier().clone(),
+    parsed_source.text().to_string(),
+  );
+  let comments = parsed_source.comments().as_single_threaded();
+  let program = parsed_source.globals().with(|marks| {
+    let mut program = (*parsed_source.program()).clone();
+    if transforms.react_refresh
+      && matches!(parsed_source.media_type(), MediaType::Jsx | MediaType::Tsx)
+    {
+      add_react_refresh(&mut program, &source_map, &comments);
+    }
+    let mut program = deno_ast::fold_program(
+      program,
+      &transpile_options,
+      &source_map,
+      &comments,
+      marks,
+      parsed_source.diagnostics(),
+    )?;
+    if transforms.instrument_coverage {
+      instrument_coverage(
+        &mut program,
+        parsed_source.specifier(),
+        source_map.inner(),
+      )?;
+    }
+    modify_program(&mut program)?;
+    Ok::<_, anyhow::Error>(program)
+  })?;
+  Ok(deno_ast::emit(
+    &program,
+    &comments,
+    &source_map,
+    emit_options,
+  )?)
+}
+
+/// Injects the registrations of the components and hooks of the module,
+/// which the JSX transform of the folding would otherwise have to do.
+fn add_react_refresh(
+  program: &mut Program,
+  source_map: &SourceMap,
+  comments: &SingleThreadedComments,
+) {
+  let top_level_mark = Mark::new();
+  program.visit_mut_with(&mut resolver(Mark::new(), top_level_mark, true));
+  program.visit_mut_with(&mut react::refresh(
+    true,
+    Some(react::RefreshOptions::default()),
+    source_map.inner().clone(),
+    Some(comments),
+    top_level_mark,
+  ));
+  // the injected bindings get unique names, after which the program is
+  // resolved again by the folding
+  program.visit_mut_with(&mut hygiene());
+  program.visit_mut_with(&mut ClearContexts);
+}
+
+/// Prefixes the names of the `$RefreshReg$()` calls with the id of the
+/// module, like `"/src/app.tsx App"`, so components with the same name in
+/// different modules don't replace each other.
+struct ScopeRefreshRegistrations<'a>(&'a str);
+
+impl VisitMut for ScopeRefreshRegistrations<'_> {
+  fn visit_mut_call_expr(&mut self, call: &mut ast::CallExpr) {
+    let is_registration = match &call.callee {
+      ast::Callee::Expr(callee) => callee
+        .as_ident()
+        .is_some_and(|ident| &*ident.sym == "$RefreshReg$"),
+      _ => false,
+    };
+    if is_registration {
+      if let Some(ast::ExprOrSpread { expr, .. }) = call.args.get_mut(1) {
+        if let ast::Expr::Lit(ast::Lit::Str(name)) = &mut **expr {
+          name.value = format!("{} {}", self.0, name.value).into();
+          name.raw = None;
+        }
+      }
+    }
+    call.visit_mut_children_with(self);
+  }
+}
+
+/// Transpiles the jobs, returning the results in the same order as the jobs.
+fn transpile_jobs(
+  jobs: Vec<TranspileJob>,
+  options: &TranspileGraphOptions,
+  maybe_cache: Option<&JobCache>,
+) -> Vec<Result<EmittedSourceBytes>> {
+  #[cfg(not(target_arch = "wasm32"))]
+  if options.parallel && jobs.len() > 1 {
+    return transpile_jobs_parallel(jobs, options, maybe_cache);
+  }
+
+  jobs
+    .into_iter()
+    .map(|job| job.transpile(options, maybe_cache))
+    .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn transpile_jobs_parallel(
+  jobs: Vec<TranspileJob>,
+  options: &TranspileGraphOptions,
+  maybe_cache: Option<&JobCache>,
+) -> Vec<Result<EmittedSourceBytes>> {
+  let len = jobs.len();
+  let thread_count = std::thread::available_parallelism()
+    .map(|count| count.get())
+    .unwrap_or(1)
+    .min(len);
+  let jobs = parking_lot::Mutex::new(jobs.into_iter().enumerate());
+  let mut results = std::iter::repeat_with(|| None)
+    .take(len)
+    .collect::<Vec<_>>();
+
+  std::thread::scope(|scope| {
+    let handles = (0..thread_count)
+      .map(|_| {
+        scope.spawn(|| {
+          let mut results = Vec::new();
+          loop {
+            // hold the lock only while taking the next job
+            let maybe_job = jobs.lock().next();
+            let Some((index, job)) = maybe_job else {
+              break;
+            };
+            results.push((index, job.transpile(options, maybe_cache)));
+          }
+          results
+        })
+      })
+      .collect::<Vec<_>>();
+    for handle in handles {
+      let thread_results = match handle.join() {
+        Ok(thread_results) => thread_results,
+        Err(panic) => std::panic::resume_unwind(panic),
+      };
+      for (index, result) in thread_results {
+        results[index] = Some(result);
+      }
+    }
+  });
+
+  results
+    .into_iter()
+    .map(|result| result.expect("every job should have been transpiled"))
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use deno_ast::EmitOptions;
+  use deno_ast::MediaType;
+  use deno_ast::ModuleSpecifier;
+  use deno_ast::ParseParams;
+  use deno_ast::SourceMapOption;
+  use deno_ast::TranspileOptions;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use deno_graph::BuildOptions;
+  use deno_graph::CapturingModuleAnalyzer;
+  use deno_graph::GraphKind;
+  use deno_graph::ModuleGraph;
+  use deno_graph::ParsedSourceStore;
+  use pretty_assertions::assert_eq;
+  use std::sync::Arc;
+
+  use super::transpile_with;
+  use super::ProgramTransforms;
+  use crate::transpile_graph;
+  use crate::EmitCacheStats;
+  use crate::FsEmitCache;
+  use crate::ModuleFilter;
+  use crate::ModuleFormat;
+  use crate::TranspileGraphOptions;
+  use crate::TranspileOutput;
+  use crate::TranspiledModule;
+
+  async fn setup<S: AsRef<str> + Copy>(
+    root: S,
+    sources: Vec<(S, Source<S>)>,
+  ) -> (ModuleGraph, CapturingModuleAnalyzer) {
+    let memory_loader = MemoryLoader::new(sources, vec![]);
+    let root = ModuleSpecifier::parse(root.as_ref()).unwrap();
+    let analyzer = CapturingModuleAnalyzer::default();
+    let mut graph = ModuleGraph::new(GraphKind::CodeOnly);
+    graph
+      .build(
+        vec![root],
+        &memory_loader,
+        BuildOptions {
+          module_analyzer: &analyzer,
+          ..Default::default()
+        },
+      )
+      .await;
+    (graph, analyzer)
+  }
+
+  fn get_module<'a>(
+    output: &'a TranspileOutput,
+    specifier: &str,
+  ) -> &'a TranspiledModule {
+    output
+      .get(&ModuleSpecifier::parse(specifier).unwrap())
+      .unwrap()
+  }
+
+  fn sources() -> Vec<(&'static str, Source<&'static str>)> {
+    vec![
+      (
+        "file:///a/mod.ts",
+        Source::Module {
+          specifier: "file:///a/mod.ts",
+          maybe_headers: None,
+          content: r#"import data from "./data.json" with { type: "json" };
+export const value: string = data.value;
+"#,
+        },
+      ),
+      (
+        "file:///a/data.json",
+        Source::Module {
+          specifier: "file:///a/data.json",
+          maybe_headers: None,
+          content: r#"{ "value": "a" }"#,
+        },
+      ),
+    ]
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_with_store() {
+    let (graph, analyzer) = setup("file:///a/mod.ts", sources()).await;
+    let output =
+      transpile_graph(&graph, Some(&analyzer), Default::default()).unwrap();
+
+    assert_eq!(
+      String::from_utf8(
+        get_module(&output, "file:///a/data.json").code.clone()
+      )
+      .unwrap(),
+      r#"export default JSON.parse("{ \"value\": \"a\" }");"#
+    );
+    let code =
+      String::from_utf8(get_module(&output, "file:///a/mod.ts").code.clone())
+        .unwrap();
+    assert!(code.starts_with(
+      r#"import data from "./data.json" with {
+  type: "json"
+};
+export const value = data.value;
+"#
+    ));
+    // the parsed source should have been taken out of the store
+    assert!(analyzer
+      .get_parsed_source(&ModuleSpecifier::parse("file:///a/mod.ts").unwrap())
+      .is_none());
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_without_store() {
+    let (graph, analyzer) = setup("file:///a/mod.ts", sources()).await;
+    let expected =
+      transpile_graph(&graph, Some(&analyzer), Default::default()).unwrap();
+    let output = transpile_graph(&graph, None, Default::default()).unwrap();
+
+    assert_eq!(output, expected);
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_parallel() {
+    let mut root_source = String::new();
+    let mut sources = Vec::new();
+    for i in 0..20 {
+      root_source.push_str(&format!("import \"./mod{i}.ts\";\n"));
+      sources.push((
+        format!("file:///a/mod{i}.ts"),
+        format!("export const value{i}: number = {i};\n"),
+      ));
+    }
+    sources.push(("file:///a/mod.ts".to_string(), root_source));
+    let sources = sources
+      .iter()
+      .map(|(specifier, content)| {
+        (
+          specifier.as_str(),
+          Source::Module {
+            specifier: specifier.as_str(),
+            maybe_headers: None,
+            content: content.as_str(),
+          },
+        )
+      })
+      .collect();
+    let (graph, _) = setup("file:///a/mod.ts", sources).await;
+
+    let expected = transpile_graph(&graph, None, Default::default()).unwrap();
+    let output = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        parallel: true,
+        ..Default::default()
+      },
+    )
+    .unwrap();
+
+    assert_eq!(output.modules.len(), 21);
+    assert_eq!(output, expected);
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_cache() {
+    let dir = std::env::temp_dir()
+      .join(format!("deno_emit_transpile_cache_{}", std::process::id()));
+    let cache = Arc::new(FsEmitCache::new(&dir));
+    let (graph, _) = setup("file:///a/mod.ts", sources()).await;
+    let options = TranspileGraphOptions {
+      cache: Some(cache.clone()),
+      ..Default::default()
+    };
+
+    let expected = transpile_graph(&graph, None, options.clone()).unwrap();
+    assert_eq!(expected.cache_stats, EmitCacheStats { hits: 0, misses: 1 });
+    let output = transpile_graph(&graph, None, options).unwrap();
+    assert_eq!(output.cache_stats, EmitCacheStats { hits: 1, misses: 0 });
+    assert_eq!(cache.stats(), EmitCacheStats { hits: 1, misses: 1 });
+    assert_eq!(output.modules, expected.modules);
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_rewrite_specifiers() {
+    let sources = vec![
+      (
+        "file:///a/mod.ts",
+        Source::Module {
+          specifier: "file:///a/mod.ts",
+          maybe_headers: None,
+          content: r#"import { b } from "./sub/b.ts";
+import data from "./data.json" with { type: "json" };
+export * from "https://example.com/c.ts";
+const d = await import("./sub/d.tsx");
+console.log(b, data, d);
+"#,
+        },
+      ),
+      (
+        "file:///a/sub/b.ts",
+        Source::Module {
+          specifier: "file:///a/sub/b.ts",
+          maybe_headers: None,
+          content: r#"export { c as b } from "https://example.com/c.ts";"#,
+        },
+      ),
+      (
+        "file:///a/sub/d.tsx",
+        Source::Module {
+          specifier: "file:///a/sub/d.tsx",
+          maybe_headers: None,
+          content: "export default 1;",
+        },
+      ),
+      (
+        "file:///a/data.json",
+        Source::Module {
+          specifier: "file:///a/data.json",
+          maybe_headers: None,
+          content: "{}",
+        },
+      ),
+      (
+        "https://example.com/c.ts",
+        Source::Module {
+          specifier: "https://example.com/c.ts",
+          maybe_headers: None,
+          content: "export const c: number = 1;",
+        },
+      ),
+    ];
+    let (graph, _) = setup("file:///a/mod.ts", sources).await;
+    let output = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        rewrite_specifiers: true,
+        ..Default::default()
+      },
+    )
+    .unwrap();
+
+    let module = get_module(&output, "file:///a/mod.ts");
+    assert_eq!(module.maybe_output_path.as_deref(), Some("mod.js"));
+    assert_eq!(
+      module.dependencies,
+      vec![
+        ModuleSpecifier::parse("file:///a/sub/b.ts").unwrap(),
+        ModuleSpecifier::parse("file:///a/data.json").unwrap(),
+        ModuleSpecifier::parse("https://example.com/c.ts").unwrap(),
+        ModuleSpecifier::parse("file:///a/sub/d.tsx").unwrap(),
+      ]
+    );
+    assert_eq!(
+      get_module(&output, "file:///a/data.json").media_type,
+      MediaType::Json
+    );
+
+    let files = output.into_files().unwrap();
+    assert_eq!(
+      files.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+      vec![
+        "data.json",
+        "mod.js",
+        "remote/example.com/c.js",
+        "sub/b.js",
+        "sub/d.js"
+      ]
+    );
+    assert_eq!(files["data.json"], b"{}");
+    assert_eq!(
+      String::from_utf8(files["mod.js"].clone()).unwrap(),
+      r#"import { b } from "./sub/b.js";
+import data from "./data.json" with {
+  type: "json"
+};
+export * from "./remote/example.com/c.js";
+const d = await import("./sub/d.js");
+console.log(b, data, d);
+"#
+    );
+    assert_eq!(
+      String::from_utf8(files["sub/b.js"].clone()).unwrap(),
+      "export { c as b } from \"../remote/example.com/c.js\";\n"
+    );
+  }
+
+  fn declaration_sources(
+    b_source: &'static str,
+  ) -> Vec<(&'static str, Source<&'static str>)> {
+    vec![
+      (
+        "file:///a/mod.ts",
+        Source::Module {
+          specifier: "file:///a/mod.ts",
+          maybe_headers: None,
+          content: r#"import type { B } from "./sub/b.ts";
+export { b } from "./sub/b.ts";
+export function getB(): B {
+  return { value: 1 };
+}
+"#,
+        },
+      ),
+      (
+        "file:///a/sub/b.ts",
+        Source::Module {
+          specifier: "file:///a/sub/b.ts",
+          maybe_headers: None,
+          content: b_source,
+        },
+      ),
+    ]
+  }
+
+  #[tokio::test]
+  async fn transpile_declarations() {
+    let mut loader = MemoryLoader::new(
+      declaration_sources(
+        "export interface B { value: number }\nexport const b: B = { value: 2 };\n",
+      ),
+      vec![],
+    );
+    let output = crate::transpile(
+      vec![ModuleSpecifier::parse("file:///a/mod.ts").unwrap()],
+      &mut loader,
+      None,
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::Separate,
+          ..Default::default()
+        },
+        rewrite_specifiers: true,
+        declarations: true,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+    let module = get_module(&output, "file:///a/mod.ts");
+    assert_eq!(
+      module.maybe_declaration.as_ref().unwrap().media_type,
+      MediaType::Dts
+    );
+
+    let files = output.into_files().unwrap();
+    assert_eq!(
+      files.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+      vec![
+        "mod.d.ts",
+        "mod.d.ts.map",
+        "mod.js",
+        "mod.js.map",
+        "sub/b.d.ts",
+        "sub/b.d.ts.map",
+        "sub/b.js",
+        "sub/b.js.map"
+      ]
+    );
+    assert_eq!(
+      String::from_utf8(files["mod.d.ts"].clone()).unwrap(),
+      r#"import type { B } from "./sub/b.js";
+export { b } from "./sub/b.js";
+export declare function getB(): B;
+"#
+    );
+    assert_eq!(
+      String::from_utf8(files["sub/b.d.ts"].clone()).unwrap(),
+      r#"export interface B {
+  value: number;
+}
+export declare const b: B;
+"#
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_declarations_same_path() {
+    let mut loader = MemoryLoader::new(
+      vec![
+        (
+          "file:///a/mod.ts",
+          Source::Module {
+            specifier: "file:///a/mod.ts",
+            maybe_headers: None,
+            content: r#"import type { A } from "./types.d.ts";
+export const a: A = 1;
+"#,
+          },
+        ),
+        (
+          "file:///a/types.d.ts",
+          Source::Module {
+            specifier: "file:///a/types.d.ts",
+            maybe_headers: None,
+            content: "export type A = number;",
+          },
+        ),
+        (
+          "file:///a/mod.d.ts",
+          Source::Module {
+            specifier: "file:///a/mod.d.ts",
+            maybe_headers: None,
+            content: "export declare const a: number;",
+          },
+        ),
+      ],
+      vec![],
+    );
+    let output = crate::transpile(
+      vec![
+        ModuleSpecifier::parse("file:///a/mod.ts").unwrap(),
+        ModuleSpecifier::parse("file:///a/mod.d.ts").unwrap(),
+      ],
+      &mut loader,
+      None,
+      TranspileGraphOptions {
+        rewrite_specifiers: true,
+        declarations: true,
+        include_declaration_files: true,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      output.into_files().unwrap_err().to_string(),
+      r#"Modules "file:///a/mod.d.ts" and "file:///a/mod.ts" would both be emitted to "mod.d.ts"."#
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_declarations_missing_types() {
+    let mut loader = MemoryLoader::new(
+      declaration_sources(
+        "export type B = { value: number };\nexport function b() {\n  return 2;\n}\n",
+      ),
+      vec![],
+    );
+    let err = crate::transpile(
+      vec![ModuleSpecifier::parse("file:///a/mod.ts").unwrap()],
+      &mut loader,
+      None,
+      TranspileGraphOptions {
+        declarations: true,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+      err.to_string(),
+      r#"Failed emitting declarations. Add explicit types to the public API of the following modules:
+
+file:///a/sub/b.ts
+  missing explicit return type in the public API at file:///a/sub/b.ts:2:17"#
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_multiple_roots() {
+    let mut loader = MemoryLoader::new(
+      vec![
+        (
+          "file:///a/a_test.ts",
+          Source::Module {
+            specifier: "file:///a/a_test.ts",
+            maybe_headers: None,
+            content: r#"import { shared } from "./shared.ts";
+shared("a");
+"#,
+          },
+        ),
+        (
+          "file:///a/b_test.ts",
+          Source::Module {
+            specifier: "file:///a/b_test.ts",
+            maybe_headers: None,
+            content: r#"import { shared } from "./shared.ts";
+shared("b");
+"#,
+          },
+        ),
+        (
+          "file:///a/shared.ts",
+          Source::Module {
+            specifier: "file:///a/shared.ts",
+            maybe_headers: None,
+            content: "export function shared(value: string) {}",
+          },
+        ),
+      ],
+      vec![],
+    );
+    let output = crate::transpile(
+      vec![
+        ModuleSpecifier::parse("file:///a/a_test.ts").unwrap(),
+        ModuleSpecifier::parse("file:///a/b_test.ts").unwrap(),
+      ],
+      &mut loader,
+      None,
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      output
+        .modules
+        .iter()
+        .map(|module| module.specifier.as_str())
+        .collect::<Vec<_>>(),
+      vec![
+        "file:///a/a_test.ts",
+        "file:///a/b_test.ts",
+        "file:///a/shared.ts"
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_filter() {
+    let sources = vec![
+      (
+        "file:///a/mod.ts",
+        Source::Module {
+          specifier: "file:///a/mod.ts",
+          maybe_headers: None,
+          content: r#"import { b } from "./b.ts";
+export * from "https://example.com/c.ts";
+console.log(b);
+"#,
+        },
+      ),
+      (
+        "file:///a/b.ts",
+        Source::Module {
+          specifier: "file:///a/b.ts",
+          maybe_headers: None,
+          content: "export const b = 1;",
+        },
+      ),
+      (
+        "https://example.com/c.ts",
+        Source::Module {
+          specifier: "https://example.com/c.ts",
+          maybe_headers: None,
+          content: "export const c: number = 1;",
+        },
+      ),
+    ];
+    let (graph, _) = setup("file:///a/mod.ts", sources).await;
+    let options = |filter| TranspileGraphOptions {
+      emit_options: deno_ast::EmitOptions {
+        source_map: deno_ast::SourceMapOption::None,
+        ..Default::default()
+      },
+      filter,
+      ..Default::default()
+    };
+
+    let output =
+      transpile_graph(&graph, None, options(ModuleFilter::RemoteOnly)).unwrap();
+    assert_eq!(
+      output
+        .modules
+        .iter()
+        .map(|module| module.specifier.as_str())
+        .collect::<Vec<_>>(),
+      vec!["https://example.com/c.ts"]
+    );
+
+    let output = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        rewrite_specifiers: true,
+        ..options(ModuleFilter::LocalOnly)
+      },
+    )
+    .unwrap()
+    .into_files()
+    .unwrap();
+    assert_eq!(
+      output.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+      vec!["b.js", "mod.js"]
+    );
+    // the filtered out module is imported from its original location
+    assert_eq!(
+      String::from_utf8(output["mod.js"].clone()).unwrap(),
+      r#"import { b } from "./b.js";
+export * from "https://example.com/c.ts";
+console.log(b);
+"#
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_json_and_declaration_files() {
+    let mut sources = sources();
+    sources[0].1 = Source::Module {
+      specifier: "file:///a/mod.ts",
+      maybe_headers: None,
+      content: r#"import type { Data } from "./types.d.ts";
+import data from "./data.json" with { type: "json" };
+export const value: Data = data;
+"#,
+    };
+    sources.push((
+      "file:///a/types.d.ts",
+      Source::Module {
+        specifier: "file:///a/types.d.ts",
+        maybe_headers: None,
+        content: "export interface Data { value: string }",
+      },
+    ));
+    let memory_loader = MemoryLoader::new(sources, vec![]);
+    let mut graph = ModuleGraph::new(GraphKind::All);
+    graph
+      .build(
+        vec![ModuleSpecifier::parse("file:///a/mod.ts").unwrap()],
+        &memory_loader,
+        Default::default(),
+      )
+      .await;
+    let options = TranspileGraphOptions {
+      emit_options: deno_ast::EmitOptions {
+        source_map: deno_ast::SourceMapOption::None,
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+
+    let output = transpile_graph(&graph, None, options.clone()).unwrap();
+    assert_eq!(
+      output
+        .modules
+        .iter()
+        .map(|module| module.specifier.as_str())
+        .collect::<Vec<_>>(),
+      vec!["file:///a/data.json", "file:///a/mod.ts"]
+    );
+    assert_eq!(
+      get_module(&output, "file:///a/data.json").media_type,
+      MediaType::JavaScript
+    );
+    assert_eq!(
+      get_module(&output, "file:///a/mod.ts").media_type,
+      MediaType::JavaScript
+    );
+
+    let output = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        json_as_is: true,
+        include_declaration_files: true,
+        ..options
+      },
+    )
+    .unwrap();
+    assert_eq!(output.modules.len(), 3);
+    assert_eq!(
+      get_module(&output, "file:///a/data.json").media_type,
+      MediaType::Json
+    );
+    assert_eq!(
+      get_module(&output, "file:///a/data.json").code,
+      br#"{ "value": "a" }"#
+    );
+    assert_eq!(
+      get_module(&output, "file:///a/types.d.ts").media_type,
+      MediaType::Dts
+    );
+    assert_eq!(
+      get_module(&output, "file:///a/types.d.ts").code,
+      b"export interface Data { value: string }"
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_rewrite_declaration_files() {
+    let mut sources = sources();
+    sources[0].1 = Source::Module {
+      specifier: "file:///a/mod.ts",
+      maybe_headers: None,
+      content: r#"import type { Data } from "./types.d.ts";
+export const value: Data = { value: "a" };
+"#,
+    };
+    sources.push((
+      "file:///a/types.d.ts",
+      Source::Module {
+        specifier: "file:///a/types.d.ts",
+        maybe_headers: None,
+        content: r#"// the data of a module
+import type { Value } from "./value.ts";
+export interface Data { value: Value }
+"#,
+      },
+    ));
+    sources.push((
+      "file:///a/value.ts",
+      Source::Module {
+        specifier: "file:///a/value.ts",
+        maybe_headers: None,
+        content: "export type Value = string;",
+      },
+    ));
+    let memory_loader = MemoryLoader::new(sources, vec![]);
+    let analyzer = CapturingModuleAnalyzer::default();
+    let mut graph = ModuleGraph::new(GraphKind::All);
+    graph
+      .build(
+        vec![ModuleSpecifier::parse("file:///a/mod.ts").unwrap()],
+        &memory_loader,
+        BuildOptions {
+          module_analyzer: &analyzer,
+          ..Default::default()
+        },
+      )
+      .await;
+    let options = TranspileGraphOptions {
+      include_declaration_files: true,
+      rewrite_specifiers: true,
+      ..Default::default()
+    };
+
+    let output =
+      transpile_graph(&graph, Some(&analyzer), options.clone()).unwrap();
+    let module = get_module(&output, "file:///a/types.d.ts");
+    assert_eq!(module.maybe_output_path.as_deref(), Some("types.d.ts"));
+    assert_eq!(module.maybe_source_map, None);
+    assert_eq!(
+      String::from_utf8(module.code.clone()).unwrap(),
+      r#"// the data of a module
+import type { Value } from "./value.js";
+export interface Data {
+  value: Value;
+}
+"#
+    );
+    assert_eq!(
+      transpile_graph(&graph, None, options).unwrap().modules,
+      output.modules
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_commonjs() {
+    let sources = vec![
+      (
+        "file:///a/mod.ts",
+        Source::Module {
+          specifier: "file:///a/mod.ts",
+          maybe_headers: None,
+          content: r#"import def, { b as c, inc } from "./b.mts";
+import * as ns from "./b.mts";
+import data from "./data.json" with { type: "json" };
+export * from "./b.mts";
+export { b as d } from "./b.mts";
+export const value: number = c + def;
+export default function main() {
+  const c = 2;
+  return { c, ns, data, lazy: import("./b.mts") };
+}
+inc();
+"#,
+        },
+      ),
+      (
+        "file:///a/b.mts",
+        Source::Module {
+          specifier: "file:///a/b.mts",
+          maybe_headers: None,
+          content: "export let b = 1;\nexport function inc() {\n  b++;\n}\nexport default 2;\n",
+        },
+      ),
+      (
+        "file:///a/data.json",
+        Source::Module {
+          specifier: "file:///a/data.json",
+          maybe_headers: None,
+          content: "{}",
+        },
+      ),
+    ];
+    let (graph, _) = setup("file:///a/mod.ts", sources).await;
+    let output = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        rewrite_specifiers: true,
+        module_format: ModuleFormat::CommonJs,
+        ..Default::default()
+      },
+    )
+    .unwrap();
+
+    assert_eq!(
+      get_module(&output, "file:///a/b.mts").media_type,
+      MediaType::Cjs
+    );
+    let files = output.into_files().unwrap();
+    assert_eq!(
+      files.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+      vec!["b.cjs", "data.json", "mod.js"]
+    );
+    let code = String::from_utf8(files["mod.js"].clone()).unwrap();
+    assert!(code.starts_with(
+      r#""use strict";
+Object.defineProperty(exports, "__esModule", {
+  value: true
+});
+Object.defineProperty(exports, "d", {
+  enumerable: true,
+  get: function() {
+    return _b.b;
+  }
+});
+"#
+    ));
+    // the interop helpers are in between
+    assert!(code.ends_with(
+      r#"const _b = _interop_require_wildcard(require("./b.cjs"));
+_export_star(_b, exports);
+const _data = _interop_require_default(require("./data.json"));
+const value = _b.b + _b.default;
+function main() {
+  const c = 2;
+  return {
+    c,
+    ns: _b,
+    data: _data.default,
+    lazy: Promise.resolve().then(()=>_interop_require_wildcard(require("./b.cjs")))
+  };
+}
+(0, _b.inc)();
+"#
+    ));
+    assert_eq!(
+      String::from_utf8(files["b.cjs"].clone()).unwrap(),
+      r#""use strict";
+Object.defineProperty(exports, "__esModule", {
+  value: true
+});
+Object.defineProperty(exports, "b", {
+  enumerable: true,
+  get: function() {
+    return b;
+  }
+});
+Object.defineProperty(exports, "inc", {
+  enumerable: true,
+  get: function() {
+    return inc;
+  }
+});
+Object.defineProperty(exports, "default", {
+  enumerable: true,
+  get: function() {
+    return _default;
+  }
+});
+let b = 1;
+function inc() {
+  b++;
+}
+const _default = 2;
+"#
+    );
+
+    let err = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        module_format: ModuleFormat::CommonJs,
+        ..Default::default()
+      },
+    )
+    .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Emitting CommonJS requires rewriting specifiers."
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_commonjs_variables() {
+    let sources = vec![
+      (
+        "file:///a/mod.ts",
+        Source::Module {
+          specifier: "file:///a/mod.ts",
+          maybe_headers: None,
+          content: r#"import { b as __dirname } from "./b.ts";
+const exports = "mine";
+function require(specifier: string) {
+  return specifier;
+}
+export const module = { exports, require };
+export { require };
+const { __filename = "mod.ts" } = {} as any;
+function load({ require }: any) {
+  return [require, import("./b.ts")];
+}
+console.log(__dirname, require("x"), __filename, load, typeof module);
+"#,
+        },
+      ),
+      (
+        "file:///a/b.ts",
+        Source::Module {
+          specifier: "file:///a/b.ts",
+          maybe_headers: None,
+          content: "export const b = 1;\n",
+        },
+      ),
+    ];
+    let (graph, _) = setup("file:///a/mod.ts", sources).await;
+    let output = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        rewrite_specifiers: true,
+        module_format: ModuleFormat::CommonJs,
+        ..Default::default()
+      },
+    )
+    .unwrap();
+
+    let files = output.into_files().unwrap();
+    let code = String::from_utf8(files["mod.js"].clone()).unwrap();
+    // the exports keep their names
+    assert!(code.contains(
+      r#"Object.defineProperty(exports, "module", {
+  enumerable: true,
+  get: function() {
+    return _module;
+  }
+});
+Object.defineProperty(exports, "require", {
+  enumerable: true,
+  get: function() {
+    return _require;
+  }
+});
+"#
+    ));
+    assert!(code.ends_with(
+      r#"const _b = (require("./b.js"));
+const _exports = "mine";
+function _require(specifier) {
+  return specifier;
+}
+const _module = {
+  exports: _exports,
+  require: _require
+};
+const { __filename: ___filename = "mod.ts" } = {};
+function load({ require: _require1 }) {
+  return [
+    _require1,
+    Promise.resolve().then(()=>_interop_require_wildcard(require("./b.js")))
+  ];
+}
+console.log(_b.b, _require("x"), ___filename, load, typeof _module);
+"#
+    ));
+  }
+
+  #[tokio::test]
+  async fn transpile_graph_commonjs_top_level_await() {
+    let sources = vec![(
+      "file:///a/mod.ts",
+      Source::Module {
+        specifier: "file:///a/mod.ts",
+        maybe_headers: None,
+        content: r#"async function main() {
+  await 1;
+}
+await main();
+"#,
+      },
+    )];
+    let (graph, _) = setup("file:///a/mod.ts", sources).await;
+    let err = transpile_graph(
+      &graph,
+      None,
+      TranspileGraphOptions {
+        rewrite_specifiers: true,
+        module_format: ModuleFormat::CommonJs,
+        ..Default::default()
+      },
+    )
+    .unwrap_err();
+
+    assert_eq!(
+      err.to_string(),
+      "Top-level await is not supported when emitting CommonJS. Emit ES modules instead.\n    at file:///a/mod.ts:4:1"
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_react_refresh() {
+    let sources = vec![
+      (
+        "file:///a/app.tsx",
+        Source::Module {
+          specifier: "file:///a/app.tsx",
+          maybe_headers: None,
+          content: r#"import { useState } from "react";
+import { helper } from "./helper.ts";
+const _c = 1;
+export function App({ label }: { label: string }) {
+  const [count, setCount] = useState(_c);
+  return <button onClick={() => setCount(count + 1)}>{label} {helper(count)}</button>;
+}
+export const Label = () => <span>label</span>;
+"#,
+        },
+      ),
+      (
+        "file:///a/helper.ts",
+        Source::Module {
+          specifier: "file:///a/helper.ts",
+          maybe_headers: None,
+          content: "export function Helper(value: number) {\n  return value;\n}\nexport const helper = Helper;\n",
+        },
+      ),
+    ];
+    let (graph, analyzer) = setup("file:///a/app.tsx", sources).await;
+    let output = transpile_graph(
+      &graph,
+      Some(&analyzer),
+      TranspileGraphOptions {
+        transpile_options: deno_ast::TranspileOptions {
+          jsx_automatic: true,
+          jsx_import_source: Some("react".to_string()),
+          ..Default::default()
+        },
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        react_refresh: true,
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    assert_eq!(
+      String::from_utf8(get_module(&output, "file:///a/app.tsx").code.clone())
+        .unwrap(),
+      r#"import { jsx as _jsx, jsxs as _jsxs } from "react/jsx-runtime";
+var _s = $RefreshSig$();
+import { useState } from "react";
+import { helper } from "./helper.ts";
+const _c = 1;
+export function App({ label }) {
+  _s();
+  const [count, setCount] = useState(_c);
+  return /*#__PURE__*/ _jsxs("button", {
+    onClick: ()=>setCount(count + 1),
+    children: [
+      label,
+      " ",
+      helper(count)
+    ]
+  });
+}
+_s(App, "dgfFn8D6EKDwSjeTWP1Ve9pFYWU=");
+_c1 = App;
+export const Label = ()=>/*#__PURE__*/ _jsx("span", {
+    children: "label"
+  });
+_c2 = Label;
+var _c1, _c2;
+$RefreshReg$(_c1, "App");
+$RefreshReg$(_c2, "Label");
+"#
+    );
+    // only JSX and TSX modules are registered
+    assert_eq!(
+      String::from_utf8(
+        get_module(&output, "file:///a/helper.ts").code.clone()
+      )
+      .unwrap(),
+      "export function Helper(value) {\n  return value;\n}\nexport const helper = Helper;\n"
+    );
+  }
+
+  #[tokio::test]
+  async fn transpile_instrument_coverage() {
+    let sources = vec![
+      (
+        "file:///a/mod.ts",
+        Source::Module {
+          specifier: "file:///a/mod.ts",
+          maybe_headers: None,
+          content: r#"import { c } from "https://example.com/c.ts";
+interface Options {
+  verbose?: boolean;
+}
+export function run(value: number, options?: Options): string {
+  if (value > c) {
+    return "large";
+  }
+  const verbose = options?.verbose ?? false;
+  return verbose && value ? `small ${value}` : "small";
+}
+export const double = (value: number) => value * 2;
+"#,
+        },
+      ),
+      (
+        "https://example.com/c.ts",
+        Source::Module {
+          specifier: "https://example.com/c.ts",
+          maybe_headers: None,
+          content: "export const c: number = 1;\n",
+        },
+      ),
+    ];
+    let (graph, analyzer) = setup("file:///a/mod.ts", sources).await;
+    let output = transpile_graph(
+      &graph,
+      Some(&analyzer),
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        instrument_coverage: Some(ModuleFilter::LocalOnly),
+        ..Default::default()
+      },
+    )
+    .unwrap();
+    let code =
+      String::from_utf8(get_module(&output, "file:///a/mod.ts").code.clone())
+        .unwrap();
+    let (preamble, body) = code.split_once("cov_a62526da();\n").unwrap();
+    assert_eq!(
+      body,
+      r#"import { c } from "https://example.com/c.ts";
+export function run(value, options) {
+  cov_a62526da().f[0]++;
+  cov_a62526da().s[0]++;
+  if (value > c) {
+    cov_a62526da().b[0][0]++;
+    cov_a62526da().s[1]++;
+    return "large";
+  } else {
+    cov_a62526da().b[0][1]++;
+  }
+  cov_a62526da().s[2]++;
+  const verbose = (cov_a62526da().b[1][0]++, options?.verbose) ?? (cov_a62526da().b[1][1]++, false);
+  cov_a62526da().s[3]++;
+  return (cov_a62526da().b[3][0]++, verbose) && (cov_a62526da().b[3][1]++, value) ? (cov_a62526da().b[2][0]++, `small ${value}`) : (cov_a62526da().b[2][1]++, "small");
+}
+cov_a62526da().s[4]++;
+export const double = (value)=>{
+  cov_a62526da().f[1]++;
+  cov_a62526da().s[5]++;
+  return value * 2;
+};
+"#
+    );
+    // the locations are the ones in the TypeScript source
+    let data = preamble
+      .split_once("var coverageData = ")
+      .and_then(|(_, data)| data.split_once(";\n"))
+      .unwrap()
+      .0;
+    let data: serde_json::Value = serde_json::from_str(data).unwrap();
+    assert_eq!(data["path"], "/a/mod.ts");
+    assert_eq!(
+      data["statementMap"]["3"],
+      serde_json::json!({
+        "start": { "line": 10, "column": 2 },
+        "end": { "line": 10, "column": 55 },
+      })
+    );
+    assert_eq!(data["fnMap"]["1"]["loc"]["start"]["line"], 12);
+    assert_eq!(data["branchMap"]["1"]["type"], "binary-expr");
+    // remote modules are excluded by the filter
+    assert_eq!(
+      String::from_utf8(
+        get_module(&output, "https://example.com/c.ts").code.clone()
+      )
+      .unwrap(),
+      "export const c = 1;\n"
+    );
+  }
+
+  #[test]
+  fn transpile_with_matches_parsed_source() {
+    let modules = [
+      (
+        "file:///a/mod.ts",
+        MediaType::TypeScript,
+        "import type { A } from \"./a.ts\";\n// comment\nexport enum B { C }\n@dec\nexport class D implements A {\n  constructor(private e: string) {}\n}\n",
+      ),
+      (
+        "file:///a/mod.tsx",
+        MediaType::Tsx,
+        "export const App = ({ name }: { name: string }) => <div class=\"a\">{name}</div>;\n",
+      ),
+      (
+        "file:///a/mod.js",
+        MediaType::JavaScript,
+        "/** @jsx h */\nexport const a = import.meta.url;\n",
+      ),
+    ];
+    let transpile_options = [
+      TranspileOptions::default(),
+      TranspileOptions {
+        jsx_automatic: true,
+        jsx_import_source: Some("react".to_string()),
+        use_ts_decorators: true,
+        ..Default::default()
+      },
+      TranspileOptions {
+        jsx_automatic: true,
+        precompile_jsx: true,
+        jsx_import_source: Some("react".to_string()),
+        use_decorators_proposal: true,
+        ..Default::default()
+      },
+    ];
+    let emit_options = [
+      EmitOptions::default(),
+      EmitOptions {
+        source_map: SourceMapOption::Separate,
+        inline_sources: false,
+        remove_comments: true,
+        ..Default::default()
+      },
+    ];
+    for (specifier, media_type, source) in modules {
+      let parsed_source = deno_ast::parse_module(ParseParams {
+        specifier: ModuleSpecifier::parse(specifier).unwrap(),
+        text: source.into(),
+        media_type,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+      })
+      .unwrap();
+      for transpile_options in &transpile_options {
+        for emit_options in &emit_options {
+          let expected = parsed_source
+            .clone()
+            .transpile(transpile_options, emit_options)
+            .unwrap()
+            .into_source();
+          let output = transpile_with(
+            &parsed_source,
+            transpile_options,
+            emit_options,
+            ProgramTransforms::default(),
+            |_| Ok(()),
+          )
+          .unwrap();
+          assert_eq!(
+            (
+              String::from_utf8(output.source).unwrap(),
+              output.source_map.map(|map| String::from_utf8(map).unwrap())
+            ),
+            (
+              String::from_utf8(expected.source).unwrap(),
+              expected
+                .source_map
+                .map(|map| String::from_utf8(map).unwrap())
+            ),
+            "{specifier} {transpile_options:?} {emit_options:?}"
+          );
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,368 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::bail;
+use anyhow::Result;
+use deno_ast::ModuleSpecifier;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use crate::output_paths::decode;
+use crate::output_paths::remote_dir;
+use crate::output_paths::remote_path;
+
+#[derive(Debug)]
+pub struct VendorOutput {
+  /// The source of every remote module, keyed by the path relative to the
+  /// output directory it should be written to.
+  pub files: BTreeMap<String, Vec<u8>>,
+  /// An import map which redirects the original specifiers to the vendored
+  /// files. It is expected to be written to the output directory.
+  pub import_map: String,
+}
+
+/// Given a module graph, copy every remote module to a deterministic path
+/// within an output directory and generate an import map which redirects
+/// the original specifiers to those paths, so the program can run offline.
+///
+/// Remote modules are written to `remote/<host>/<path>`, with a hash of the
+/// query string appended to the file name when the URL has one. Two modules
+/// which would be written to the same path are an error. The output
+/// directory is used to refer to local modules in the import map.
+pub fn vendor_graph(
+  graph: &ModuleGraph,
+  output_dir: &ModuleSpecifier,
+) -> Result<VendorOutput> {
+  if !output_dir.path().ends_with('/') {
+    bail!(
+      "The vendor output directory \"{}\" must end with a slash.",
+      output_dir
+    );
+  }
+
+  let mut files = BTreeMap::new();
+  let mut local_paths = HashMap::new();
+  let mut specifiers_by_path: HashMap<String, &ModuleSpecifier> =
+    HashMap::new();
+  for module in graph.modules() {
+    let (source, media_type) = match module {
+      Module::Js(module) => (&module.source, module.media_type),
+      Module::Json(module) => (&module.source, module.media_type),
+      Module::Npm(_) | Module::Node(_) | Module::External(_) => continue,
+    };
+    let specifier = module.specifier();
+    if specifier.scheme() == "file" {
+      continue;
+    }
+    let path = remote_path(specifier, media_type);
+    if let Some(other) = specifiers_by_path.insert(path.clone(), specifier) {
+      bail!(
+        "Modules \"{}\" and \"{}\" would both be vendored to \"{}\".",
+        other,
+        specifier,
+        path
+      );
+    }
+    files.insert(path.clone(), source.as_bytes().to_vec());
+    local_paths.insert(specifier.clone(), format!("./{path}"));
+  }
+
+  let mut imports = BTreeMap::new();
+  for (specifier, local_path) in &local_paths {
+    // most modules are covered by mapping their origin to its directory, but
+    // the paths of modules with a query string, without a file name or
+    // without the extension of their media type differ from their url
+    let has_url_path = !specifier.cannot_be_a_base()
+      && specifier.query().is_none()
+      && local_path[2..]
+        == format!("{}{}", remote_dir(specifier), decode(specifier.path()));
+    if has_url_path {
+      imports.insert(
+        origin(specifier).to_string(),
+        format!("./{}/", remote_dir(specifier)),
+      );
+    } else {
+      imports.insert(specifier.to_string(), local_path.clone());
+    }
+  }
+  for (from, to) in &graph.redirects {
+    if let Some(local_path) = local_paths.get(graph.resolve(to)) {
+      imports.insert(from.to_string(), local_path.clone());
+    }
+  }
+
+  // specifiers which were resolved with an import map or resolve to a
+  // different module than their text suggests need explicit entries
+  let address = |specifier: &ModuleSpecifier| match local_paths.get(specifier) {
+    Some(local_path) => local_path.clone(),
+    None => relative_address(output_dir, specifier),
+  };
+  let mut scopes: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+  for module in graph.modules() {
+    let Module::Js(module) = module else {
+      continue;
+    };
+    for (text, dependency) in &module.dependencies {
+      let Some(resolved) = dependency.get_code() else {
+        continue;
+      };
+      if is_relative(text)
+        || ModuleSpecifier::parse(text).ok().as_ref() == Some(resolved)
+      {
+        continue;
+      }
+      let resolved = graph.resolve(resolved);
+      if !matches!(graph.get(resolved), Some(Module::Js(_) | Module::Json(_))) {
+        continue;
+      }
+      let target = address(resolved);
+      match imports.get(text) {
+        Some(existing) if *existing != target => {
+          scopes
+            .entry(address(&module.specifier))
+            .or_default()
+            .insert(text.clone(), target);
+        }
+        Some(_) => {}
+        None => {
+          imports.insert(text.clone(), target);
+        }
+      }
+    }
+  }
+
+  let mut import_map = serde_json::Map::new();
+  import_map.insert("imports".to_string(), serde_json::to_value(imports)?);
+  if !scopes.is_empty() {
+    import_map.insert("scopes".to_string(), serde_json::to_value(scopes)?);
+  }
+  let import_map = serde_json::to_string_pretty(&import_map)?;
+
+  Ok(VendorOutput { files, import_map })
+}
+
+/// The scheme, host and port of the specifier as a directory specifier.
+pub(crate) fn origin(specifier: &ModuleSpecifier) -> ModuleSpecifier {
+  let mut origin = specifier.clone();
+  origin.set_path("/");
+  origin.set_query(None);
+  origin.set_fragment(None);
+  origin
+}
+
+fn relative_address(
+  output_dir: &ModuleSpecifier,
+  specifier: &ModuleSpecifier,
+) -> String {
+  match output_dir.make_relative(specifier) {
+    Some(relative) if relative.starts_with("../") => relative,
+    Some(relative) => format!("./{relative}"),
+    None => specifier.to_string(),
+  }
+}
+
+fn is_relative(specifier: &str) -> bool {
+  specifier.starts_with("./")
+    || specifier.starts_with("../")
+    || specifier.starts_with('/')
+}
+
+#[cfg(test)]
+mod test {
+  use deno_ast::ModuleSpecifier;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use deno_graph::BuildOptions;
+  use deno_graph::GraphKind;
+  use deno_graph::ModuleGraph;
+  use pretty_assertions::assert_eq;
+
+  use crate::vendor_graph;
+
+  #[tokio::test]
+  async fn vendor_remote_modules() {
+    let memory_loader = MemoryLoader::new(
+      vec![
+        (
+          "file:///app/mod.ts",
+          Source::Module {
+            specifier: "file:///app/mod.ts",
+            maybe_headers: None,
+            content: r#"import "https://deno.land/std/path/mod.ts";
+import "https://esm.sh/react?target=deno";
+import "https://esm.sh/preact";
+import "https://example.com/redirect.ts";
+"#,
+          },
+        ),
+        (
+          "https://deno.land/std/path/mod.ts",
+          Source::Module {
+            specifier: "https://deno.land/std/path/mod.ts",
+            maybe_headers: None,
+            content: r#"import "./util.ts";"#,
+          },
+        ),
+        (
+          "https://deno.land/std/path/util.ts",
+          Source::Module {
+            specifier: "https://deno.land/std/path/util.ts",
+            maybe_headers: None,
+            content: "export {};",
+          },
+        ),
+        (
+          "https://esm.sh/react?target=deno",
+          Source::Module {
+            specifier: "https://esm.sh/react?target=deno",
+            maybe_headers: Some(vec![(
+              "content-type",
+              "application/javascript",
+            )]),
+            content: "export default {};",
+          },
+        ),
+        (
+          "https://esm.sh/preact",
+          Source::Module {
+            specifier: "https://esm.sh/preact",
+            maybe_headers: Some(vec![(
+              "content-type",
+              "application/typescript",
+            )]),
+            content: "export default {};",
+          },
+        ),
+        (
+          "https://example.com/redirect.ts",
+          Source::Redirect("https://example.com/final.ts"),
+        ),
+        (
+          "https://example.com/final.ts",
+          Source::Module {
+            specifier: "https://example.com/final.ts",
+            maybe_headers: None,
+            content: "export {};",
+          },
+        ),
+      ],
+      vec![],
+    );
+    let mut graph = ModuleGraph::new(GraphKind::CodeOnly);
+    graph
+      .build(
+        vec![ModuleSpecifier::parse("file:///app/mod.ts").unwrap()],
+        &memory_loader,
+        BuildOptions::default(),
+      )
+      .await;
+    graph.valid().unwrap();
+
+    let output = vendor_graph(
+      &graph,
+      &ModuleSpecifier::parse("file:///app/vendor/").unwrap(),
+    )
+    .unwrap();
+
+    let react_path = output
+      .files
+      .keys()
+      .find(|path| path.starts_with("remote/esm.sh/react_"))
+      .unwrap()
+      .clone();
+    assert!(react_path.ends_with(".js"));
+    assert_eq!(
+      output.files.keys().collect::<Vec<_>>(),
+      vec![
+        "remote/deno.land/std/path/mod.ts",
+        "remote/deno.land/std/path/util.ts",
+        "remote/esm.sh/preact.ts",
+        &react_path,
+        "remote/example.com/final.ts",
+      ]
+    );
+    assert_eq!(
+      output.import_map,
+      format!(
+        r#"{{
+  "imports": {{
+    "https://deno.land/": "./remote/deno.land/",
+    "https://esm.sh/preact": "./remote/esm.sh/preact.ts",
+    "https://esm.sh/react?target=deno": "./{react_path}",
+    "https://example.com/": "./remote/example.com/",
+    "https://example.com/redirect.ts": "./remote/example.com/final.ts"
+  }}
+}}"#
+      )
+    );
+  }
+
+  async fn build_graph(remote_sources: Vec<(&str, &str)>) -> ModuleGraph {
+    let mut imports = String::new();
+    for (specifier, _) in &remote_sources {
+      imports.push_str(&format!("import \"{specifier}\";\n"));
+    }
+    let mut sources = vec![(
+      "file:///app/mod.ts",
+      Source::Module {
+        specifier: "file:///app/mod.ts",
+        maybe_headers: None,
+        content: imports.as_str(),
+      },
+    )];
+    for (specifier, content_type) in remote_sources {
+      sources.push((
+        specifier,
+        Source::Module {
+          specifier,
+          maybe_headers: Some(vec![("content-type", content_type)]),
+          content: "export {};",
+        },
+      ));
+    }
+    let memory_loader = MemoryLoader::new(sources, vec![]);
+    let mut graph = ModuleGraph::new(GraphKind::CodeOnly);
+    graph
+      .build(
+        vec![ModuleSpecifier::parse("file:///app/mod.ts").unwrap()],
+        &memory_loader,
+        BuildOptions::default(),
+      )
+      .await;
+    graph.valid().unwrap();
+    graph
+  }
+
+  #[tokio::test]
+  async fn vendor_unsafe_paths() {
+    let output_dir = ModuleSpecifier::parse("file:///app/vendor/").unwrap();
+    let graph = build_graph(vec![(
+      "https://ex.com/..%2F..%2F..%2Fescape.ts",
+      "application/typescript",
+    )])
+    .await;
+    let output = vendor_graph(&graph, &output_dir).unwrap();
+    let path = output.files.keys().next().unwrap();
+    assert!(path.starts_with("remote/ex.com/_"), "{path}");
+    assert!(!path.contains(".."), "{path}");
+    assert!(
+      output.import_map.contains(&format!(
+        "\"https://ex.com/..%2F..%2F..%2Fescape.ts\": \"./{path}\""
+      )),
+      "{}",
+      output.import_map
+    );
+
+    let graph = build_graph(vec![
+      ("https://ex.com/preact", "application/typescript"),
+      ("https://ex.com/preact.ts", "application/typescript"),
+    ])
+    .await;
+    let err = vendor_graph(&graph, &output_dir).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Modules \"https://ex.com/preact\" and \"https://ex.com/preact.ts\" would both be vendored to \"remote/ex.com/preact.ts\"."
+    );
+  }
+}
@@ -0,0 +1,15 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use deno_ast::swc::ast;
+use deno_ast::swc::common::SyntaxContext;
+use deno_ast::swc::visit::VisitMut;
+
+/// Clears the syntax contexts of every identifier, so the program can be
+/// resolved again with new marks.
+pub(crate) struct ClearContexts;
+
+impl VisitMut for ClearContexts {
+  fn visit_mut_ident(&mut self, ident: &mut ast::Ident) {
+    ident.ctxt = SyntaxContext::empty();
+  }
+}
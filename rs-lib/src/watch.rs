@@ -0,0 +1,794 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::Result;
+use deno_ast::ModuleSpecifier;
+use deno_ast::ParsedSource;
+use deno_graph::source::LoadFuture;
+use deno_graph::source::LoadOptions;
+use deno_graph::source::LoadResponse;
+use deno_graph::source::Loader;
+use deno_graph::source::Resolver;
+use deno_graph::CapturingModuleAnalyzer;
+use deno_graph::Module;
+use deno_graph::ModuleGraph;
+use deno_graph::ParsedSourceStore;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::dev_bundle::emit_hmr_update;
+use crate::emit::BundleModuleCache;
+use crate::get_import_map_from_input;
+use crate::paths::PathsResolver;
+use crate::workspace::WorkspaceResolver;
+use crate::BundleEmit;
+use crate::BundleMode;
+use crate::BundleOptions;
+use crate::CompilerOptions;
+use crate::ImportMapInput;
+use crate::ImportMapResolver;
+use crate::MemoryEmitCache;
+use crate::TranspileGraphOptions;
+use crate::TranspileOutput;
+use crate::Workspace;
+
+/// What a [`Watcher`] builds.
+pub enum WatchTarget {
+  Bundle {
+    root: ModuleSpecifier,
+    options: BundleOptions,
+  },
+  Transpile {
+    roots: Vec<ModuleSpecifier>,
+    options: TranspileGraphOptions,
+  },
+}
+
+#[derive(Debug)]
+pub enum WatchOutput {
+  Bundle(BundleEmit),
+  Transpile(TranspileOutput),
+}
+
+/// The outcome of a build of a [`Watcher`].
+#[derive(Debug)]
+pub enum WatchEvent {
+  Built {
+    output: WatchOutput,
+    /// The modules which changed since the previous build, which is empty
+    /// for the first build.
+    changed: Vec<ModuleSpecifier>,
+    /// The number of modules which were loaded with the loader, instead of
+    /// being reused from the previous graph.
+    loaded: usize,
+    /// The number of modules which were transpiled, instead of being reused
+    /// from the previous build.
+    transpiled: usize,
+    /// The update for a bundle with [`crate::BundleMode::DevHmr`], which
+    /// contains the modules that changed or were added since the previous
+    /// successful build. It's `None` for the first build, whose bundle is
+    /// loaded as a whole.
+    maybe_hmr_update: Option<String>,
+    duration: Duration,
+  },
+  /// The build failed, and the watcher keeps the modules which were loaded
+  /// for the next build.
+  Failed {
+    error: anyhow::Error,
+    changed: Vec<ModuleSpecifier>,
+    duration: Duration,
+  },
+}
+
+enum WatchResolver {
+  ImportMap(ImportMapResolver),
+  Workspace {
+    resolver: WorkspaceResolver,
+    compiler_options: CompilerOptions,
+    maybe_paths_base: Option<ModuleSpecifier>,
+  },
+}
+
+/// Rebuilds a bundle or the transpiled modules of a graph incrementally as
+/// its modules change.
+///
+/// The watcher doesn't watch the file system itself. Instead, the caller
+/// watches the modules of [`Watcher::watched`] and passes the ones which
+/// changed to [`Watcher::build`]. Only those modules are loaded, parsed and
+/// transpiled again, while the others are reused from the previous build.
+pub struct Watcher {
+  target: WatchTarget,
+  loader: Box<dyn Loader>,
+  resolver: WatchResolver,
+  analyzer: CapturingModuleAnalyzer,
+  maybe_graph: Option<ModuleGraph>,
+  emit_cache: Arc<MemoryEmitCache>,
+  module_cache: BundleModuleCache,
+  /// The modules of the last successful bundle with HMR.
+  maybe_hmr_modules: Option<HashSet<ModuleSpecifier>>,
+  /// The modules which changed since the last successful bundle with HMR.
+  hmr_changes: HashSet<ModuleSpecifier>,
+  /// The modules of the graphs since the last successful build, whose parsed
+  /// sources and transpiled modules may be kept.
+  built_modules: HashSet<ModuleSpecifier>,
+}
+
+impl Watcher {
+  pub fn new(
+    target: WatchTarget,
+    loader: Box<dyn Loader>,
+    maybe_import_map: Option<ImportMapInput>,
+  ) -> Result<Self> {
+    let resolver = WatchResolver::ImportMap(ImportMapResolver(
+      get_import_map_from_input(maybe_import_map)?,
+    ));
+    Ok(Self::with_resolver(target, loader, resolver))
+  }
+
+  /// Creates a watcher which resolves imports like
+  /// [`crate::bundle_workspace`] and [`crate::transpile_workspace`].
+  pub fn new_workspace(
+    target: WatchTarget,
+    loader: Box<dyn Loader>,
+    workspace: &Workspace,
+  ) -> Result<Self> {
+    let resolver = WorkspaceResolver::new(workspace)?;
+    let compiler_options = workspace.root.compiler_options.clone();
+    let maybe_paths_base = workspace.root.maybe_paths_base.clone();
+    // check the paths up front, as they're resolved again for every build
+    PathsResolver::new(
+      &resolver,
+      &compiler_options,
+      maybe_paths_base.as_ref(),
+    )?;
+    let resolver = WatchResolver::Workspace {
+      resolver,
+      compiler_options,
+      maybe_paths_base,
+    };
+    Ok(Self::with_resolver(target, loader, resolver))
+  }
+
+  fn with_resolver(
+    target: WatchTarget,
+    loader: Box<dyn Loader>,
+    resolver: WatchResolver,
+  ) -> Self {
+    Self {
+      target,
+      loader,
+      resolver,
+      analyzer: Default::default(),
+      maybe_graph: None,
+      emit_cache: Default::default(),
+      module_cache: BundleModuleCache::new(),
+      maybe_hmr_modules: None,
+      hmr_changes: HashSet::new(),
+      built_modules: HashSet::new(),
+    }
+  }
+
+  /// The local modules of the graph of the last build, which should be
+  /// watched for changes. This includes the modules which couldn't be
+  /// loaded, so creating them fixes the build.
+  pub fn watched(&self) -> Vec<ModuleSpecifier> {
+    let Some(graph) = &self.maybe_graph else {
+      return Vec::new();
+    };
+    graph
+      .specifiers()
+      .map(|(specifier, _)| specifier)
+      .chain(graph.redirects.values())
+      .filter(|specifier| specifier.scheme() == "file")
+      .cloned()
+      .collect::<HashSet<_>>()
+      .into_iter()
+      .collect()
+  }
+
+  /// Builds the output, loading the changed modules again. The first build
+  /// loads every module, so it's passed no changed modules.
+  pub async fn build(&mut self, changed: Vec<ModuleSpecifier>) -> WatchEvent {
+    let start = Instant::now();
+    let maybe_previous = self.maybe_graph.take();
+    let mut loader = WatchLoader {
+      loader: &*self.loader,
+      maybe_previous: maybe_previous.as_ref(),
+      changed: changed.iter().collect(),
+      loaded: Default::default(),
+    };
+    let transpiled_before = self.transpiled();
+    let (graph, result) = match &self.resolver {
+      WatchResolver::ImportMap(resolver) => {
+        self.build_with(resolver.as_resolver(), &mut loader).await
+      }
+      WatchResolver::Workspace {
+        resolver,
+        compiler_options,
+        maybe_paths_base,
+      } => {
+        // this was checked when the watcher was created
+        let resolver = PathsResolver::new(
+          resolver,
+          compiler_options,
+          maybe_paths_base.as_ref(),
+        )
+        .unwrap();
+        let mut loader = resolver.loader(&loader);
+        self.build_with(&resolver, &mut loader).await
+      }
+    };
+    let loaded = loader.loaded.get();
+    drop(loader);
+    self
+      .built_modules
+      .extend(graph.modules().map(|module| module.specifier().clone()));
+    self.maybe_graph = Some(graph);
+    if self.hmr_options().is_some() {
+      // kept until a build succeeds, as the page still runs the modules of
+      // the last successful one
+      self.hmr_changes.extend(changed.iter().cloned());
+    }
+    let result = result.and_then(|output| Ok((output, self.hmr_update()?)));
+    if result.is_ok() {
+      self.prune();
+    }
+    let duration = start.elapsed();
+    match result {
+      Ok((output, maybe_hmr_update)) => WatchEvent::Built {
+        output,
+        changed,
+        loaded,
+        transpiled: self.transpiled() - transpiled_before,
+        maybe_hmr_update,
+        duration,
+      },
+      Err(error) => WatchEvent::Failed {
+        error,
+        changed,
+        duration,
+      },
+    }
+  }
+
+  async fn build_with(
+    &self,
+    resolver: &dyn Resolver,
+    loader: &mut dyn Loader,
+  ) -> (ModuleGraph, Result<WatchOutput>) {
+    match &self.target {
+      WatchTarget::Bundle { root, options } => {
+        let mut graph = crate::build_graph(
+          vec![root.clone()],
+          loader,
+          resolver,
+          &self.analyzer,
+          options.declarations,
+        )
+        .await;
+        // report the errors of the graph, like a module that fails to parse,
+        // rather than the bundler failing to resolve its dependents
+        let result = graph
+          .valid()
+          .map_err(anyhow::Error::from)
+          .and_then(|()| {
+            crate::bundle_built_graph(
+              &mut graph,
+              &self.analyzer,
+              resolver,
+              options.clone(),
+              Some(&self.module_cache),
+            )
+          })
+          .map(WatchOutput::Bundle);
+        (graph, result)
+      }
+      WatchTarget::Transpile { roots, options } => {
+        let mut graph = crate::build_graph(
+          roots.clone(),
+          loader,
+          resolver,
+          &self.analyzer,
+          options.declarations,
+        )
+        .await;
+        let options = TranspileGraphOptions {
+          cache: Some(self.emit_cache.clone()),
+          ..options.clone()
+        };
+        let result = crate::transpile_built_graph(
+          &mut graph,
+          &self.analyzer,
+          &KeepParsedSources(&self.analyzer),
+          resolver,
+          options,
+        )
+        .map(WatchOutput::Transpile);
+        (graph, result)
+      }
+    }
+  }
+
+  fn hmr_options(&self) -> Option<&BundleOptions> {
+    match &self.target {
+      WatchTarget::Bundle { options, .. }
+        if options.mode == BundleMode::DevHmr =>
+      {
+        Some(options)
+      }
+      _ => None,
+    }
+  }
+
+  /// Emits the update of a successful bundle with HMR.
+  fn hmr_update(&mut self) -> Result<Option<String>> {
+    let Some(options) = self.hmr_options() else {
+      return Ok(None);
+    };
+    let graph = self.maybe_graph.as_ref().unwrap();
+    let modules = graph
+      .modules()
+      .map(|module| module.specifier().clone())
+      .collect::<HashSet<_>>();
+    let maybe_update = match &self.maybe_hmr_modules {
+      Some(previous) => {
+        let changed = self
+          .hmr_changes
+          .iter()
+          .map(|specifier| graph.resolve(specifier))
+          .collect::<HashSet<_>>();
+        let specifiers = graph
+          .modules()
+          .map(|module| module.specifier())
+          .filter(|specifier| {
+            changed.contains(specifier) || !previous.contains(*specifier)
+          })
+          .cloned()
+          .collect::<Vec<_>>();
+        Some(emit_hmr_update(
+          graph,
+          &specifiers,
+          options,
+          Some(&self.module_cache.emit_cache),
+        )?)
+      }
+      None => None,
+    };
+    self.maybe_hmr_modules = Some(modules);
+    self.hmr_changes.clear();
+    Ok(maybe_update)
+  }
+
+  /// Drops the parsed sources and the transpiled modules of the modules which
+  /// are no longer in the graph. This only happens after a successful build,
+  /// as the graph of a failed one may be missing modules which come back once
+  /// it's fixed.
+  fn prune(&mut self) {
+    let graph = self.maybe_graph.as_ref().unwrap();
+    for specifier in &self.built_modules {
+      if graph.get(specifier).is_none() {
+        self.analyzer.remove_parsed_source(specifier);
+      }
+    }
+    self.built_modules = graph
+      .modules()
+      .map(|module| module.specifier().clone())
+      .collect();
+    self
+      .emit_cache
+      .retain(|specifier| graph.get(specifier).is_some());
+    self.module_cache.retain(graph);
+  }
+
+  /// The number of modules transpiled by the watcher since it was created.
+  fn transpiled(&self) -> usize {
+    self.module_cache.transpiled() + self.emit_cache.stats().misses
+  }
+}
+
+/// A parsed source store which keeps the parsed sources that are taken out
+/// of it, so the next build reuses those of the unchanged modules instead of
+/// parsing them again.
+struct KeepParsedSources<'a>(&'a dyn ParsedSourceStore);
+
+impl ParsedSourceStore for KeepParsedSources<'_> {
+  fn set_parsed_source(
+    &self,
+    specifier: ModuleSpecifier,
+    parsed_source: ParsedSource,
+  ) -> Option<ParsedSource> {
+    self.0.set_parsed_source(specifier, parsed_source)
+  }
+
+  fn get_parsed_source(
+    &self,
+    specifier: &ModuleSpecifier,
+  ) -> Option<ParsedSource> {
+    self.0.get_parsed_source(specifier)
+  }
+
+  fn remove_parsed_source(
+    &self,
+    specifier: &ModuleSpecifier,
+  ) -> Option<ParsedSource> {
+    self.0.get_parsed_source(specifier)
+  }
+
+  fn get_scope_analysis_parsed_source(
+    &self,
+    specifier: &ModuleSpecifier,
+  ) -> Option<ParsedSource> {
+    self.0.get_scope_analysis_parsed_source(specifier)
+  }
+}
+
+/// A loader which reuses the unchanged modules of the previous graph.
+struct WatchLoader<'a> {
+  loader: &'a dyn Loader,
+  maybe_previous: Option<&'a ModuleGraph>,
+  changed: HashSet<&'a ModuleSpecifier>,
+  loaded: Cell<usize>,
+}
+
+impl WatchLoader<'_> {
+  fn previous_response(
+    &self,
+    specifier: &ModuleSpecifier,
+  ) -> Option<LoadResponse> {
+    let graph = self.maybe_previous?;
+    if self.changed.contains(specifier)
+      || self.changed.contains(graph.resolve(specifier))
+    {
+      return None;
+    }
+    let (found, source, media_type) = match graph.get(specifier)? {
+      // types from a header are only known to the loader
+      Module::Js(module)
+        if module.maybe_types_dependency.is_none()
+          || module.specifier.scheme() == "file" =>
+      {
+        (&module.specifier, &module.source, module.media_type)
+      }
+      Module::Json(module) => {
+        (&module.specifier, &module.source, module.media_type)
+      }
+      _ => return None,
+    };
+    let maybe_headers = media_type.as_content_type().map(|content_type| {
+      HashMap::from([("content-type".to_string(), content_type.to_string())])
+    });
+    Some(LoadResponse::Module {
+      content: source.as_bytes().into(),
+      specifier: found.clone(),
+      maybe_headers: if found.scheme() == "file" {
+        None
+      } else {
+        maybe_headers
+      },
+    })
+  }
+}
+
+impl Loader for WatchLoader<'_> {
+  fn load(
+    &self,
+    specifier: &ModuleSpecifier,
+    options: LoadOptions,
+  ) -> LoadFuture {
+    if let Some(response) = self.previous_response(specifier) {
+      return Box::pin(futures::future::ready(Ok(Some(response))));
+    }
+    self.loaded.set(self.loaded.get() + 1);
+    self.loader.load(specifier, options)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_ast::EmitOptions;
+  use deno_ast::ParseDiagnostic;
+  use deno_ast::SourceMapOption;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use deno_graph::DefaultModuleParser;
+  use deno_graph::ModuleParser;
+  use deno_graph::ParseOptions;
+  use pretty_assertions::assert_eq;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  /// A loader whose modules can be changed between builds.
+  #[derive(Clone, Default)]
+  struct TestLoader(Rc<RefCell<HashMap<String, String>>>);
+
+  impl TestLoader {
+    fn set(&self, specifier: &str, content: &str) {
+      self
+        .0
+        .borrow_mut()
+        .insert(specifier.to_string(), content.to_string());
+    }
+
+    fn memory_loader(&self) -> MemoryLoader {
+      MemoryLoader::new(
+        self
+          .0
+          .borrow()
+          .iter()
+          .map(|(specifier, content)| {
+            (
+              specifier.clone(),
+              Source::Module {
+                specifier: specifier.clone(),
+                maybe_headers: None,
+                content: content.clone(),
+              },
+            )
+          })
+          .collect(),
+        vec![],
+      )
+    }
+  }
+
+  impl Loader for TestLoader {
+    fn load(
+      &self,
+      specifier: &ModuleSpecifier,
+      options: LoadOptions,
+    ) -> LoadFuture {
+      self.memory_loader().load(specifier, options)
+    }
+  }
+
+  /// A parser which counts the modules it parses.
+  struct CountingParser(Rc<Cell<usize>>);
+
+  impl ModuleParser for CountingParser {
+    fn parse_module(
+      &self,
+      options: ParseOptions,
+    ) -> Result<ParsedSource, ParseDiagnostic> {
+      self.0.set(self.0.get() + 1);
+      DefaultModuleParser.parse_module(options)
+    }
+  }
+
+  fn bundle_options() -> BundleOptions {
+    BundleOptions {
+      emit_options: EmitOptions {
+        source_map: SourceMapOption::None,
+        ..Default::default()
+      },
+      ..Default::default()
+    }
+  }
+
+  fn specifier(specifier: &str) -> ModuleSpecifier {
+    ModuleSpecifier::parse(specifier).unwrap()
+  }
+
+  #[tokio::test]
+  async fn watch_bundle() {
+    let loader = TestLoader::default();
+    loader.set(
+      "file:///a/main.ts",
+      "import { b } from \"./b.ts\";\nconsole.log(b);\n",
+    );
+    loader.set(
+      "file:///a/b.ts",
+      "import { c } from \"./c.ts\";\nexport const b: number = c + 1;\n",
+    );
+    loader.set("file:///a/c.ts", "export const c: number = 1;\n");
+    let root = specifier("file:///a/main.ts");
+    let mut watcher = Watcher::new(
+      WatchTarget::Bundle {
+        root: root.clone(),
+        options: bundle_options(),
+      },
+      Box::new(loader.clone()),
+      None,
+    )
+    .unwrap();
+
+    let WatchEvent::Built {
+      loaded, transpiled, ..
+    } = watcher.build(Vec::new()).await
+    else {
+      panic!("expected the first build to succeed");
+    };
+    assert_eq!((loaded, transpiled), (3, 3));
+    let mut watched = watcher.watched();
+    watched.sort();
+    assert_eq!(
+      watched,
+      vec![
+        specifier("file:///a/b.ts"),
+        specifier("file:///a/c.ts"),
+        specifier("file:///a/main.ts"),
+      ]
+    );
+
+    loader.set(
+      "file:///a/b.ts",
+      "import { c } from \"./c.ts\";\nexport const b: number = c + 2;\n",
+    );
+    let WatchEvent::Built {
+      output: WatchOutput::Bundle(output),
+      changed,
+      loaded,
+      transpiled,
+      ..
+    } = watcher.build(vec![specifier("file:///a/b.ts")]).await
+    else {
+      panic!("expected the rebuild to succeed");
+    };
+    assert_eq!(changed, vec![specifier("file:///a/b.ts")]);
+    assert_eq!((loaded, transpiled), (1, 1));
+    let expected = crate::bundle(
+      root.clone(),
+      &mut loader.memory_loader(),
+      None,
+      bundle_options(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(output.code, expected.code);
+    assert!(output.code.contains("const b = 1 + 2;"), "{}", output.code);
+
+    loader.set("file:///a/c.ts", "export const c: number = ;\n");
+    let event = watcher.build(vec![specifier("file:///a/c.ts")]).await;
+    let WatchEvent::Failed { error, .. } = event else {
+      panic!("expected the rebuild to fail: {event:?}");
+    };
+    assert!(
+      format!("{error:#}").contains("Expression expected"),
+      "{error:#}"
+    );
+
+    loader.set("file:///a/c.ts", "export const c: number = 3;\n");
+    let WatchEvent::Built {
+      output: WatchOutput::Bundle(output),
+      loaded,
+      transpiled,
+      ..
+    } = watcher.build(vec![specifier("file:///a/c.ts")]).await
+    else {
+      panic!("expected the fixed build to succeed");
+    };
+    assert_eq!((loaded, transpiled), (1, 1));
+    assert!(output.code.contains("const b = 3 + 2;"), "{}", output.code);
+  }
+
+  #[tokio::test]
+  async fn watch_bundle_hmr() {
+    let loader = TestLoader::default();
+    loader.set(
+      "file:///a/main.ts",
+      "import { b } from \"./b.ts\";\nconsole.log(b);\n",
+    );
+    loader.set("file:///a/b.ts", "export const b: number = 1;\n");
+    let mut watcher = Watcher::new(
+      WatchTarget::Bundle {
+        root: specifier("file:///a/main.ts"),
+        options: BundleOptions {
+          mode: BundleMode::DevHmr,
+          ..bundle_options()
+        },
+      },
+      Box::new(loader.clone()),
+      None,
+    )
+    .unwrap();
+    let WatchEvent::Built {
+      output: WatchOutput::Bundle(output),
+      maybe_hmr_update,
+      ..
+    } = watcher.build(Vec::new()).await
+    else {
+      panic!("expected the first build to succeed");
+    };
+    assert!(output.code.contains("__deno_emit_dev.define("));
+    assert_eq!(maybe_hmr_update, None);
+
+    // the changes of a failed build are part of the next update
+    loader.set("file:///a/b.ts", "export const b: number = ;\n");
+    let event = watcher.build(vec![specifier("file:///a/b.ts")]).await;
+    assert!(matches!(event, WatchEvent::Failed { .. }), "{event:?}");
+    loader.set(
+      "file:///a/main.ts",
+      "import { b } from \"./b.ts\";\nimport { c } from \"./c.ts\";\nconsole.log(b, c);\n",
+    );
+    loader.set("file:///a/b.ts", "export const b: number = 2;\n");
+    loader.set("file:///a/c.ts", "export const c: number = 3;\n");
+    let WatchEvent::Built {
+      maybe_hmr_update: Some(update),
+      transpiled,
+      ..
+    } = watcher.build(vec![specifier("file:///a/main.ts")]).await
+    else {
+      panic!("expected the fixed build to succeed");
+    };
+    assert_eq!(transpiled, 3);
+    let ids = update
+      .lines()
+      .filter_map(|line| line.strip_prefix("[\""))
+      .map(|line| line.split_once('"').unwrap().0)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      ids,
+      vec!["file:///a/b.ts", "file:///a/c.ts", "file:///a/main.ts"]
+    );
+    assert!(update.contains("const b = 2;"), "{update}");
+  }
+
+  #[tokio::test]
+  async fn watch_transpile() {
+    let loader = TestLoader::default();
+    loader.set(
+      "file:///a/main.ts",
+      "import { b } from \"./b.ts\";\nconsole.log(b);\n",
+    );
+    loader.set("file:///a/b.ts", "export const b: number = 1;\n");
+    let parses = Rc::new(Cell::new(0));
+    let mut watcher = Watcher::new(
+      WatchTarget::Transpile {
+        roots: vec![specifier("file:///a/main.ts")],
+        options: TranspileGraphOptions {
+          emit_options: EmitOptions {
+            source_map: SourceMapOption::None,
+            ..Default::default()
+          },
+          ..Default::default()
+        },
+      },
+      Box::new(loader.clone()),
+      None,
+    )
+    .unwrap();
+    watcher.analyzer = CapturingModuleAnalyzer::new(
+      Some(Box::new(CountingParser(parses.clone()))),
+      None,
+    );
+    let WatchEvent::Built { transpiled, .. } = watcher.build(Vec::new()).await
+    else {
+      panic!("expected the first build to succeed");
+    };
+    assert_eq!((parses.get(), transpiled), (2, 2));
+
+    loader.set("file:///a/b.ts", "export const b: number = 2;\n");
+    let WatchEvent::Built {
+      output: WatchOutput::Transpile(output),
+      loaded,
+      transpiled,
+      ..
+    } = watcher.build(vec![specifier("file:///a/b.ts")]).await
+    else {
+      panic!("expected the rebuild to succeed");
+    };
+    // the parsed source of the unchanged module is reused
+    assert_eq!((parses.get(), loaded, transpiled), (3, 1, 1));
+    let module = output.get(&specifier("file:///a/b.ts")).unwrap();
+    assert_eq!(
+      String::from_utf8(module.code.clone()).unwrap(),
+      "export const b = 2;\n"
+    );
+
+    loader.set("file:///a/main.ts", "console.log(1);\n");
+    let WatchEvent::Built { .. } =
+      watcher.build(vec![specifier("file:///a/main.ts")]).await
+    else {
+      panic!("expected the rebuild to succeed");
+    };
+    assert_eq!(parses.get(), 4);
+    // the module which is no longer imported is dropped
+    assert!(watcher
+      .analyzer
+      .get_parsed_source(&specifier("file:///a/b.ts"))
+      .is_none());
+  }
+}
@@ -0,0 +1,392 @@
+// Copyright 2018-2024 the Deno authors. All rights reserved. MIT license.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+use deno_ast::EmitOptions;
+use deno_ast::ModuleSpecifier;
+use deno_ast::TranspileOptions;
+use deno_graph::source::Loader;
+use deno_graph::source::ResolutionMode;
+use deno_graph::source::ResolveError;
+use deno_graph::source::Resolver;
+use deno_graph::Range;
+use import_map::ImportMap;
+use import_map::ImportMapError;
+use indexmap::IndexMap;
+
+use crate::config::maybe_load_text;
+use crate::get_import_map_from_input;
+use crate::ConfigFile;
+
+/// The file names of a workspace member's configuration file, in the order
+/// they're probed.
+const MEMBER_CONFIG_NAMES: &[&str] = &["deno.json", "deno.jsonc"];
+
+/// A Deno workspace, which is a root `deno.json(c)` listing the directories of
+/// its `"workspace"` members, each with a configuration file of its own.
+///
+/// A configuration file without members is a workspace of its own, so a
+/// single `deno.json(c)` or `tsconfig.json` can be loaded as a workspace too.
+#[derive(Debug)]
+pub struct Workspace {
+  pub root: ConfigFile,
+  pub members: Vec<ConfigFile>,
+}
+
+impl Workspace {
+  /// Loads the root configuration file of the workspace and the
+  /// configuration files of its members with the loader.
+  pub async fn load(
+    specifier: ModuleSpecifier,
+    loader: &dyn Loader,
+  ) -> Result<Self> {
+    let root = ConfigFile::load(specifier, loader).await?;
+    let mut members = Vec::with_capacity(root.workspace.len());
+    for member in &root.workspace {
+      if member.contains('*') {
+        bail!(
+          "Unsupported workspace member \"{member}\" in \"{}\". Globs can't be expanded, so list the members instead.",
+          root.specifier
+        );
+      }
+      let dir = root
+        .specifier
+        .join(&format!("{}/", member.trim_end_matches('/')))?;
+      let mut maybe_config = None;
+      for name in MEMBER_CONFIG_NAMES {
+        if let Some((specifier, text)) =
+          maybe_load_text(loader, dir.join(name)?).await?
+        {
+          maybe_config =
+            Some(ConfigFile::load_from_text(specifier, &text, loader).await?);
+          break;
+        }
+      }
+      let Some(config) = maybe_config else {
+        bail!(
+          "Could not find a deno.json or deno.jsonc for the workspace member \"{member}\" in \"{dir}\"."
+        );
+      };
+      members.push(config);
+    }
+    Ok(Self { root, members })
+  }
+
+  /// The transpile and emit options of the workspace, which are the ones of
+  /// the root configuration file.
+  pub fn to_options(&self) -> Result<(TranspileOptions, EmitOptions)> {
+    self.root.to_options()
+  }
+}
+
+/// A workspace member prepared for resolution.
+#[derive(Debug)]
+struct MemberResolver {
+  dir: ModuleSpecifier,
+  maybe_name: Option<String>,
+  exports: IndexMap<String, String>,
+  maybe_import_map: Option<ImportMap>,
+}
+
+/// Resolves the imports of the modules of a workspace.
+///
+/// The import map of the member which contains the referrer applies first,
+/// and then the import map of the root. Bare specifiers that neither maps
+/// resolve are resolved against the names of the members, to the member's
+/// `"exports"`.
+#[derive(Debug)]
+pub(crate) struct WorkspaceResolver {
+  maybe_root_import_map: Option<ImportMap>,
+  members: Vec<MemberResolver>,
+}
+
+impl WorkspaceResolver {
+  pub fn new(workspace: &Workspace) -> Result<Self> {
+    let maybe_root_import_map =
+      get_import_map_from_input(workspace.root.maybe_import_map.clone())?;
+    let mut members = workspace
+      .members
+      .iter()
+      .map(|member| {
+        Ok(MemberResolver {
+          dir: member.specifier.join("./")?,
+          maybe_name: member.maybe_name.clone(),
+          exports: member.exports.clone(),
+          maybe_import_map: get_import_map_from_input(
+            member.maybe_import_map.clone(),
+          )?,
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    // nested members come first, so the innermost member contains a module
+    members.sort_by_key(|member| std::cmp::Reverse(member.dir.path().len()));
+    Ok(Self {
+      maybe_root_import_map,
+      members,
+    })
+  }
+
+  fn member_of(&self, specifier: &ModuleSpecifier) -> Option<&MemberResolver> {
+    self
+      .members
+      .iter()
+      .find(|member| specifier.as_str().starts_with(member.dir.as_str()))
+  }
+
+  /// Resolves an import of a member by its name, like `"@scope/a/util"` or
+  /// `"jsr:@scope/a@^1/util"`, to the module it exports.
+  fn resolve_member_export(
+    &self,
+    specifier: &str,
+  ) -> Result<Option<ModuleSpecifier>> {
+    let (bare, is_jsr) = match specifier.strip_prefix("jsr:") {
+      Some(bare) => (bare.trim_start_matches('/'), true),
+      None => (specifier, false),
+    };
+    for member in &self.members {
+      let Some(name) = &member.maybe_name else {
+        continue;
+      };
+      let Some(rest) = bare.strip_prefix(name.as_str()) else {
+        continue;
+      };
+      // a jsr specifier may pin a version, which the member always satisfies
+      let rest = match rest.strip_prefix('@') {
+        Some(version) if is_jsr => version
+          .find('/')
+          .map(|index| &version[index..])
+          .unwrap_or(""),
+        _ => rest,
+      };
+      let export = match rest {
+        "" => ".".to_string(),
+        rest if rest.starts_with('/') => format!(".{rest}"),
+        _ => continue,
+      };
+      let Some(path) = member.exports.get(&export) else {
+        bail!(
+          "The workspace member \"{name}\" does not export \"{export}\". Add it to the \"exports\" of \"{}deno.json\".",
+          member.dir
+        );
+      };
+      return Ok(Some(member.dir.join(path)?));
+    }
+    Ok(None)
+  }
+}
+
+impl Resolver for WorkspaceResolver {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer_range: &Range,
+    _mode: ResolutionMode,
+  ) -> Result<ModuleSpecifier, ResolveError> {
+    let referrer = &referrer_range.specifier;
+    let maybe_member_import_map = self
+      .member_of(referrer)
+      .and_then(|member| member.maybe_import_map.as_ref());
+    let mut maybe_unmapped_err = None;
+    for import_map in maybe_member_import_map
+      .into_iter()
+      .chain(self.maybe_root_import_map.as_ref())
+    {
+      match import_map.resolve(specifier, referrer) {
+        // a jsr specifier of a member, or one mapped to it, resolves locally
+        Ok(resolved) if resolved.scheme() == "jsr" => {
+          return match self.resolve_member_export(resolved.as_str()) {
+            Ok(Some(member_resolved)) => Ok(member_resolved),
+            Ok(None) => Ok(resolved),
+            Err(err) => Err(ResolveError::Other(err)),
+          };
+        }
+        Ok(resolved) => return Ok(resolved),
+        Err(err @ ImportMapError::UnmappedBareSpecifier(..)) => {
+          maybe_unmapped_err.get_or_insert(err);
+        }
+        Err(err) => return Err(ResolveError::Other(err.into())),
+      }
+    }
+    match self.resolve_member_export(specifier) {
+      Ok(Some(resolved)) => return Ok(resolved),
+      Ok(None) => {}
+      Err(err) => return Err(ResolveError::Other(err)),
+    }
+    match maybe_unmapped_err {
+      Some(err) => Err(ResolveError::Other(anyhow!(err))),
+      None => deno_graph::resolve_import(specifier, referrer)
+        .map_err(|err| err.into()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use deno_graph::source::MemoryLoader;
+  use deno_graph::source::Source;
+  use pretty_assertions::assert_eq;
+
+  use crate::TranspileGraphOptions;
+
+  fn setup() -> MemoryLoader {
+    let sources = vec![
+      (
+        "file:///w/deno.json",
+        r#"{
+  "workspace": ["./packages/a", "packages/b/"],
+  "imports": { "shared/": "./shared/" }
+}"#,
+      ),
+      (
+        "file:///w/packages/a/deno.json",
+        r#"{
+  "name": "@scope/a",
+  "exports": "./mod.ts",
+  "imports": { "dep": "./dep.ts" }
+}"#,
+      ),
+      (
+        "file:///w/packages/a/mod.ts",
+        "export { dep } from \"dep\";\n",
+      ),
+      (
+        "file:///w/packages/a/dep.ts",
+        "export const dep: number = 1;\n",
+      ),
+      (
+        "file:///w/packages/b/deno.jsonc",
+        r#"{
+  // the main module and a utility module
+  "name": "@scope/b",
+  "exports": { ".": "./mod.ts", "./util": "./util.ts" }
+}"#,
+      ),
+      (
+        "file:///w/packages/b/mod.ts",
+        r#"import { dep } from "@scope/a";
+import { util } from "jsr:@scope/b@^1.0.0/util";
+import { shared } from "shared/mod.ts";
+console.log(dep, util, shared);
+"#,
+      ),
+      ("file:///w/packages/b/util.ts", "export const util = 2;\n"),
+      (
+        "file:///w/packages/b/bad.ts",
+        "import { dep } from \"dep\";\nconsole.log(dep);\n",
+      ),
+      (
+        "file:///w/packages/b/missing.ts",
+        "import \"@scope/a/other\";\n",
+      ),
+      ("file:///w/shared/mod.ts", "export const shared = 3;\n"),
+    ];
+    MemoryLoader::new(
+      sources
+        .into_iter()
+        .map(|(specifier, content)| {
+          (
+            specifier,
+            Source::Module {
+              specifier,
+              maybe_headers: None,
+              content,
+            },
+          )
+        })
+        .collect(),
+      vec![],
+    )
+  }
+
+  #[tokio::test]
+  async fn transpile_workspace() {
+    let mut loader = setup();
+    let workspace = Workspace::load(
+      ModuleSpecifier::parse("file:///w/deno.json").unwrap(),
+      &loader,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+      workspace
+        .members
+        .iter()
+        .map(|member| member.specifier.as_str())
+        .collect::<Vec<_>>(),
+      vec![
+        "file:///w/packages/a/deno.json",
+        "file:///w/packages/b/deno.jsonc"
+      ]
+    );
+
+    let root = ModuleSpecifier::parse("file:///w/packages/b/mod.ts").unwrap();
+    let output = crate::transpile_workspace(
+      vec![root],
+      &mut loader,
+      &workspace,
+      TranspileGraphOptions {
+        emit_options: deno_ast::EmitOptions {
+          source_map: deno_ast::SourceMapOption::None,
+          ..Default::default()
+        },
+        rewrite_specifiers: true,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap()
+    .into_files()
+    .unwrap();
+    assert_eq!(
+      output.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+      vec![
+        "packages/a/dep.js",
+        "packages/a/mod.js",
+        "packages/b/mod.js",
+        "packages/b/util.js",
+        "shared/mod.js",
+      ]
+    );
+    assert_eq!(
+      String::from_utf8(output["packages/b/mod.js"].clone()).unwrap(),
+      r#"import { dep } from "../a/mod.js";
+import { util } from "./util.js";
+import { shared } from "../../shared/mod.js";
+console.log(dep, util, shared);
+"#
+    );
+
+    // the import map of a member doesn't apply to its siblings
+    let err = crate::transpile_workspace(
+      vec![ModuleSpecifier::parse("file:///w/packages/b/bad.ts").unwrap()],
+      &mut loader,
+      &workspace,
+      Default::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(
+      err
+        .to_string()
+        .contains("Relative import path \"dep\" not prefixed"),
+      "{err}"
+    );
+
+    let err = crate::transpile_workspace(
+      vec![ModuleSpecifier::parse("file:///w/packages/b/missing.ts").unwrap()],
+      &mut loader,
+      &workspace,
+      Default::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(
+      err.to_string().contains(
+        "The workspace member \"@scope/a\" does not export \"./other\"."
+      ),
+      "{err}"
+    );
+  }
+}
@@ -1,3 +1,7 @@
+import {
+  assertRejects,
+} from "https://deno.land/std@0.182.0/testing/asserts.ts";
+import { transpile } from "../js/mod.ts";
 import {
   resolveFixture,
   testTranspile,
@@ -84,3 +88,20 @@ Deno.test({
     },
   ),
 });
+
+Deno.test({
+  name: "jsx unsupported type",
+  async fn() {
+    await assertRejects(
+      () =>
+        transpile(resolveFixture("jsx/main.tsx"), {
+          compilerOptions: {
+            // deno-lint-ignore no-explicit-any
+            jsx: "react-jsxprod" as any,
+          },
+        }),
+      Error,
+      'Unsupported "jsx" compiler option "react-jsxprod".',
+    );
+  },
+});
@@ -4,113 +4,20 @@ use std::collections::HashMap;
 use std::string::FromUtf8Error;
 
 use anyhow::anyhow;
+use anyhow::bail;
 use deno_emit::BundleOptions;
 use deno_emit::BundleType;
-use deno_emit::EmitOptions;
+use deno_emit::CompilerOptions;
 use deno_emit::ImportMapInput;
-use deno_emit::ImportsNotUsedAsValues;
 use deno_emit::LoadFuture;
 use deno_emit::LoadOptions;
 use deno_emit::Loader;
 use deno_emit::ModuleSpecifier;
-use deno_emit::SourceMapOption;
-use deno_emit::TranspileOptions;
+use deno_emit::TranspileGraphOptions;
 use serde::Serialize;
 use url::Url;
 use wasm_bindgen::prelude::*;
 
-/// This is a deserializable structure of the `"compilerOptions"` section of a
-/// TypeScript or Deno configuration file which can effect how the emitting is
-/// handled, all other options don't impact the output.
-#[derive(serde::Deserialize)]
-#[serde(default, rename_all = "camelCase")]
-#[derive(Debug)]
-pub struct CompilerOptions {
-  pub check_js: bool,
-  pub experimental_decorators: bool,
-  pub emit_decorator_metadata: bool,
-  pub imports_not_used_as_values: String,
-  pub inline_source_map: bool,
-  pub inline_sources: bool,
-  pub jsx: String,
-  pub jsx_factory: String,
-  pub jsx_fragment_factory: String,
-  pub jsx_import_source: Option<String>,
-  pub source_map: bool,
-}
-
-impl CompilerOptions {
-  pub fn into_options(self) -> (TranspileOptions, EmitOptions) {
-    let imports_not_used_as_values =
-      match self.imports_not_used_as_values.as_str() {
-        "preserve" => ImportsNotUsedAsValues::Preserve,
-        "error" => ImportsNotUsedAsValues::Error,
-        _ => ImportsNotUsedAsValues::Remove,
-      };
-
-    // copied from the CLI
-    let (transform_jsx, jsx_automatic, jsx_development, precompile_jsx) =
-      match self.jsx.as_str() {
-        "react" => (true, false, false, false),
-        "react-jsx" => (true, true, false, false),
-        "react-jsxdev" => (true, true, true, false),
-        "precompile" => (false, false, false, true),
-        _ => (false, false, false, false),
-      };
-    let source_map = if self.inline_source_map {
-      SourceMapOption::Inline
-    } else if self.source_map {
-      SourceMapOption::Separate
-    } else {
-      SourceMapOption::None
-    };
-
-    (
-      TranspileOptions {
-        use_decorators_proposal: !self.experimental_decorators,
-        use_ts_decorators: self.experimental_decorators,
-        emit_metadata: self.emit_decorator_metadata,
-        imports_not_used_as_values,
-        jsx_factory: self.jsx_factory,
-        jsx_fragment_factory: self.jsx_fragment_factory,
-        transform_jsx,
-        var_decl_imports: false,
-        jsx_automatic,
-        jsx_development,
-        jsx_import_source: self.jsx_import_source,
-        precompile_jsx,
-        precompile_jsx_skip_elements: None,
-        precompile_jsx_dynamic_props: None,
-      },
-      EmitOptions {
-        inline_sources: self.inline_sources,
-        source_map_file: None,
-        source_map_base: None,
-        remove_comments: false,
-        source_map,
-      },
-    )
-  }
-}
-
-impl Default for CompilerOptions {
-  fn default() -> Self {
-    Self {
-      experimental_decorators: false,
-      check_js: false,
-      emit_decorator_metadata: false,
-      imports_not_used_as_values: "remove".to_string(),
-      inline_source_map: false,
-      inline_sources: false,
-      jsx: "react".to_string(),
-      jsx_factory: "React.createElement".to_string(),
-      jsx_fragment_factory: "React.Fragment".to_string(),
-      jsx_import_source: None,
-      source_map: false,
-    }
-  }
-}
-
 #[derive(serde::Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct ImportMapJsInput {
@@ -189,6 +96,20 @@ impl Loader for JsLoader {
   }
 }
 
+/// Imports are only resolved with the `paths` and `baseUrl` compiler options
+/// of a workspace's configuration file, which the JS API doesn't load.
+fn ensure_supported_compiler_options(
+  compiler_options: &CompilerOptions,
+) -> anyhow::Result<()> {
+  if !compiler_options.paths.is_empty() {
+    bail!("The \"paths\" compiler option is not supported. Use an import map instead.");
+  }
+  if compiler_options.base_url.is_some() {
+    bail!("The \"baseUrl\" compiler option is not supported. Use an import map instead.");
+  }
+  Ok(())
+}
+
 #[wasm_bindgen]
 pub async fn bundle(
   root: String,
@@ -208,7 +129,11 @@ pub async fn bundle(
   let root = ModuleSpecifier::parse(&root)
     .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
   let mut loader = JsLoader::new(load);
-  let (transpile_options, emit_options) = compiler_options.into_options();
+  ensure_supported_compiler_options(&compiler_options)
+    .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
+  let (transpile_options, emit_options) = compiler_options
+    .into_options()
+    .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
   let bundle_type = match maybe_bundle_type.as_deref() {
     Some("module") | None => BundleType::Module,
     Some("classic") => BundleType::Classic,
@@ -236,9 +161,9 @@ pub async fn bundle(
     BundleOptions {
       bundle_type,
       emit_options,
-      emit_ignore_directives: false,
       transpile_options,
       minify,
+      ..Default::default()
     },
   )
   .await
@@ -267,7 +192,11 @@ pub async fn transpile(
   let root = ModuleSpecifier::parse(&root)
     .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
   let mut loader = JsLoader::new(load);
-  let (transpile_options, emit_options) = compiler_options.into_options();
+  ensure_supported_compiler_options(&compiler_options)
+    .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
+  let (transpile_options, emit_options) = compiler_options
+    .into_options()
+    .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
 
   let maybe_import_map = serde_wasm_bindgen::from_value::<
     Option<ImportMapJsInput>,
@@ -281,19 +210,24 @@ pub async fn transpile(
   .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
 
   let map = deno_emit::transpile(
-    root,
+    vec![root],
     &mut loader,
     maybe_import_map,
-    &transpile_options,
-    &emit_options,
+    TranspileGraphOptions {
+      transpile_options,
+      emit_options,
+      // wasm builds always transpile sequentially
+      parallel: false,
+      ..Default::default()
+    },
   )
   .await
   .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
   let map = map
+    .into_files()
+    .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?
     .into_iter()
-    .map(|(specifier, source)| {
-      Ok((specifier.to_string(), String::from_utf8(source)?))
-    })
+    .map(|(specifier, source)| Ok((specifier, String::from_utf8(source)?)))
     .collect::<Result<HashMap<String, String>, FromUtf8Error>>()
     .map_err(|err| JsValue::from(js_sys::Error::new(&format!("{:#}", err))))?;
 